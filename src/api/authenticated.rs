@@ -0,0 +1,107 @@
+use actix_web::{dev::Payload, error::InternalError, web, FromRequest, HttpRequest, HttpResponse};
+use futures_util::future::LocalBoxFuture;
+use mongodb::Database;
+use mongodb::bson::DateTime;
+use crate::auth::{user_from_claims, verify_jwt_claims, Claims};
+use crate::models::User;
+use crate::services::{RevokedTokenService, SessionService, UserService};
+
+// Activity is written at most this often per session
+const TOUCH_INTERVAL_SECONDS: i64 = 60;
+
+// The caller of a protected endpoint. Every protected handler takes it as an argument, so the
+// access token and the session behind it are checked the same way everywhere
+pub struct Authenticated {
+    pub claims: Claims,
+    // The caller as described by the token, with the roles it carries
+    pub user: User,
+    // The caller's stored record, loaded to check the token was not revoked
+    pub stored: User,
+}
+
+impl Authenticated {
+    // The caller, unless acting through impersonation; use it for sensitive actions
+    pub fn direct_user(self) -> Result<User, HttpResponse> {
+        if self.claims.is_impersonated() {
+            return Err(HttpResponse::Forbidden().json("Not allowed while impersonating"));
+        }
+        Ok(self.user)
+    }
+
+    // The stored record with a user service scoped to the caller's tenant
+    pub fn stored_user(self, db: &Database) -> (User, UserService, Claims) {
+        let user_service = UserService::new(db.clone(), &self.claims.tenant_id);
+        (self.stored, user_service, self.claims)
+    }
+}
+
+impl FromRequest for Authenticated {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            authenticate(&req).await
+                .map_err(|response| InternalError::from_response("Authentication failed", response).into())
+        })
+    }
+}
+
+async fn authenticate(req: &HttpRequest) -> Result<Authenticated, HttpResponse> {
+    let claims = verify_jwt_claims(req)
+        .map_err(|_| HttpResponse::Unauthorized().json("Invalid token"))?;
+    let user = user_from_claims(claims.clone())
+        .map_err(|_| HttpResponse::Unauthorized().json("Invalid token"))?;
+
+    let db = req.app_data::<web::Data<Database>>()
+        .ok_or_else(|| HttpResponse::InternalServerError().json("Internal server error"))?;
+    let stored = match UserService::new(db.get_ref().clone(), &claims.tenant_id).find_by_id(&user._id.unwrap()).await {
+        Ok(Some(stored)) if stored.token_revoked(claims.iat) => return Err(HttpResponse::Unauthorized().json("Token revoked")),
+        Ok(Some(stored)) if !stored.is_active => return Err(HttpResponse::Unauthorized().json("Account inactive")),
+        Ok(Some(stored)) => stored,
+        Ok(None) => return Err(HttpResponse::Unauthorized().json("Invalid token")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Err(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+    check_session(db, &claims, &user).await?;
+
+    Ok(Authenticated { claims, user, stored })
+}
+
+// Rejects access tokens whose session was revoked or has ended and records activity
+// on live ones; tokens without a session (impersonation) last until ended or expired
+async fn check_session(db: &Database, claims: &Claims, user: &User) -> Result<(), HttpResponse> {
+    let Some(sid) = &claims.sid else {
+        return match RevokedTokenService::new(db.clone()).is_revoked(&claims.jti).await {
+            Ok(false) => Ok(()),
+            Ok(true) => Err(HttpResponse::Unauthorized().json("Token revoked")),
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                Err(HttpResponse::InternalServerError().json("Internal server error"))
+            }
+        };
+    };
+
+    let session_service = SessionService::new(db.clone(), &claims.tenant_id);
+    match session_service.find_active(sid).await {
+        Ok(Some(session)) => {
+            if DateTime::now().timestamp_millis() - session.last_seen_at.timestamp_millis() >= TOUCH_INTERVAL_SECONDS * 1000 {
+                if let Err(e) = session_service.touch(&session).await {
+                    eprintln!("Failed to record session activity: {}", e);
+                }
+            }
+            Ok(())
+        }
+        Ok(None) => {
+            session_service.expire_idle_sessions(user).await;
+            Err(HttpResponse::Unauthorized().json("Session revoked"))
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
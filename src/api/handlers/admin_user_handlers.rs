@@ -0,0 +1,408 @@
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use mongodb::Database;
+use mongodb::bson::{doc, oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use crate::api::authenticated::Authenticated;
+use crate::auth::admin_scope;
+use crate::config::TenantConfig;
+use crate::models::{AuditLog, TenantScope, User, UserSummary};
+use crate::services::{AuditService, SessionService, UserService};
+use crate::services::user_service::{UserListQuery, UserSortField, MAX_USERS_PER_PAGE};
+use crate::api::handlers::session_handlers::{list_sessions_for, revoke_sessions_for};
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ListUsersQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    per_page: Option<i64>,
+    // Older alias of per_page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sort: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    order: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    is_active: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email_verified: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email_prefix: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct UserPage {
+    data: Vec<UserSummary>,
+    total: u64,
+    page: u64,
+    per_page: i64,
+    total_pages: u64,
+    next: Option<String>,
+    prev: Option<String>,
+}
+
+impl ListUsersQuery {
+    fn to_user_list_query(&self) -> std::result::Result<UserListQuery, String> {
+        let defaults = UserListQuery::default();
+
+        let sort_by = match &self.sort {
+            Some(sort) => UserSortField::parse(sort)
+                .ok_or("sort must be one of created_at, last_login, email")?,
+            None => defaults.sort_by,
+        };
+        let descending = match self.order.as_deref() {
+            Some("asc") => false,
+            Some("desc") => true,
+            Some(_) => return Err("order must be asc or desc".to_string()),
+            None => defaults.descending,
+        };
+
+        Ok(UserListQuery {
+            page: self.page.unwrap_or(1).max(1),
+            per_page: self.per_page.or(self.limit).unwrap_or(defaults.per_page).clamp(1, MAX_USERS_PER_PAGE),
+            sort_by,
+            descending,
+            role: self.role.clone(),
+            is_active: self.is_active,
+            email_verified: self.email_verified,
+            email_prefix: self.email_prefix.clone(),
+        })
+    }
+
+    // Same query pointing at another page
+    fn page_link(&self, path: &str, page: u64, per_page: i64) -> Option<String> {
+        let mut query = self.clone();
+        query.page = Some(page);
+        query.per_page = Some(per_page);
+        query.limit = None;
+
+        serde_urlencoded::to_string(&query)
+            .ok()
+            .map(|qs| format!("{}?{}", path, qs))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UpdateUserRequest {
+    roles: Option<Vec<String>>,
+    is_active: Option<bool>,
+    email_verified: Option<bool>,
+}
+
+// Authenticates an admin and resolves the tenant scope they operate on
+fn user_admin(req: &HttpRequest, auth: Authenticated, tenant_config: &TenantConfig) -> std::result::Result<(User, TenantScope), HttpResponse> {
+    let admin = auth.direct_user()?;
+
+    if !admin.is_admin() {
+        return Err(HttpResponse::Forbidden().json("Admin access required"));
+    }
+
+    let scope = admin_scope(req, tenant_config, &admin)
+        .map_err(|e| HttpResponse::BadRequest().json(e))?;
+
+    Ok((admin, scope))
+}
+
+async fn load_user(user_service: &UserService, id: &str) -> std::result::Result<User, HttpResponse> {
+    let user_id = ObjectId::from_str(id)
+        .map_err(|_| HttpResponse::BadRequest().json("Invalid user ID"))?;
+
+    match user_service.find_by_id(&user_id).await {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err(HttpResponse::NotFound().json("User not found")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
+
+pub async fn list_users(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
+    query: web::Query<ListUsersQuery>,
+) -> Result<HttpResponse> {
+    let (_, scope) = match user_admin(&req, auth, &tenant_config) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    let list_query = match query.to_user_list_query() {
+        Ok(list_query) => list_query,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(e)),
+    };
+
+    let user_service = UserService::with_scope(db.get_ref().clone(), scope);
+
+    match user_service.list_users(&list_query).await {
+        Ok((users, total)) => {
+            let page = list_query.page;
+            let per_page = list_query.per_page;
+            let total_pages = total.div_ceil(per_page as u64);
+
+            let response = UserPage {
+                data: users.iter().map(UserSummary::from).collect(),
+                total,
+                page,
+                per_page,
+                total_pages,
+                next: (page < total_pages).then(|| query.page_link(req.path(), page + 1, per_page)).flatten(),
+                prev: (page > 1).then(|| query.page_link(req.path(), page - 1, per_page)).flatten(),
+            };
+            Ok(HttpResponse::Ok().json(response))
+        }
+        Err(e) => {
+            eprintln!("Error fetching users: {}", e);
+            Ok(HttpResponse::InternalServerError().json("Failed to fetch users"))
+        }
+    }
+}
+
+pub async fn get_user(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let (_, scope) = match user_admin(&req, auth, &tenant_config) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    let user_service = UserService::with_scope(db.get_ref().clone(), scope);
+
+    match load_user(&user_service, &path).await {
+        Ok(user) => Ok(HttpResponse::Ok().json(UserSummary::from(&user))),
+        Err(response) => Ok(response),
+    }
+}
+
+pub async fn update_user(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
+    path: web::Path<String>,
+    body: web::Json<UpdateUserRequest>,
+) -> Result<HttpResponse> {
+    let (admin, scope) = match user_admin(&req, auth, &tenant_config) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    let user_service = UserService::with_scope(db.get_ref().clone(), scope);
+    let user = match load_user(&user_service, &path).await {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+
+    let user_id = user._id.unwrap();
+    let is_self = admin._id == Some(user_id);
+    let before = UserSummary::from(&user);
+
+    if let Some(roles) = &body.roles {
+        if roles.iter().any(|role| role.trim().is_empty()) {
+            return Ok(HttpResponse::BadRequest().json("Roles cannot be empty"));
+        }
+        let touches_super_admin = roles.iter().any(|r| r == "super_admin") != user.is_super_admin();
+        if touches_super_admin && !admin.is_super_admin() {
+            return Ok(HttpResponse::Forbidden().json("Only super admins can grant or revoke the super_admin role"));
+        }
+        if is_self && !roles.iter().any(|r| r == "admin" || r == "super_admin") {
+            return Ok(HttpResponse::BadRequest().json("Admins cannot remove their own admin role"));
+        }
+    }
+    if is_self && body.is_active == Some(false) {
+        return Ok(HttpResponse::BadRequest().json("Admins cannot deactivate themselves"));
+    }
+
+    let user = match user_service.update_account(&user_id, body.roles.as_deref(), body.is_active, body.email_verified).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return Ok(HttpResponse::NotFound().json("User not found")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+    let after = UserSummary::from(&user);
+
+    // Existing tokens and sessions carry the old roles, so they stop working
+    if after.roles != before.roles || after.is_active != before.is_active {
+        if let Err(e) = user_service.revoke_tokens(&user_id).await {
+            eprintln!("Failed to revoke tokens: {}", e);
+        }
+        let session_service = SessionService::new(db.get_ref().clone(), &user.tenant_id);
+        if let Err(e) = session_service.revoke_all(&user_id.to_hex(), None, "account_updated").await {
+            eprintln!("Failed to revoke sessions: {}", e);
+        }
+    }
+
+    let audit_service = AuditService::new(db.get_ref().clone(), &user.tenant_id);
+    audit_service.record(&AuditLog::new(
+        &user.tenant_id,
+        &admin,
+        "user.updated",
+        "user",
+        &user_id.to_hex(),
+        Some(doc! {
+            "previous": { "roles": &before.roles, "is_active": before.is_active, "email_verified": before.email_verified },
+            "current": { "roles": &after.roles, "is_active": after.is_active, "email_verified": after.email_verified },
+        }),
+    )).await;
+
+    Ok(HttpResponse::Ok().json(after))
+}
+
+pub async fn delete_user(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let (admin, scope) = match user_admin(&req, auth, &tenant_config) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    let user_service = UserService::with_scope(db.get_ref().clone(), scope);
+    let user = match load_user(&user_service, &path).await {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+
+    let user_id = user._id.unwrap();
+    if admin._id == Some(user_id) {
+        return Ok(HttpResponse::BadRequest().json("Admins cannot delete themselves"));
+    }
+    if user.is_super_admin() && !admin.is_super_admin() {
+        return Ok(HttpResponse::Forbidden().json("Only super admins can delete a super admin"));
+    }
+
+    match user_service.delete_user(&user_id).await {
+        Ok(true) => {
+            let audit_service = AuditService::new(db.get_ref().clone(), &user.tenant_id);
+            audit_service.record(&AuditLog::new(
+                &user.tenant_id,
+                &admin,
+                "user.deleted",
+                "user",
+                &user_id.to_hex(),
+                Some(doc! { "email": &user.email, "roles": user.roles.clone().unwrap_or_default() }),
+            )).await;
+
+            Ok(HttpResponse::NoContent().finish())
+        }
+        Ok(false) => Ok(HttpResponse::NotFound().json("User not found")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Ok(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
+
+// Lifts a lockout and clears the failed login counters
+pub async fn unlock_user(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let (admin, scope) = match user_admin(&req, auth, &tenant_config) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    let user_service = UserService::with_scope(db.get_ref().clone(), scope);
+    let mut user = match load_user(&user_service, &path).await {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+
+    let user_id = user._id.unwrap();
+    let was_locked = user.is_locked(DateTime::now());
+    user.clear_login_failures();
+
+    if let Err(e) = user_service.clear_login_failures(&user_id).await {
+        eprintln!("Database error: {}", e);
+        return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+    }
+
+    let audit_service = AuditService::new(db.get_ref().clone(), &user.tenant_id);
+    audit_service.record(&AuditLog::new(
+        &user.tenant_id,
+        &admin,
+        "user.unlocked",
+        "user",
+        &user_id.to_hex(),
+        Some(doc! { "email": &user.email, "was_locked": was_locked }),
+    )).await;
+
+    Ok(HttpResponse::Ok().json(UserSummary::from(&user)))
+}
+
+pub async fn list_user_sessions(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let (_, scope) = match user_admin(&req, auth, &tenant_config) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    let user_service = UserService::with_scope(db.get_ref().clone(), scope);
+    match load_user(&user_service, &path).await {
+        Ok(user) => Ok(list_sessions_for(&db, &user).await),
+        Err(response) => Ok(response),
+    }
+}
+
+pub async fn revoke_user_session(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse> {
+    let (admin, scope) = match user_admin(&req, auth, &tenant_config) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    let (user_id, session_id) = path.into_inner();
+    let user_service = UserService::with_scope(db.get_ref().clone(), scope);
+    match load_user(&user_service, &user_id).await {
+        Ok(user) => Ok(revoke_sessions_for(&req, &db, &admin, &user, Some(&session_id)).await),
+        Err(response) => Ok(response),
+    }
+}
+
+pub async fn revoke_user_sessions(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let (admin, scope) = match user_admin(&req, auth, &tenant_config) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    let user_service = UserService::with_scope(db.get_ref().clone(), scope);
+    match load_user(&user_service, &path).await {
+        Ok(user) => Ok(revoke_sessions_for(&req, &db, &admin, &user, None).await),
+        Err(response) => Ok(response),
+    }
+}
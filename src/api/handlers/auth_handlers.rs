@@ -1,17 +1,30 @@
 use actix_web::{web, HttpRequest, HttpResponse, Result};
 use serde::{Deserialize, Serialize};
 use mongodb::Database;
-use bcrypt::{hash, verify, DEFAULT_COST};
 use std::time::SystemTime;
-use mongodb::bson::DateTime;
-use crate::auth::{create_jwt_token, verify_jwt_token};
-use crate::models::{User, LoginLog};
-use crate::services::{UserService, LogService};
+use mongodb::bson::{doc, DateTime};
+use crate::auth::{create_jwt_token, create_mfa_challenge, create_refresh_token, effective_roles, resolve_tenant, session_cookies, HashingPool, PasswordContext, PasswordPolicy, PasswordPolicyError, TokenOptions};
+use crate::config::{EmailVerificationConfig, SessionCookieConfig, TenantConfig, UnverifiedLoginPolicy};
+use crate::api::handlers::email_handlers::send_verification_email;
+use crate::api::authenticated::Authenticated;
+use crate::utils::request_info::{get_client_ip, get_user_agent};
+use crate::models::{AuditLog, User, LoginLog, SecuritySettings, Session, SessionLimitAction};
+use crate::models::user::validate_name;
+use crate::services::{AuditService, UserService, LogService, SessionService, KongService, MailService, MailTemplate, OrganizationService, GroupService, SettingsService};
+use mongodb::bson::oid::ObjectId;
+use std::str::FromStr;
+use crate::utils::secure_token::hash_token;
+
+// Lifetime of a session and of the refresh tokens issued for it
+pub const SESSION_LIFETIME_DAYS: i64 = 30;
 
 #[derive(Deserialize)]
 pub struct LoginRequest {
     email: String,
     password: String,
+    tenant: Option<String>,
+    // Organization to activate in the issued token
+    org_id: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -19,12 +32,35 @@ pub struct RegisterRequest {
     email: String,
     password: String,
     name: String,
+    tenant: Option<String>,
 }
 
 #[derive(Serialize)]
 pub struct AuthResponse {
-    token: String,
+    // Left out when the token is set as a cookie instead
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    refresh_token: Option<String>,
     user: UserResponse,
+    // Asks the client to prompt for a password change
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    password_breached: bool,
+}
+
+// Error with a machine-readable code for clients that need to react to it
+#[derive(Serialize)]
+pub struct ErrorResponse {
+    error: &'static str,
+    message: &'static str,
+}
+
+// Returned by login instead of the tokens when a second factor is required
+#[derive(Serialize)]
+pub struct MfaChallengeResponse {
+    mfa_required: bool,
+    challenge_token: String,
+    methods: Vec<&'static str>,
 }
 
 #[derive(Serialize)]
@@ -35,32 +71,299 @@ pub struct UserResponse {
     roles: Vec<String>,
 }
 
-fn get_client_ip(req: &HttpRequest) -> Option<String> {
-    req.connection_info()
-        .peer_addr()
-        .map(|addr| addr.to_string())
+impl From<&User> for UserResponse {
+    fn from(user: &User) -> Self {
+        Self {
+            id: user._id.map(|id| id.to_hex()).unwrap_or_default(),
+            email: user.email.clone(),
+            name: user.display_name(),
+            roles: user.roles.clone().unwrap_or_default(),
+        }
+    }
+}
+
+pub fn account_locked() -> HttpResponse {
+    HttpResponse::Locked().json(ErrorResponse {
+        error: "account_locked",
+        message: "Account temporarily locked after too many failed attempts",
+    })
+}
+
+// Counts a failed factor towards the account lockout and tells the owner when this
+// failure locks the account; returns whether it did
+pub async fn register_login_failure(db: &Database, mail: &MailService, user_service: &UserService, user: &User) -> bool {
+    let settings = match SettingsService::new(db.clone(), &user.tenant_id).get().await {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Failed to load security settings: {}", e);
+            SecuritySettings::new(user.tenant_id.clone())
+        }
+    };
+
+    let locked = match user_service.register_login_failure(&user._id.unwrap(), &settings.lockout, DateTime::now()).await {
+        Ok(locked) => locked,
+        Err(e) => {
+            eprintln!("Failed to save lockout state: {}", e);
+            false
+        }
+    };
+    if locked {
+        mail.send_template(&user.email, user.locale.as_deref(), MailTemplate::SecurityAlert { event: "account_locked" });
+    }
+    locked
+}
+
+// Ok(true) when the user may only log in with the limited "unverified" role
+pub fn apply_unverified_login_policy(user: &mut User, config: &EmailVerificationConfig) -> std::result::Result<bool, HttpResponse> {
+    if user.email_verified {
+        return Ok(false);
+    }
+
+    match config.unverified_login {
+        UnverifiedLoginPolicy::Allow => Ok(false),
+        UnverifiedLoginPolicy::Limited => {
+            // Only the "unverified" role until the email is confirmed
+            user.roles = Some(vec!["unverified".to_string()]);
+            Ok(true)
+        }
+        UnverifiedLoginPolicy::Reject => Err(HttpResponse::Forbidden().json(ErrorResponse {
+            error: "email_not_verified",
+            message: "Verify your email address before logging in",
+        })),
+    }
+}
+
+// Ends the first factor of a login for users with a second factor; the client trades
+// the challenge and a code for the tokens. amr lists the first factor's methods
+pub async fn start_mfa_challenge(
+    db: &Database,
+    user: &User,
+    org_id: Option<String>,
+    amr: &[&str],
+    mut login_log: LoginLog,
+) -> HttpResponse {
+    login_log.set_mfa_challenge(user._id.unwrap().to_hex());
+    if let Err(e) = LogService::new(db.clone(), &user.tenant_id).save_login_log(&login_log).await {
+        eprintln!("Failed to save login log: {}", e);
+    }
+
+    HttpResponse::Ok().json(MfaChallengeResponse {
+        mfa_required: true,
+        challenge_token: create_mfa_challenge(user, org_id, amr),
+        methods: user.second_factor_methods(),
+    })
+}
+
+// Roles and active organization for an access token. Err carries the failure
+// reason for the login log along with the response
+pub async fn login_token_options(
+    db: &Database,
+    user: &User,
+    org_id: Option<&str>,
+    limited: bool,
+    amr: Vec<String>,
+) -> std::result::Result<TokenOptions, (String, HttpResponse)> {
+    let tenant_id = &user.tenant_id;
+    let user_id = user._id.unwrap().to_hex();
+    let internal_error = || ("Database error".to_string(), HttpResponse::InternalServerError().json("Internal server error"));
+    let mut token_options = TokenOptions { amr, ..TokenOptions::default() };
+
+    if !limited {
+        let group_service = GroupService::new(db.clone(), tenant_id);
+        token_options.group_roles = group_service.roles_for_user(&user_id).await.map_err(|e| {
+            eprintln!("Database error: {}", e);
+            internal_error()
+        })?;
+    }
+
+    if limited && org_id.is_some() {
+        return Err(("Email not verified".to_string(), HttpResponse::Forbidden().json(ErrorResponse {
+            error: "email_not_verified",
+            message: "Verify your email address to access organizations",
+        })));
+    }
+
+    // Activate the requested organization if the user is a member of it
+    if let Some(org_id) = org_id {
+        let org_service = OrganizationService::new(db.clone(), tenant_id);
+        let membership = match ObjectId::from_str(org_id) {
+            Ok(oid) => org_service.find_active_membership(&oid, &user_id).await,
+            Err(_) => Ok(None),
+        };
+
+        match membership {
+            Ok(Some(membership)) => {
+                token_options.org_id = Some(org_id.to_string());
+                token_options.org_roles = membership.roles;
+            }
+            Ok(None) => {
+                return Err(("Organization access denied".to_string(), HttpResponse::Forbidden().json("Not a member of this organization")));
+            }
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                return Err(internal_error());
+            }
+        }
+    }
+
+    Ok(token_options)
 }
 
-fn get_user_agent(req: &HttpRequest) -> Option<String> {
-    req.headers()
-        .get("user-agent")
-        .and_then(|value| value.to_str().ok())
-        .map(|s| s.to_string())
+// Final step of every login once all factors are checked: opens a session, issues
+// the access and refresh tokens and records the login
+pub async fn issue_login_token(
+    db: &Database,
+    cookie_config: &SessionCookieConfig,
+    user: &User,
+    org_id: Option<&str>,
+    limited: bool,
+    amr: Vec<String>,
+    mut login_log: LoginLog,
+) -> HttpResponse {
+    let tenant_id = &user.tenant_id;
+    let user_id = user._id.unwrap().to_hex();
+    let log_service = LogService::new(db.clone(), tenant_id);
+
+    let mut token_options = match login_token_options(db, user, org_id, limited, amr.clone()).await {
+        Ok(options) => options,
+        Err((reason, response)) => {
+            login_log.set_failure(reason);
+            if let Err(e) = log_service.save_login_log(&login_log).await {
+                eprintln!("Failed to save login log: {}", e);
+            }
+            return response;
+        }
+    };
+
+    // Idle sessions are ended first so they don't count against the session limit
+    let session_service = SessionService::new(db.clone(), tenant_id);
+    session_service.expire_idle_sessions(user).await;
+
+    let settings = match SettingsService::new(db.clone(), tenant_id).get().await {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Failed to load security settings: {}", e);
+            SecuritySettings::new(tenant_id.clone())
+        }
+    };
+    let limits = settings.sessions.limits_for(&effective_roles(user, &token_options.group_roles));
+
+    if let Some(max_sessions) = limits.max_sessions {
+        let active = match session_service.list_active(&user_id).await {
+            Ok(sessions) => sessions.len(),
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                return HttpResponse::InternalServerError().json("Internal server error");
+            }
+        };
+
+        let excess = (active + 1).saturating_sub(max_sessions as usize);
+        if excess > 0 {
+            match settings.sessions.on_limit {
+                SessionLimitAction::Reject => {
+                    login_log.set_failure("Session limit reached".to_string());
+                    if let Err(e) = log_service.save_login_log(&login_log).await {
+                        eprintln!("Failed to save login log: {}", e);
+                    }
+                    return HttpResponse::Conflict().json(ErrorResponse {
+                        error: "session_limit_reached",
+                        message: "Too many active sessions; sign out of another device first",
+                    });
+                }
+                SessionLimitAction::EvictOldest => match session_service.evict_oldest(&user_id, excess).await {
+                    Ok(evicted) => session_service.record_evictions(user, &evicted, "session_limit").await,
+                    Err(e) => {
+                        eprintln!("Database error: {}", e);
+                        return HttpResponse::InternalServerError().json("Internal server error");
+                    }
+                },
+            }
+        }
+    }
+
+    // The session shares its id with the login log entry
+    let expires_at = DateTime::from_millis(DateTime::now().timestamp_millis() + SESSION_LIFETIME_DAYS * 24 * 60 * 60 * 1000);
+    let session_id = login_log.session_id.get_or_insert_with(|| uuid::Uuid::new_v4().to_string()).clone();
+    let (refresh_token, refresh_jti) = create_refresh_token(user, &session_id, expires_at);
+    let session = Session::from_login(&login_log, &user_id, hash_token(&refresh_jti), amr, token_options.org_id.clone(), expires_at)
+        .with_idle_timeout(limits.idle_timeout_minutes);
+
+    if let Err(e) = session_service.create(&session).await {
+        eprintln!("Database error: {}", e);
+        login_log.set_failure("Session creation failed".to_string());
+        if let Err(e) = log_service.save_login_log(&login_log).await {
+            eprintln!("Failed to save login log: {}", e);
+        }
+        return HttpResponse::InternalServerError().json("Internal server error");
+    }
+    token_options.sid = Some(session_id);
+
+    if let Err(e) = UserService::new(db.clone(), tenant_id).record_login(&user._id.unwrap()).await {
+        eprintln!("Failed to record login: {}", e);
+    }
+
+    // Generate JWT token
+    match create_jwt_token(user, &token_options) {
+        Ok(token) => {
+            // Update login log as successful
+            login_log.set_success(user_id, true, true);
+
+            // Save login log
+            if let Err(e) = log_service.save_login_log(&login_log).await {
+                eprintln!("Failed to save login log: {}", e);
+            }
+
+            let mut response = HttpResponse::Ok();
+            if cookie_config.enabled {
+                for cookie in session_cookies(cookie_config, &token, &refresh_token, expires_at) {
+                    response.cookie(cookie);
+                }
+            }
+
+            response.json(AuthResponse {
+                token: (!cookie_config.access_token_cookie()).then_some(token),
+                refresh_token: (!cookie_config.enabled).then_some(refresh_token),
+                user: UserResponse::from(user),
+                password_breached: user.password_breached,
+            })
+        }
+        Err(e) => {
+            login_log.set_failure("Token generation failed".to_string());
+            if let Err(e) = log_service.save_login_log(&login_log).await {
+                eprintln!("Failed to save login log: {}", e);
+            }
+            eprintln!("Token generation error: {}", e);
+            HttpResponse::InternalServerError().json("Internal server error")
+        }
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn login(
     req: HttpRequest,
     db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
+    verification_config: web::Data<EmailVerificationConfig>,
+    mail: web::Data<MailService>,
+    password_policy: web::Data<PasswordPolicy>,
+    password_hasher: web::Data<HashingPool>,
+    cookie_config: web::Data<SessionCookieConfig>,
     login_req: web::Json<LoginRequest>,
 ) -> Result<HttpResponse> {
-    let user_service = UserService::new(db.get_ref().clone());
-    let log_service = LogService::new(db.get_ref().clone());
+    let tenant_id = match resolve_tenant(&req, &tenant_config, login_req.tenant.as_deref()) {
+        Ok(tenant_id) => tenant_id,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(e)),
+    };
+
+    let user_service = UserService::new(db.get_ref().clone(), &tenant_id);
+    let log_service = LogService::new(db.get_ref().clone(), &tenant_id);
     
     let ip_address = get_client_ip(&req);
     let user_agent = get_user_agent(&req);
     
     // Create initial login log
     let mut login_log = LoginLog::new(
+        tenant_id.clone(),
         login_req.email.clone(),
         false, // Initially failed, will be updated if successful
         ip_address,
@@ -69,42 +372,99 @@ pub async fn login(
 
     // Find user by email
     match user_service.find_by_email(&login_req.email).await {
-        Ok(Some(user)) => {
+        Ok(Some(mut user)) => {
+            let settings = match SettingsService::new(db.get_ref().clone(), &tenant_id).get().await {
+                Ok(settings) => settings,
+                Err(e) => {
+                    eprintln!("Failed to load security settings: {}", e);
+                    SecuritySettings::new(tenant_id.clone())
+                }
+            };
+            let now = DateTime::now();
+
+            // Locked accounts get the same answer whatever the password
+            if user.is_locked(now) {
+                login_log.set_failure("Account locked".to_string());
+                if let Err(e) = log_service.save_login_log(&login_log).await {
+                    eprintln!("Failed to save login log: {}", e);
+                }
+                return Ok(account_locked());
+            }
+            if !user.is_active {
+                login_log.set_failure("Account inactive".to_string());
+                if let Err(e) = log_service.save_login_log(&login_log).await {
+                    eprintln!("Failed to save login log: {}", e);
+                }
+                return Ok(HttpResponse::Unauthorized().json("Invalid credentials"));
+            }
+
             // Verify password
-            if verify(&login_req.password, &user.password).unwrap_or(false) {
-                // Generate JWT token
-                match create_jwt_token(&user) {
-                    Ok(token) => {
-                        // Update login log as successful
-                        login_log.set_success(user._id.unwrap().to_hex(), true, false);
-                        
-                        // Save login log
-                        if let Err(e) = log_service.save_login_log(&login_log).await {
-                            eprintln!("Failed to save login log: {}", e);
-                        }
+            let password_valid = match password_hasher.verify(&login_req.password, &user.password).await {
+                Ok(valid) => valid,
+                Err(e) => {
+                    eprintln!("Password verification error: {}", e);
+                    return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+                }
+            };
 
-                        let user_email = user.email.clone();
-                        let response = AuthResponse {
-                            token,
-                            user: UserResponse {
-                                id: user._id.unwrap().to_hex(),
-                                email: user.email,
-                                name: user_email, // Use email as name for now
-                                roles: user.roles.unwrap_or_default(),
-                            },
-                        };
-                        Ok(HttpResponse::Ok().json(response))
+            if password_valid {
+                let user_id = user._id.unwrap().to_hex();
+
+                // Moves bcrypt and outdated Argon2 hashes to the current parameters
+                if password_hasher.needs_rehash(&user.password) {
+                    match password_hasher.hash(&login_req.password).await {
+                        Ok(new_hash) => match user_service.upgrade_password_hash(&user._id.unwrap(), &user.password, &new_hash).await {
+                            Ok(_) => user.password = new_hash,
+                            Err(e) => eprintln!("Failed to upgrade password hash: {}", e),
+                        },
+                        Err(e) => eprintln!("Password hashing error: {}", e),
                     }
-                    Err(e) => {
-                        login_log.set_failure("Token generation failed".to_string());
+                }
+
+                // The plaintext is only available here, so existing passwords are screened at login
+                if settings.check_breached_on_login && !user.password_breached && password_policy.breached.contains(&login_req.password) {
+                    user.password_breached = true;
+                    if let Err(e) = user_service.set_password_breached(&user._id.unwrap(), true).await {
+                        eprintln!("Failed to flag breached password: {}", e);
+                    }
+
+                    let audit_service = AuditService::new(db.get_ref().clone(), &tenant_id);
+                    audit_service.record(&AuditLog::new(
+                        &tenant_id,
+                        &user,
+                        "user.password_breached",
+                        "user",
+                        &user_id,
+                        Some(doc! { "email": &user.email }),
+                    )).await;
+                }
+
+                let limited = match apply_unverified_login_policy(&mut user, &verification_config) {
+                    Ok(limited) => limited,
+                    Err(response) => {
+                        login_log.set_failure("Email not verified".to_string());
                         if let Err(e) = log_service.save_login_log(&login_log).await {
                             eprintln!("Failed to save login log: {}", e);
                         }
-                        eprintln!("Token generation error: {}", e);
-                        Ok(HttpResponse::InternalServerError().json("Internal server error"))
+                        return Ok(response);
                     }
+                };
+
+                // The password was only the first factor
+                if !user.second_factor_methods().is_empty() {
+                    return Ok(start_mfa_challenge(&db, &user, login_req.org_id.clone(), &["pwd"], login_log).await);
                 }
+
+                Ok(issue_login_token(&db, &cookie_config, &user, login_req.org_id.as_deref(), limited, vec!["pwd".to_string()], login_log).await)
             } else {
+                if register_login_failure(&db, &mail, &user_service, &user).await {
+                    login_log.set_failure("Invalid password, account locked".to_string());
+                    if let Err(e) = log_service.save_login_log(&login_log).await {
+                        eprintln!("Failed to save login log: {}", e);
+                    }
+                    return Ok(account_locked());
+                }
+
                 login_log.set_failure("Invalid password".to_string());
                 if let Err(e) = log_service.save_login_log(&login_log).await {
                     eprintln!("Failed to save login log: {}", e);
@@ -130,11 +490,31 @@ pub async fn login(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn register(
+    req: HttpRequest,
     db: web::Data<Database>,
+    kong: web::Data<KongService>,
+    mail: web::Data<MailService>,
+    tenant_config: web::Data<TenantConfig>,
+    password_policy: web::Data<PasswordPolicy>,
+    password_hasher: web::Data<HashingPool>,
     register_req: web::Json<RegisterRequest>,
 ) -> Result<HttpResponse> {
-    let user_service = UserService::new(db.get_ref().clone());
+    let tenant_id = match resolve_tenant(&req, &tenant_config, register_req.tenant.as_deref()) {
+        Ok(tenant_id) => tenant_id,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(e)),
+    };
+
+    if let Err(e) = validate_name(&register_req.name) {
+        return Ok(HttpResponse::BadRequest().json(e));
+    }
+    let password_context = PasswordContext { email: &register_req.email, name: Some(&register_req.name) };
+    if let Err(violations) = password_policy.check(&register_req.password, &password_context) {
+        return Ok(HttpResponse::BadRequest().json(PasswordPolicyError::from(violations)));
+    }
+
+    let user_service = UserService::new(db.get_ref().clone(), &tenant_id);
 
     // Check if user already exists
     match user_service.find_by_email(&register_req.email).await {
@@ -147,7 +527,7 @@ pub async fn register(
     }
 
     // Hash password
-    let hashed_password = match hash(&register_req.password, DEFAULT_COST) {
+    let hashed_password = match password_hasher.hash(&register_req.password).await {
         Ok(h) => h,
         Err(e) => {
             eprintln!("Password hashing error: {}", e);
@@ -159,22 +539,34 @@ pub async fn register(
     let now = SystemTime::now();
     let _datetime = DateTime::from_system_time(now);
     
-    let new_user = User::new(
+    let mut new_user = User::new(
+        tenant_id,
         register_req.email.clone(),
         hashed_password,
     );
+    let name = register_req.name.trim();
+    new_user.name = (!name.is_empty()).then(|| name.to_string());
 
     // Save user to database
     match user_service.create_user(&new_user).await {
-        Ok(_) => {
-            let new_user_email = new_user.email.clone();
-            let response = UserResponse {
-                id: new_user._id.unwrap().to_hex(),
-                email: new_user.email,
-                name: new_user_email, // Use email as name for now
-                roles: new_user.roles.unwrap_or_default(),
-            };
-            Ok(HttpResponse::Created().json(response))
+        Ok(user_id) => {
+            if let Err(e) = send_verification_email(&user_service, &mail, &new_user).await {
+                eprintln!("Failed to send verification email: {}", e);
+            }
+
+            // Register the consumer in Kong so it can verify this user's tokens locally
+            if kong.is_enabled() {
+                match kong.sync_user(&new_user).await {
+                    Ok(consumer_id) => {
+                        if let Err(e) = user_service.set_kong_consumer_id(&user_id, &consumer_id).await {
+                            eprintln!("Failed to save Kong consumer id: {}", e);
+                        }
+                    }
+                    Err(e) => eprintln!("Kong sync error for user {}: {}", user_id.to_hex(), e),
+                }
+            }
+
+            Ok(HttpResponse::Created().json(UserResponse::from(&new_user)))
         }
         Err(e) => {
             eprintln!("Database error: {}", e);
@@ -183,18 +575,63 @@ pub async fn register(
     }
 }
 
-pub async fn protected(req: HttpRequest) -> Result<HttpResponse> {
-    match verify_jwt_token(&req) {
-        Ok(user) => {
-            let user_email = user.email.clone();
-            let response = UserResponse {
-                id: user._id.unwrap().to_hex(),
-                email: user.email,
-                name: user_email, // Use email as name for now
-                roles: user.roles.unwrap_or_default(),
-            };
-            Ok(HttpResponse::Ok().json(response))
+pub async fn protected(auth: Authenticated) -> Result<HttpResponse> {
+    let mut user = auth.user;
+
+    // The name is not part of the token, so it comes from the stored profile
+    user.name = auth.stored.name;
+
+    Ok(HttpResponse::Ok().json(UserResponse::from(&user)))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+
+    fn unverified_user() -> User {
+        let mut user = User::new("acme".to_string(), "jane@example.com".to_string(), String::new());
+        user.roles = Some(vec!["user".to_string(), "billing".to_string()]);
+        user
+    }
+
+    fn policy(unverified_login: UnverifiedLoginPolicy) -> EmailVerificationConfig {
+        EmailVerificationConfig { unverified_login }
+    }
+
+    #[test]
+    fn test_unverified_login_allowed() {
+        let mut user = unverified_user();
+
+        assert!(matches!(apply_unverified_login_policy(&mut user, &policy(UnverifiedLoginPolicy::Allow)), Ok(false)));
+        assert_eq!(user.roles, Some(vec!["user".to_string(), "billing".to_string()]));
+    }
+
+    #[test]
+    fn test_unverified_login_limited_to_unverified_role() {
+        let mut user = unverified_user();
+
+        assert!(matches!(apply_unverified_login_policy(&mut user, &policy(UnverifiedLoginPolicy::Limited)), Ok(true)));
+        assert_eq!(user.roles, Some(vec!["unverified".to_string()]));
+    }
+
+    #[test]
+    fn test_unverified_login_blocked() {
+        let mut user = unverified_user();
+
+        match apply_unverified_login_policy(&mut user, &policy(UnverifiedLoginPolicy::Reject)) {
+            Err(response) => assert_eq!(response.status(), StatusCode::FORBIDDEN),
+            Ok(_) => panic!("unverified login should be rejected"),
         }
-        Err(_) => Ok(HttpResponse::Unauthorized().json("Invalid token")),
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_verified_users_skip_the_policy() {
+        let mut user = unverified_user();
+        user.email_verified = true;
+
+        for unverified_login in [UnverifiedLoginPolicy::Limited, UnverifiedLoginPolicy::Reject] {
+            assert!(matches!(apply_unverified_login_policy(&mut user, &policy(unverified_login)), Ok(false)));
+        }
+        assert_eq!(user.roles, Some(vec!["user".to_string(), "billing".to_string()]));
+    }
+}
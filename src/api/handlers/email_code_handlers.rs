@@ -0,0 +1,216 @@
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use mongodb::Database;
+use mongodb::bson::{doc, DateTime};
+use serde::Deserialize;
+use crate::api::handlers::auth_handlers::{account_locked, apply_unverified_login_policy, issue_login_token, start_mfa_challenge};
+use crate::auth::resolve_tenant;
+use crate::config::{EmailVerificationConfig, SessionCookieConfig, TenantConfig};
+use crate::models::{AuditLog, LoginLog, SecuritySettings, User};
+use crate::services::{AuditService, LogService, MailService, MailTemplate, RateLimitService, SettingsService, UserService};
+use crate::utils::request_info::{get_client_ip, get_user_agent};
+use crate::utils::secure_token::{generate_numeric_code, hash_token};
+
+const LOGIN_CODE_DIGITS: u32 = 6;
+const LOGIN_CODE_TTL_MINUTES: i64 = 10;
+const MAX_LOGIN_CODE_ATTEMPTS: u32 = 5;
+const LOGIN_CODES_PER_EMAIL_PER_HOUR: i64 = 5;
+const LOGIN_CODES_PER_IP_PER_HOUR: i64 = 20;
+
+#[derive(Deserialize)]
+pub struct LoginCodeRequest {
+    email: String,
+    tenant: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct LoginCodeVerifyRequest {
+    email: String,
+    code: String,
+    tenant: Option<String>,
+    // Organization to activate in the issued token
+    org_id: Option<String>,
+}
+
+// Salted with the user id so equal codes of different users hash differently
+fn hash_login_code(user: &User, code: &str) -> String {
+    hash_token(&format!("{}:{}", user._id.unwrap().to_hex(), code.trim()))
+}
+
+// Answers the same way whether or not the account exists
+pub async fn request_login_code(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    mail: web::Data<MailService>,
+    tenant_config: web::Data<TenantConfig>,
+    body: web::Json<LoginCodeRequest>,
+) -> Result<HttpResponse> {
+    let tenant_id = match resolve_tenant(&req, &tenant_config, body.tenant.as_deref()) {
+        Ok(tenant_id) => tenant_id,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(e)),
+    };
+
+    let rate_limiter = RateLimitService::new(db.get_ref().clone());
+    let email_key = format!("login_code:{}:{}", tenant_id, body.email.to_lowercase());
+    let ip_key = format!("login_code_ip:{}", get_client_ip(&req).unwrap_or_default());
+
+    for (key, limit) in [(email_key, LOGIN_CODES_PER_EMAIL_PER_HOUR), (ip_key, LOGIN_CODES_PER_IP_PER_HOUR)] {
+        match rate_limiter.check(&key, limit, chrono::Duration::hours(1)).await {
+            Ok(true) => {}
+            Ok(false) => return Ok(HttpResponse::TooManyRequests().json("Too many requests, try again later")),
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+            }
+        }
+    }
+
+    let accepted = HttpResponse::Accepted().json("If the account exists, a sign-in code has been sent");
+    let user_service = UserService::new(db.get_ref().clone(), &tenant_id);
+
+    let user = match user_service.find_by_email(&body.email).await {
+        Ok(Some(user)) if user.is_active => user,
+        Ok(_) => return Ok(accepted),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+
+    // A new code replaces any previous one
+    let code = generate_numeric_code(LOGIN_CODE_DIGITS);
+    let expiry = DateTime::from_millis(
+        DateTime::now().timestamp_millis() + LOGIN_CODE_TTL_MINUTES * 60 * 1000,
+    );
+
+    if let Err(e) = user_service.set_email_otp(&user._id.unwrap(), &hash_login_code(&user, &code), expiry).await {
+        eprintln!("Database error: {}", e);
+        return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+    }
+
+    mail.send_template(&user.email, user.locale.as_deref(), MailTemplate::LoginCode {
+        code: &code,
+        ttl_minutes: LOGIN_CODE_TTL_MINUTES,
+    });
+
+    Ok(accepted)
+}
+
+// Trades the emailed code for the normal login response
+pub async fn verify_login_code(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
+    verification_config: web::Data<EmailVerificationConfig>,
+    mail: web::Data<MailService>,
+    cookie_config: web::Data<SessionCookieConfig>,
+    body: web::Json<LoginCodeVerifyRequest>,
+) -> Result<HttpResponse> {
+    let tenant_id = match resolve_tenant(&req, &tenant_config, body.tenant.as_deref()) {
+        Ok(tenant_id) => tenant_id,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(e)),
+    };
+
+    let user_service = UserService::new(db.get_ref().clone(), &tenant_id);
+    let log_service = LogService::new(db.get_ref().clone(), &tenant_id);
+
+    let mut login_log = LoginLog::new(tenant_id.clone(), body.email.clone(), false, get_client_ip(&req), get_user_agent(&req));
+    login_log.login_method = "email_otp".to_string();
+    login_log.request_path = req.path().to_string();
+
+    let invalid = || HttpResponse::Unauthorized().json("Invalid or expired code");
+    let user = match user_service.find_by_email(&body.email).await {
+        Ok(user) => user,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+
+    let now = DateTime::now();
+    let failure = match &user {
+        None => Some(("User not found", invalid())),
+        Some(user) if !user.is_active => Some(("Account inactive", invalid())),
+        Some(user) if user.is_locked(now) => Some(("Account locked", account_locked())),
+        Some(user) if user.email_otp_hash.is_none() => Some(("No login code requested", invalid())),
+        Some(user) if user.email_otp_expiry.is_none_or(|expiry| expiry <= now) => Some(("Login code expired", invalid())),
+        Some(user) if user.email_otp_attempts >= MAX_LOGIN_CODE_ATTEMPTS => Some(("Too many login code attempts", invalid())),
+        Some(_) => None,
+    };
+
+    if let Some((reason, response)) = failure {
+        login_log.set_failure(reason.to_string());
+        if let Err(e) = log_service.save_login_log(&login_log).await {
+            eprintln!("Failed to save login log: {}", e);
+        }
+        return Ok(response);
+    }
+
+    let mut user = user.unwrap();
+    let user_id = user._id.unwrap();
+    let code_accepted = match user_service.use_email_otp(&user_id, &hash_login_code(&user, &body.code), MAX_LOGIN_CODE_ATTEMPTS).await {
+        Ok(accepted) => accepted,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+
+    // Wrong codes use up the code's attempts and count towards the account lockout
+    if !code_accepted {
+        if let Err(e) = user_service.register_email_otp_failure(&user_id).await {
+            eprintln!("Failed to count login code attempt: {}", e);
+        }
+
+        let settings = match SettingsService::new(db.get_ref().clone(), &tenant_id).get().await {
+            Ok(settings) => settings,
+            Err(e) => {
+                eprintln!("Failed to load security settings: {}", e);
+                SecuritySettings::new(tenant_id.clone())
+            }
+        };
+        let locked = match user_service.register_login_failure(&user_id, &settings.lockout, now).await {
+            Ok(locked) => locked,
+            Err(e) => {
+                eprintln!("Failed to save lockout state: {}", e);
+                false
+            }
+        };
+
+        login_log.set_failure(if locked { "Invalid login code, account locked" } else { "Invalid login code" }.to_string());
+        if let Err(e) = log_service.save_login_log(&login_log).await {
+            eprintln!("Failed to save login log: {}", e);
+        }
+
+        if locked {
+            mail.send_template(&user.email, user.locale.as_deref(), MailTemplate::SecurityAlert { event: "account_locked" });
+            return Ok(account_locked());
+        }
+        return Ok(invalid());
+    }
+
+    if !user.email_verified {
+        user.email_verified = true;
+        let audit_service = AuditService::new(db.get_ref().clone(), &tenant_id);
+        audit_service.record(&AuditLog::new(
+            &tenant_id,
+            &user,
+            "user.email_verified",
+            "user",
+            &user_id.to_hex(),
+            Some(doc! { "email": &user.email, "method": "email_otp" }),
+        )).await;
+    }
+
+    let limited = match apply_unverified_login_policy(&mut user, &verification_config) {
+        Ok(limited) => limited,
+        Err(response) => return Ok(response),
+    };
+
+    // Access to the mailbox is one factor; a configured second factor is still required
+    if !user.second_factor_methods().is_empty() {
+        return Ok(start_mfa_challenge(&db, &user, body.org_id.clone(), &["email", "otp"], login_log).await);
+    }
+
+    let amr = ["email", "otp"].iter().map(|m| m.to_string()).collect();
+    Ok(issue_login_token(&db, &cookie_config, &user, body.org_id.as_deref(), limited, amr, login_log).await)
+}
@@ -0,0 +1,155 @@
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use mongodb::Database;
+use mongodb::bson::{doc, DateTime};
+use serde::Deserialize;
+use std::error::Error;
+use crate::auth::resolve_tenant;
+use crate::config::TenantConfig;
+use crate::models::{AuditLog, User};
+use crate::services::{AuditService, MailService, MailTemplate, RateLimitService, UserService};
+use crate::utils::request_info::get_client_ip;
+use crate::utils::secure_token::{generate_token, hash_token};
+
+const EMAIL_VERIFICATION_TTL_HOURS: i64 = 24;
+const RESENDS_PER_EMAIL_PER_HOUR: i64 = 3;
+const RESENDS_PER_IP_PER_HOUR: i64 = 10;
+
+#[derive(Deserialize)]
+pub struct VerifyEmailRequest {
+    token: String,
+    tenant: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ResendVerificationRequest {
+    email: String,
+    tenant: Option<String>,
+}
+
+// Issues a new verification token for the user and emails the link
+pub async fn send_verification_email(
+    user_service: &UserService,
+    mail: &MailService,
+    user: &User,
+) -> std::result::Result<(), Box<dyn Error + Send + Sync>> {
+    let token = generate_token();
+    let expiry = DateTime::from_millis(
+        DateTime::now().timestamp_millis() + EMAIL_VERIFICATION_TTL_HOURS * 60 * 60 * 1000,
+    );
+
+    user_service.set_email_verification(&user._id.unwrap(), &hash_token(&token), expiry).await?;
+
+    mail.send_template(&user.email, user.locale.as_deref(), MailTemplate::EmailVerification {
+        link: &mail.link("/verify-email", &token),
+        ttl_hours: EMAIL_VERIFICATION_TTL_HOURS,
+    });
+
+    Ok(())
+}
+
+pub async fn verify_email(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
+    body: web::Json<VerifyEmailRequest>,
+) -> Result<HttpResponse> {
+    let tenant_id = match resolve_tenant(&req, &tenant_config, body.tenant.as_deref()) {
+        Ok(tenant_id) => tenant_id,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(e)),
+    };
+
+    let user_service = UserService::new(db.get_ref().clone(), &tenant_id);
+    let user = match user_service.verify_email(&hash_token(&body.token)).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return Ok(HttpResponse::BadRequest().json("Invalid or expired token")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+
+    let audit_service = AuditService::new(db.get_ref().clone(), &tenant_id);
+    audit_service.record(&AuditLog::new(
+        &tenant_id,
+        &user,
+        "user.email_verified",
+        "user",
+        &user._id.unwrap().to_hex(),
+        Some(doc! { "email": &user.email }),
+    )).await;
+
+    Ok(HttpResponse::Ok().json("Email verified"))
+}
+
+// Hourly limits on resends, per address and per client IP
+fn resend_rate_limits(tenant_id: &str, email: &str, ip: &str) -> [(String, i64); 2] {
+    [
+        (format!("email_resend:{}:{}", tenant_id, email.to_lowercase()), RESENDS_PER_EMAIL_PER_HOUR),
+        (format!("email_resend_ip:{}", ip), RESENDS_PER_IP_PER_HOUR),
+    ]
+}
+
+// Answers the same way whether or not the account exists
+pub async fn resend_verification(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    mail: web::Data<MailService>,
+    tenant_config: web::Data<TenantConfig>,
+    body: web::Json<ResendVerificationRequest>,
+) -> Result<HttpResponse> {
+    let tenant_id = match resolve_tenant(&req, &tenant_config, body.tenant.as_deref()) {
+        Ok(tenant_id) => tenant_id,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(e)),
+    };
+
+    let rate_limiter = RateLimitService::new(db.get_ref().clone());
+
+    for (key, limit) in resend_rate_limits(&tenant_id, &body.email, &get_client_ip(&req).unwrap_or_default()) {
+        match rate_limiter.check(&key, limit, chrono::Duration::hours(1)).await {
+            Ok(true) => {}
+            Ok(false) => return Ok(HttpResponse::TooManyRequests().json("Too many requests, try again later")),
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+            }
+        }
+    }
+
+    let accepted = HttpResponse::Accepted().json("If the account needs verification, an email has been sent");
+    let user_service = UserService::new(db.get_ref().clone(), &tenant_id);
+
+    let user = match user_service.find_by_email(&body.email).await {
+        Ok(Some(user)) if user.is_active && !user.email_verified => user,
+        Ok(_) => return Ok(accepted),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+
+    if let Err(e) = send_verification_email(&user_service, &mail, &user).await {
+        eprintln!("Database error: {}", e);
+        return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+    }
+
+    Ok(accepted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resend_rate_limit_keys() {
+        let [(email_key, email_limit), (ip_key, ip_limit)] = resend_rate_limits("acme", "Jane@Example.com", "203.0.113.7");
+
+        assert_eq!(email_key, "email_resend:acme:jane@example.com");
+        assert_eq!(email_limit, RESENDS_PER_EMAIL_PER_HOUR);
+        assert_eq!(ip_key, "email_resend_ip:203.0.113.7");
+        assert_eq!(ip_limit, RESENDS_PER_IP_PER_HOUR);
+
+        // Changing the case of the address doesn't get a fresh allowance, another tenant does
+        assert_eq!(resend_rate_limits("acme", "JANE@example.com", "203.0.113.7")[0].0, email_key);
+        assert_ne!(resend_rate_limits("globex", "jane@example.com", "203.0.113.7")[0].0, email_key);
+    }
+}
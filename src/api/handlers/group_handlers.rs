@@ -0,0 +1,515 @@
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use mongodb::Database;
+use mongodb::bson::{doc, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use crate::api::authenticated::Authenticated;
+use crate::auth::admin_scope;
+use crate::config::TenantConfig;
+use crate::models::{AuditLog, Group, User};
+use crate::services::{AuditService, GroupService, UserService};
+
+#[derive(Deserialize)]
+pub struct CreateGroupRequest {
+    name: String,
+    description: Option<String>,
+    roles: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateGroupRequest {
+    name: Option<String>,
+    description: Option<String>,
+    roles: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+pub struct GroupMemberRequest {
+    user_id: String,
+}
+
+#[derive(Deserialize)]
+pub struct AuditLogsQuery {
+    target_type: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct GroupResponse {
+    id: String,
+    name: String,
+    description: Option<String>,
+    roles: Vec<String>,
+    member_ids: Vec<String>,
+}
+
+impl From<Group> for GroupResponse {
+    fn from(group: Group) -> Self {
+        Self {
+            id: group._id.map(|id| id.to_hex()).unwrap_or_default(),
+            name: group.name,
+            description: group.description,
+            roles: group.roles,
+            member_ids: group.member_ids,
+        }
+    }
+}
+
+// Authenticates an admin and resolves the single tenant whose groups they manage
+fn group_admin(req: &HttpRequest, auth: Authenticated, tenant_config: &TenantConfig) -> std::result::Result<(User, String), HttpResponse> {
+    let admin = auth.direct_user()?;
+
+    if !admin.is_admin() {
+        return Err(HttpResponse::Forbidden().json("Admin access required"));
+    }
+
+    let scope = admin_scope(req, tenant_config, &admin)
+        .map_err(|e| HttpResponse::BadRequest().json(e))?;
+
+    match scope.tenant_id() {
+        Some(tenant_id) => {
+            let tenant_id = tenant_id.to_string();
+            Ok((admin, tenant_id))
+        }
+        None => Err(HttpResponse::BadRequest().json("Groups are managed one tenant at a time")),
+    }
+}
+
+fn validate_group_roles(admin: &User, roles: &[String]) -> std::result::Result<(), String> {
+    if roles.iter().any(|role| role.trim().is_empty()) {
+        return Err("Roles cannot be empty".to_string());
+    }
+
+    // Tenant admins cannot use groups to escalate to cross-tenant access
+    if roles.iter().any(|role| role == "super_admin") && !admin.is_super_admin() {
+        return Err("Only super admins can grant the super_admin role".to_string());
+    }
+
+    Ok(())
+}
+
+// Members get every role of their group, so a group granting super_admin or a role the caller
+// doesn't hold can only be changed or deleted by a super admin
+fn check_group_access(admin: &User, group: &Group) -> std::result::Result<(), HttpResponse> {
+    if admin.is_super_admin() || group.roles.iter().all(|role| admin.has_role(role)) {
+        return Ok(());
+    }
+    Err(HttpResponse::Forbidden().json("Only super admins can manage groups granting roles you don't hold"))
+}
+
+fn parse_group_id(id: &str) -> std::result::Result<ObjectId, HttpResponse> {
+    ObjectId::from_str(id).map_err(|_| HttpResponse::BadRequest().json("Invalid group ID"))
+}
+
+async fn load_group(group_service: &GroupService, id: &ObjectId) -> std::result::Result<Group, HttpResponse> {
+    match group_service.find_by_id(id).await {
+        Ok(Some(group)) => Ok(group),
+        Ok(None) => Err(HttpResponse::NotFound().json("Group not found")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
+
+pub async fn create_group(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
+    body: web::Json<CreateGroupRequest>,
+) -> Result<HttpResponse> {
+    let (admin, tenant_id) = match group_admin(&req, auth, &tenant_config) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    let name = body.name.trim();
+    if name.is_empty() {
+        return Ok(HttpResponse::BadRequest().json("Group name is required"));
+    }
+
+    if let Err(e) = validate_group_roles(&admin, &body.roles) {
+        return Ok(HttpResponse::BadRequest().json(e));
+    }
+
+    let group_service = GroupService::new(db.get_ref().clone(), &tenant_id);
+
+    match group_service.find_by_name(name).await {
+        Ok(Some(_)) => return Ok(HttpResponse::Conflict().json("Group already exists")),
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    }
+
+    let group = Group::new(tenant_id.clone(), name.to_string(), body.description.clone(), body.roles.clone());
+
+    match group_service.create_group(&group).await {
+        Ok(group_id) => {
+            let audit_service = AuditService::new(db.get_ref().clone(), &tenant_id);
+            audit_service.record(&AuditLog::new(
+                &tenant_id,
+                &admin,
+                "group.created",
+                "group",
+                &group_id.to_hex(),
+                Some(doc! { "name": &group.name, "roles": &group.roles }),
+            )).await;
+
+            Ok(HttpResponse::Created().json(GroupResponse::from(group)))
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Ok(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
+
+pub async fn list_groups(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
+) -> Result<HttpResponse> {
+    let (_, tenant_id) = match group_admin(&req, auth, &tenant_config) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    let group_service = GroupService::new(db.get_ref().clone(), &tenant_id);
+
+    match group_service.list_groups().await {
+        Ok(groups) => {
+            let response: Vec<GroupResponse> = groups.into_iter().map(GroupResponse::from).collect();
+            Ok(HttpResponse::Ok().json(response))
+        }
+        Err(e) => {
+            eprintln!("Error fetching groups: {}", e);
+            Ok(HttpResponse::InternalServerError().json("Failed to fetch groups"))
+        }
+    }
+}
+
+pub async fn get_group(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let (_, tenant_id) = match group_admin(&req, auth, &tenant_config) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    let group_id = match parse_group_id(&path) {
+        Ok(id) => id,
+        Err(response) => return Ok(response),
+    };
+
+    let group_service = GroupService::new(db.get_ref().clone(), &tenant_id);
+
+    match load_group(&group_service, &group_id).await {
+        Ok(group) => Ok(HttpResponse::Ok().json(GroupResponse::from(group))),
+        Err(response) => Ok(response),
+    }
+}
+
+pub async fn update_group(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
+    path: web::Path<String>,
+    body: web::Json<UpdateGroupRequest>,
+) -> Result<HttpResponse> {
+    let (admin, tenant_id) = match group_admin(&req, auth, &tenant_config) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    let group_id = match parse_group_id(&path) {
+        Ok(id) => id,
+        Err(response) => return Ok(response),
+    };
+
+    let group_service = GroupService::new(db.get_ref().clone(), &tenant_id);
+    let mut group = match load_group(&group_service, &group_id).await {
+        Ok(group) => group,
+        Err(response) => return Ok(response),
+    };
+    if let Err(response) = check_group_access(&admin, &group) {
+        return Ok(response);
+    }
+
+    let previous_roles = group.roles.clone();
+
+    if let Some(name) = &body.name {
+        if name.trim().is_empty() {
+            return Ok(HttpResponse::BadRequest().json("Group name is required"));
+        }
+        group.name = name.trim().to_string();
+    }
+    if let Some(description) = &body.description {
+        group.description = Some(description.clone());
+    }
+    if let Some(roles) = &body.roles {
+        if let Err(e) = validate_group_roles(&admin, roles) {
+            return Ok(HttpResponse::BadRequest().json(e));
+        }
+        group.roles = roles.clone();
+    }
+
+    match group_service.update_group(&group_id, &group).await {
+        Ok(_) => {
+            let audit_service = AuditService::new(db.get_ref().clone(), &tenant_id);
+            audit_service.record(&AuditLog::new(
+                &tenant_id,
+                &admin,
+                "group.updated",
+                "group",
+                &group_id.to_hex(),
+                Some(doc! {
+                    "name": &group.name,
+                    "previous_roles": &previous_roles,
+                    "roles": &group.roles,
+                }),
+            )).await;
+
+            Ok(HttpResponse::Ok().json(GroupResponse::from(group)))
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Ok(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
+
+pub async fn delete_group(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let (admin, tenant_id) = match group_admin(&req, auth, &tenant_config) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    let group_id = match parse_group_id(&path) {
+        Ok(id) => id,
+        Err(response) => return Ok(response),
+    };
+
+    let group_service = GroupService::new(db.get_ref().clone(), &tenant_id);
+    let group = match load_group(&group_service, &group_id).await {
+        Ok(group) => group,
+        Err(response) => return Ok(response),
+    };
+    if let Err(response) = check_group_access(&admin, &group) {
+        return Ok(response);
+    }
+
+    match group_service.delete_group(&group_id).await {
+        Ok(_) => {
+            let audit_service = AuditService::new(db.get_ref().clone(), &tenant_id);
+            audit_service.record(&AuditLog::new(
+                &tenant_id,
+                &admin,
+                "group.deleted",
+                "group",
+                &group_id.to_hex(),
+                Some(doc! {
+                    "name": &group.name,
+                    "roles": &group.roles,
+                    "member_ids": &group.member_ids,
+                }),
+            )).await;
+
+            Ok(HttpResponse::NoContent().finish())
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Ok(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
+
+pub async fn add_group_member(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
+    path: web::Path<String>,
+    body: web::Json<GroupMemberRequest>,
+) -> Result<HttpResponse> {
+    let (admin, tenant_id) = match group_admin(&req, auth, &tenant_config) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    let group_id = match parse_group_id(&path) {
+        Ok(id) => id,
+        Err(response) => return Ok(response),
+    };
+
+    let group_service = GroupService::new(db.get_ref().clone(), &tenant_id);
+    let group = match load_group(&group_service, &group_id).await {
+        Ok(group) => group,
+        Err(response) => return Ok(response),
+    };
+    if let Err(response) = check_group_access(&admin, &group) {
+        return Ok(response);
+    }
+
+    // Members must be users of the same tenant
+    let user_service = UserService::new(db.get_ref().clone(), &tenant_id);
+    let member_exists = match ObjectId::from_str(&body.user_id) {
+        Ok(user_id) => user_service.find_by_id(&user_id).await.map(|user| user.is_some()),
+        Err(_) => Ok(false),
+    };
+
+    match member_exists {
+        Ok(true) => {}
+        Ok(false) => return Ok(HttpResponse::NotFound().json("User not found")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    }
+
+    match group_service.add_member(&group_id, &body.user_id).await {
+        Ok(true) => {
+            let audit_service = AuditService::new(db.get_ref().clone(), &tenant_id);
+            audit_service.record(&AuditLog::new(
+                &tenant_id,
+                &admin,
+                "group.member_added",
+                "group",
+                &group_id.to_hex(),
+                Some(doc! { "user_id": &body.user_id }),
+            )).await;
+
+            Ok(HttpResponse::Ok().json("Member added"))
+        }
+        Ok(false) => Ok(HttpResponse::Conflict().json("User is already a member of this group")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Ok(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
+
+pub async fn remove_group_member(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse> {
+    let (admin, tenant_id) = match group_admin(&req, auth, &tenant_config) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    let (group_id, user_id) = path.into_inner();
+    let group_id = match parse_group_id(&group_id) {
+        Ok(id) => id,
+        Err(response) => return Ok(response),
+    };
+
+    let group_service = GroupService::new(db.get_ref().clone(), &tenant_id);
+    let group = match load_group(&group_service, &group_id).await {
+        Ok(group) => group,
+        Err(response) => return Ok(response),
+    };
+    if let Err(response) = check_group_access(&admin, &group) {
+        return Ok(response);
+    }
+
+    match group_service.remove_member(&group_id, &user_id).await {
+        Ok(true) => {
+            let audit_service = AuditService::new(db.get_ref().clone(), &tenant_id);
+            audit_service.record(&AuditLog::new(
+                &tenant_id,
+                &admin,
+                "group.member_removed",
+                "group",
+                &group_id.to_hex(),
+                Some(doc! { "user_id": &user_id }),
+            )).await;
+
+            Ok(HttpResponse::NoContent().finish())
+        }
+        Ok(false) => Ok(HttpResponse::NotFound().json("Member not found")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Ok(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
+
+pub async fn get_audit_logs(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
+    query: web::Query<AuditLogsQuery>,
+) -> Result<HttpResponse> {
+    let admin = match auth.direct_user() {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+
+    if !admin.is_admin() {
+        return Ok(HttpResponse::Forbidden().json("Admin access required"));
+    }
+
+    let scope = match admin_scope(&req, &tenant_config, &admin) {
+        Ok(scope) => scope,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(e)),
+    };
+
+    let audit_service = AuditService::with_scope(db.get_ref().clone(), scope);
+
+    match audit_service.list(query.target_type.as_deref(), query.limit).await {
+        Ok(entries) => Ok(HttpResponse::Ok().json(entries)),
+        Err(e) => {
+            eprintln!("Error fetching audit logs: {}", e);
+            Ok(HttpResponse::InternalServerError().json("Failed to fetch audit logs"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+
+    fn user(roles: &[&str]) -> User {
+        let mut user = User::new("acme".to_string(), "admin@acme.com".to_string(), String::new());
+        user.roles = Some(roles.iter().map(|r| r.to_string()).collect());
+        user
+    }
+
+    fn group(roles: &[&str]) -> Group {
+        Group::new("acme".to_string(), "ops".to_string(), None, roles.iter().map(|r| r.to_string()).collect())
+    }
+
+    #[test]
+    fn test_tenant_admins_cannot_manage_privileged_groups() {
+        let admin = user(&["admin", "support"]);
+        let super_admin = user(&["super_admin"]);
+
+        assert!(check_group_access(&admin, &group(&["support"])).is_ok());
+        assert!(check_group_access(&admin, &group(&[])).is_ok());
+        for roles in [&["super_admin"][..], &["support", "billing"][..]] {
+            let denied = check_group_access(&admin, &group(roles)).unwrap_err();
+            assert_eq!(denied.status(), StatusCode::FORBIDDEN);
+            assert!(check_group_access(&super_admin, &group(roles)).is_ok());
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use mongodb::Database;
+use mongodb::bson::{doc, oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use crate::api::authenticated::Authenticated;
+use crate::auth::{admin_scope, create_jwt_token, effective_roles, Actor, TokenOptions};
+use crate::config::TenantConfig;
+use crate::models::{AuditLog, LoginLog, TenantScope, User};
+use crate::services::{AuditService, GroupService, LogService, RevokedTokenService, UserService};
+use crate::utils::request_info::{get_client_ip, get_user_agent};
+
+// Impersonation tokens are deliberately short-lived
+const IMPERSONATION_TOKEN_MINUTES: i64 = 15;
+
+#[derive(Deserialize)]
+pub struct ImpersonationRequest {
+    reason: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ImpersonationResponse {
+    token: String,
+    expires_in: i64,
+    user_id: String,
+    email: String,
+}
+
+#[derive(Debug, PartialEq)]
+enum ImpersonationDenied {
+    Yourself,
+    Admin,
+    OtherTenant,
+    Inactive,
+}
+
+impl ImpersonationDenied {
+    fn response(&self) -> HttpResponse {
+        match self {
+            ImpersonationDenied::Yourself => HttpResponse::BadRequest().json("Cannot impersonate yourself"),
+            ImpersonationDenied::Admin => HttpResponse::Forbidden().json("Admins cannot be impersonated"),
+            ImpersonationDenied::OtherTenant => HttpResponse::Forbidden().json("Cannot impersonate users of another tenant"),
+            ImpersonationDenied::Inactive => HttpResponse::Conflict().json("User is not active"),
+        }
+    }
+}
+
+// Admins (including super_admins, directly or through a group) are never impersonated, and
+// the target must belong to the single tenant the request is scoped to
+fn check_impersonation(admin: &User, target: &User, group_roles: &[String], scope: &TenantScope) -> Result<(), ImpersonationDenied> {
+    if target._id.is_some() && target._id == admin._id {
+        return Err(ImpersonationDenied::Yourself);
+    }
+    // Judged on the roles the token would carry
+    let token_user = User { roles: Some(effective_roles(target, group_roles)), ..target.clone() };
+    if token_user.is_admin() {
+        return Err(ImpersonationDenied::Admin);
+    }
+    if scope.tenant_id() != Some(target.tenant_id.as_str()) {
+        return Err(ImpersonationDenied::OtherTenant);
+    }
+    if !target.is_active {
+        return Err(ImpersonationDenied::Inactive);
+    }
+    Ok(())
+}
+
+// The token is issued for the target user, with the admin recorded in the `act` claim
+fn impersonation_token_options(admin_id: &str, admin: &User, group_roles: Vec<String>) -> TokenOptions {
+    TokenOptions {
+        group_roles,
+        act: Some(Actor { sub: admin_id.to_string(), email: admin.email.clone() }),
+        lifetime: Some(chrono::Duration::minutes(IMPERSONATION_TOKEN_MINUTES)),
+        ..TokenOptions::default()
+    }
+}
+
+fn impersonation_log(req: &HttpRequest, target: &User, admin_id: &str, method: &str) -> LoginLog {
+    let mut login_log = LoginLog::new(
+        target.tenant_id.clone(),
+        target.email.clone(),
+        true,
+        get_client_ip(req),
+        get_user_agent(req),
+    );
+    login_log.login_method = method.to_string();
+    login_log.impersonator_id = Some(admin_id.to_string());
+    login_log.request_path = req.path().to_string();
+    login_log
+}
+
+pub async fn start_impersonation(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
+    path: web::Path<String>,
+    body: Option<web::Json<ImpersonationRequest>>,
+) -> Result<HttpResponse> {
+    // An impersonation token cannot start another impersonation
+    let admin = match auth.direct_user() {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+
+    if !admin.is_admin() {
+        return Ok(HttpResponse::Forbidden().json("Admin access required"));
+    }
+
+    let scope = match admin_scope(&req, &tenant_config, &admin) {
+        Ok(scope) => scope,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(e)),
+    };
+
+    let target_id = match ObjectId::from_str(&path.into_inner()) {
+        Ok(id) => id,
+        Err(_) => return Ok(HttpResponse::BadRequest().json("Invalid user ID")),
+    };
+
+    let user_service = UserService::with_scope(db.get_ref().clone(), scope.clone());
+    let target = match user_service.find_by_id(&target_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return Ok(HttpResponse::NotFound().json("User not found")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+
+    let group_service = GroupService::new(db.get_ref().clone(), &target.tenant_id);
+    let group_roles = match group_service.roles_for_user(&target_id.to_hex()).await {
+        Ok(roles) => roles,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+
+    if let Err(denied) = check_impersonation(&admin, &target, &group_roles, &scope) {
+        return Ok(denied.response());
+    }
+    let admin_id = admin._id.unwrap().to_hex();
+
+    let options = impersonation_token_options(&admin_id, &admin, group_roles);
+    let token = match create_jwt_token(&target, &options) {
+        Ok(token) => token,
+        Err(e) => {
+            eprintln!("Token generation error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+
+    // Recorded under the target user so it shows up in their own login history
+    let mut login_log = impersonation_log(&req, &target, &admin_id, "impersonation");
+    login_log.set_success(target_id.to_hex(), true, false);
+    let log_service = LogService::new(db.get_ref().clone(), &target.tenant_id);
+    if let Err(e) = log_service.save_login_log(&login_log).await {
+        eprintln!("Failed to save login log: {}", e);
+    }
+
+    let reason = body.and_then(|b| b.into_inner().reason);
+    let audit_service = AuditService::new(db.get_ref().clone(), &target.tenant_id);
+    audit_service.record(&AuditLog::new(
+        &target.tenant_id,
+        &admin,
+        "impersonation.started",
+        "user",
+        &target_id.to_hex(),
+        Some(doc! { "email": &target.email, "reason": reason }),
+    )).await;
+
+    Ok(HttpResponse::Ok().json(ImpersonationResponse {
+        token,
+        expires_in: IMPERSONATION_TOKEN_MINUTES * 60,
+        user_id: target_id.to_hex(),
+        email: target.email,
+    }))
+}
+
+// Called with the impersonation token when the admin is done; the token stops working
+pub async fn end_impersonation(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+) -> Result<HttpResponse> {
+    let actor = match auth.claims.act.clone() {
+        Some(actor) => actor,
+        None => return Ok(HttpResponse::BadRequest().json("Not an impersonation token")),
+    };
+
+    let expires_at = DateTime::from_millis(auth.claims.exp as i64 * 1000);
+    if let Err(e) = RevokedTokenService::new(db.get_ref().clone()).revoke(&auth.claims.jti, expires_at).await {
+        eprintln!("Failed to revoke impersonation token: {}", e);
+        return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+    }
+
+    let target = auth.user;
+    let target_id = target._id.unwrap().to_hex();
+
+    let mut login_log = impersonation_log(&req, &target, &actor.sub, "impersonation_end");
+    login_log.user_id = Some(target_id.clone());
+    let log_service = LogService::new(db.get_ref().clone(), &target.tenant_id);
+    if let Err(e) = log_service.save_login_log(&login_log).await {
+        eprintln!("Failed to save login log: {}", e);
+    }
+
+    let audit_service = AuditService::new(db.get_ref().clone(), &target.tenant_id);
+    audit_service.record(&AuditLog::new(
+        &target.tenant_id,
+        &target,
+        "impersonation.ended",
+        "user",
+        &target_id,
+        Some(doc! { "email": &target.email }),
+    ).with_actor(&actor.sub, &actor.email)).await;
+
+    Ok(HttpResponse::Ok().json("Impersonation ended"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use crate::auth::{user_from_claims, verify_jwt_claims};
+
+    fn user(tenant: &str, email: &str, roles: &[&str]) -> User {
+        let mut user = User::new(tenant.to_string(), email.to_string(), String::new());
+        user.roles = Some(roles.iter().map(|r| r.to_string()).collect());
+        user
+    }
+
+    fn acme() -> TenantScope {
+        TenantScope::Tenant("acme".to_string())
+    }
+
+    #[test]
+    fn test_impersonation_rules() {
+        let admin = user("acme", "admin@acme.com", &["admin"]);
+        let target = user("acme", "jane@acme.com", &["user"]);
+        assert_eq!(check_impersonation(&admin, &target, &[], &acme()), Ok(()));
+
+        assert_eq!(check_impersonation(&admin, &admin, &[], &acme()), Err(ImpersonationDenied::Yourself));
+
+        let mut inactive = target.clone();
+        inactive.is_active = false;
+        assert_eq!(check_impersonation(&admin, &inactive, &[], &acme()), Err(ImpersonationDenied::Inactive));
+    }
+
+    #[test]
+    fn test_admins_and_super_admins_cannot_be_impersonated() {
+        let super_admin = user("acme", "root@acme.com", &["super_admin"]);
+        let admin = user("acme", "admin@acme.com", &["admin"]);
+
+        assert_eq!(check_impersonation(&super_admin, &admin, &[], &acme()), Err(ImpersonationDenied::Admin));
+        assert_eq!(check_impersonation(&admin, &super_admin, &[], &acme()), Err(ImpersonationDenied::Admin));
+    }
+
+    #[test]
+    fn test_admins_through_a_group_cannot_be_impersonated() {
+        let admin = user("acme", "admin@acme.com", &["admin"]);
+        let target = user("acme", "jane@acme.com", &["user"]);
+
+        let group_roles = ["super_admin".to_string()];
+        assert_eq!(check_impersonation(&admin, &target, &group_roles, &acme()), Err(ImpersonationDenied::Admin));
+        assert_eq!(check_impersonation(&admin, &target, &["admin".to_string()], &acme()), Err(ImpersonationDenied::Admin));
+        assert_eq!(check_impersonation(&admin, &target, &["support".to_string()], &acme()), Ok(()));
+    }
+
+    #[test]
+    fn test_cannot_impersonate_another_tenant() {
+        let super_admin = user("acme", "root@acme.com", &["super_admin"]);
+        let outsider = user("globex", "hank@globex.com", &["user"]);
+
+        assert_eq!(check_impersonation(&super_admin, &outsider, &[], &acme()), Err(ImpersonationDenied::OtherTenant));
+        // Cross-tenant scope must name the tenant explicitly
+        assert_eq!(check_impersonation(&super_admin, &outsider, &[], &TenantScope::AllTenants), Err(ImpersonationDenied::OtherTenant));
+        assert_eq!(check_impersonation(&super_admin, &outsider, &[], &TenantScope::Tenant("globex".to_string())), Ok(()));
+    }
+
+    #[test]
+    fn test_impersonation_token_carries_act_claim() {
+        let admin = user("acme", "admin@acme.com", &["admin"]);
+        let target = user("acme", "jane@acme.com", &["user"]);
+        let admin_id = admin._id.unwrap().to_hex();
+
+        let options = impersonation_token_options(&admin_id, &admin, vec!["support".to_string()]);
+        let token = create_jwt_token(&target, &options).unwrap();
+        let req = TestRequest::get()
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_http_request();
+
+        let claims = verify_jwt_claims(&req).unwrap();
+        assert_eq!(claims.sub, target._id.unwrap().to_hex());
+        assert_eq!(claims.roles, vec!["user", "support"]);
+        assert_eq!(claims.exp - claims.iat, (IMPERSONATION_TOKEN_MINUTES * 60) as usize);
+        let actor = claims.act.expect("act claim");
+        assert_eq!(actor.sub, admin_id);
+        assert_eq!(actor.email, "admin@acme.com");
+    }
+
+    #[test]
+    fn test_impersonation_token_cannot_start_nested_impersonation() {
+        let admin = user("acme", "admin@acme.com", &["admin"]);
+        // Even an impersonated admin account must not be able to chain impersonations
+        let target = user("acme", "ops@acme.com", &["admin"]);
+        let options = impersonation_token_options(&admin._id.unwrap().to_hex(), &admin, Vec::new());
+        let token = create_jwt_token(&target, &options).unwrap();
+        let req = TestRequest::post()
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_http_request();
+
+        let claims = verify_jwt_claims(&req).unwrap();
+        let auth = Authenticated { user: user_from_claims(claims.clone()).unwrap(), stored: target, claims };
+        assert_eq!(auth.direct_user().unwrap_err().status(), actix_web::http::StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,141 @@
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use mongodb::Database;
+use mongodb::bson::oid::ObjectId;
+use serde::Serialize;
+use std::str::FromStr;
+use crate::api::authenticated::Authenticated;
+use crate::auth::{admin_scope, rotate_signing_key};
+use crate::config::TenantConfig;
+use crate::models::TenantScope;
+use crate::services::{KongService, UserService};
+
+#[derive(Serialize)]
+pub struct KongSyncResponse {
+    user_id: String,
+    consumer_id: String,
+}
+
+#[derive(Serialize)]
+pub struct KeyRotationResponse {
+    kid: String,
+    synced_consumers: usize,
+    failed_consumers: Vec<String>,
+}
+
+pub async fn sync_kong_consumer(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    kong: web::Data<KongService>,
+    tenant_config: web::Data<TenantConfig>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let admin = match auth.direct_user() {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+
+    if !admin.is_admin() {
+        return Ok(HttpResponse::Forbidden().json("Admin access required"));
+    }
+
+    if !kong.is_enabled() {
+        return Ok(HttpResponse::ServiceUnavailable().json("Kong integration is not configured"));
+    }
+
+    let user_id = match ObjectId::from_str(&path.into_inner()) {
+        Ok(id) => id,
+        Err(_) => return Ok(HttpResponse::BadRequest().json("Invalid user ID")),
+    };
+
+    let scope = match admin_scope(&req, &tenant_config, &admin) {
+        Ok(scope) => scope,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(e)),
+    };
+
+    let user_service = UserService::with_scope(db.get_ref().clone(), scope);
+
+    let user = match user_service.find_by_id(&user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return Ok(HttpResponse::NotFound().json("User not found")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+
+    let consumer_id = match kong.sync_user(&user).await {
+        Ok(id) => id,
+        Err(e) => {
+            eprintln!("Kong sync error for user {}: {}", user_id.to_hex(), e);
+            return Ok(HttpResponse::BadGateway().json("Failed to sync Kong consumer"));
+        }
+    };
+
+    if let Err(e) = user_service.set_kong_consumer_id(&user_id, &consumer_id).await {
+        eprintln!("Database error: {}", e);
+        return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+    }
+
+    Ok(HttpResponse::Ok().json(KongSyncResponse {
+        user_id: user_id.to_hex(),
+        consumer_id,
+    }))
+}
+
+pub async fn rotate_jwt_signing_key(
+    auth: Authenticated,
+    db: web::Data<Database>,
+    kong: web::Data<KongService>,
+) -> Result<HttpResponse> {
+    let admin = match auth.direct_user() {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+
+    // The signing key is shared by every tenant
+    if !admin.is_super_admin() {
+        return Ok(HttpResponse::Forbidden().json("Super admin access required"));
+    }
+
+    let new_key = match rotate_signing_key() {
+        Ok(key) => key,
+        Err(e) => {
+            eprintln!("Key rotation error: {}", e);
+            return Ok(HttpResponse::Conflict().json(e));
+        }
+    };
+
+    let mut synced_consumers = 0;
+    let mut failed_consumers = Vec::new();
+
+    if kong.is_enabled() {
+        let user_service = UserService::with_scope(db.get_ref().clone(), TenantScope::AllTenants);
+
+        let users = match user_service.list_kong_consumers().await {
+            Ok(users) => users,
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+            }
+        };
+
+        // Push the new public key to every consumer so Kong keeps verifying our tokens locally
+        for user in users {
+            let user_id = user._id.map(|id| id.to_hex()).unwrap_or_default();
+            match kong.sync_user(&user).await {
+                Ok(_) => synced_consumers += 1,
+                Err(e) => {
+                    eprintln!("Kong sync error for user {}: {}", user_id, e);
+                    failed_consumers.push(user_id);
+                }
+            }
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(KeyRotationResponse {
+        kid: new_key.kid,
+        synced_consumers,
+        failed_consumers,
+    }))
+}
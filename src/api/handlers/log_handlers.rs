@@ -1,8 +1,9 @@
 use actix_web::{web, HttpRequest, HttpResponse, Result};
 use mongodb::Database;
 use serde::Deserialize;
-use crate::auth::verify_jwt_token;
-use crate::models::{User, LoginLog};
+use crate::api::authenticated::Authenticated;
+use crate::auth::admin_scope;
+use crate::config::TenantConfig;
 use crate::services::LogService;
 
 #[derive(Deserialize)]
@@ -11,17 +12,13 @@ pub struct LogsQuery {
 }
 
 pub async fn get_my_logs(
-    req: HttpRequest,
+    auth: Authenticated,
     db: web::Data<Database>,
     query: web::Query<LogsQuery>,
 ) -> Result<HttpResponse> {
-    // Verify JWT token
-    let user = match verify_jwt_token(&req) {
-        Ok(user) => user,
-        Err(_) => return Ok(HttpResponse::Unauthorized().json("Invalid token")),
-    };
+    let user = auth.user;
 
-    let log_service = LogService::new(db.get_ref().clone());
+    let log_service = LogService::new(db.get_ref().clone(), &user.tenant_id);
     
     // Get logs for the current user
     match log_service.get_user_logs(&user._id.unwrap().to_hex(), query.limit).await {
@@ -35,20 +32,26 @@ pub async fn get_my_logs(
 
 pub async fn get_all_logs(
     req: HttpRequest,
+    auth: Authenticated,
     db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
     query: web::Query<LogsQuery>,
 ) -> Result<HttpResponse> {
-    // Verify JWT token and check admin role
-    let user = match verify_jwt_token(&req) {
+    let user = match auth.direct_user() {
         Ok(user) => user,
-        Err(_) => return Ok(HttpResponse::Unauthorized().json("Invalid token")),
+        Err(response) => return Ok(response),
     };
 
     if !user.is_admin() {
         return Ok(HttpResponse::Forbidden().json("Admin access required"));
     }
 
-    let log_service = LogService::new(db.get_ref().clone());
+    let scope = match admin_scope(&req, &tenant_config, &user) {
+        Ok(scope) => scope,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(e)),
+    };
+
+    let log_service = LogService::with_scope(db.get_ref().clone(), scope);
     
     match log_service.get_all_logs(query.limit).await {
         Ok(logs) => Ok(HttpResponse::Ok().json(logs)),
@@ -61,19 +64,25 @@ pub async fn get_all_logs(
 
 pub async fn get_login_stats(
     req: HttpRequest,
+    auth: Authenticated,
     db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
 ) -> Result<HttpResponse> {
-    // Verify JWT token and check admin role
-    let user = match verify_jwt_token(&req) {
+    let user = match auth.direct_user() {
         Ok(user) => user,
-        Err(_) => return Ok(HttpResponse::Unauthorized().json("Invalid token")),
+        Err(response) => return Ok(response),
     };
 
     if !user.is_admin() {
         return Ok(HttpResponse::Forbidden().json("Admin access required"));
     }
 
-    let log_service = LogService::new(db.get_ref().clone());
+    let scope = match admin_scope(&req, &tenant_config, &user) {
+        Ok(scope) => scope,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(e)),
+    };
+
+    let log_service = LogService::with_scope(db.get_ref().clone(), scope);
     
     // Get stats for last 30 days by default
     match log_service.get_login_stats(30).await {
@@ -0,0 +1,160 @@
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use mongodb::Database;
+use mongodb::bson::DateTime;
+use mongodb::bson::oid::ObjectId;
+use serde::Deserialize;
+use std::str::FromStr;
+use crate::api::handlers::auth_handlers::{account_locked, apply_unverified_login_policy, issue_login_token, start_mfa_challenge};
+use crate::auth::{create_magic_link, resolve_tenant, verify_magic_link_token};
+use crate::config::{EmailVerificationConfig, SessionCookieConfig, TenantConfig};
+use crate::models::LoginLog;
+use crate::services::{LogService, MailService, MailTemplate, RateLimitService, UserService};
+use crate::utils::request_info::{get_client_ip, get_user_agent};
+use crate::utils::secure_token::hash_token;
+
+const MAGIC_LINK_TTL_MINUTES: i64 = 15;
+const MAGIC_LINKS_PER_EMAIL_PER_HOUR: i64 = 5;
+const MAGIC_LINKS_PER_IP_PER_HOUR: i64 = 20;
+
+#[derive(Deserialize)]
+pub struct MagicLinkRequest {
+    email: String,
+    tenant: Option<String>,
+    // Organization to activate in the issued token
+    org_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ConsumeMagicLinkRequest {
+    token: String,
+}
+
+// Answers the same way whether or not the account exists
+pub async fn request_magic_link(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    mail: web::Data<MailService>,
+    tenant_config: web::Data<TenantConfig>,
+    body: web::Json<MagicLinkRequest>,
+) -> Result<HttpResponse> {
+    let tenant_id = match resolve_tenant(&req, &tenant_config, body.tenant.as_deref()) {
+        Ok(tenant_id) => tenant_id,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(e)),
+    };
+
+    let rate_limiter = RateLimitService::new(db.get_ref().clone());
+    let email_key = format!("magic_link:{}:{}", tenant_id, body.email.to_lowercase());
+    let ip_key = format!("magic_link_ip:{}", get_client_ip(&req).unwrap_or_default());
+
+    for (key, limit) in [(email_key, MAGIC_LINKS_PER_EMAIL_PER_HOUR), (ip_key, MAGIC_LINKS_PER_IP_PER_HOUR)] {
+        match rate_limiter.check(&key, limit, chrono::Duration::hours(1)).await {
+            Ok(true) => {}
+            Ok(false) => return Ok(HttpResponse::TooManyRequests().json("Too many requests, try again later")),
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+            }
+        }
+    }
+
+    let accepted = HttpResponse::Accepted().json("If the account exists, a sign-in link has been sent");
+    let user_service = UserService::new(db.get_ref().clone(), &tenant_id);
+
+    let user = match user_service.find_by_email(&body.email).await {
+        Ok(Some(user)) if user.is_active => user,
+        Ok(_) => return Ok(accepted),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+
+    // Only the jti's hash is stored; a new link replaces any previous one
+    let (token, jti) = create_magic_link(&user, body.org_id.clone(), chrono::Duration::minutes(MAGIC_LINK_TTL_MINUTES));
+    if let Err(e) = user_service.set_magic_link(&user._id.unwrap(), &hash_token(&jti)).await {
+        eprintln!("Database error: {}", e);
+        return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+    }
+
+    mail.send_template(&user.email, user.locale.as_deref(), MailTemplate::MagicLink {
+        link: &mail.link("/magic-link", &token),
+        ttl_minutes: MAGIC_LINK_TTL_MINUTES,
+    });
+
+    Ok(accepted)
+}
+
+// Trades a link from the email for the normal login response
+pub async fn consume_magic_link(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    verification_config: web::Data<EmailVerificationConfig>,
+    cookie_config: web::Data<SessionCookieConfig>,
+    body: web::Json<ConsumeMagicLinkRequest>,
+) -> Result<HttpResponse> {
+    let invalid = || HttpResponse::BadRequest().json("Invalid or expired link");
+    let Some(link) = verify_magic_link_token(&body.token) else {
+        return Ok(invalid());
+    };
+    let Ok(user_id) = ObjectId::from_str(&link.sub) else {
+        return Ok(invalid());
+    };
+
+    let user_service = UserService::new(db.get_ref().clone(), &link.tenant_id);
+    let mut user = match user_service.find_by_id(&user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return Ok(invalid()),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+
+    let log_service = LogService::new(db.get_ref().clone(), &link.tenant_id);
+    let mut login_log = LoginLog::new(link.tenant_id.clone(), user.email.clone(), false, get_client_ip(&req), get_user_agent(&req));
+    login_log.login_method = "magic_link".to_string();
+    login_log.request_path = req.path().to_string();
+
+    let failure = if !user.is_active {
+        Some(("Account inactive", invalid()))
+    } else if user.token_revoked(link.iat) {
+        Some(("Magic link issued before password change", invalid()))
+    } else if user.is_locked(DateTime::now()) {
+        Some(("Account locked", account_locked()))
+    } else {
+        match user_service.use_magic_link(&user_id, &hash_token(&link.jti)).await {
+            Ok(true) => None,
+            Ok(false) => Some(("Magic link already used", invalid())),
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+            }
+        }
+    };
+
+    if let Some((reason, response)) = failure {
+        login_log.set_failure(reason.to_string());
+        if let Err(e) = log_service.save_login_log(&login_log).await {
+            eprintln!("Failed to save login log: {}", e);
+        }
+        return Ok(response);
+    }
+
+    let limited = match apply_unverified_login_policy(&mut user, &verification_config) {
+        Ok(limited) => limited,
+        Err(response) => {
+            login_log.set_failure("Email not verified".to_string());
+            if let Err(e) = log_service.save_login_log(&login_log).await {
+                eprintln!("Failed to save login log: {}", e);
+            }
+            return Ok(response);
+        }
+    };
+
+    // Access to the mailbox is one factor; a configured second factor is still required
+    if !user.second_factor_methods().is_empty() {
+        return Ok(start_mfa_challenge(&db, &user, link.org_id.clone(), &["email"], login_log).await);
+    }
+
+    Ok(issue_login_token(&db, &cookie_config, &user, link.org_id.as_deref(), limited, vec!["email".to_string()], login_log).await)
+}
@@ -0,0 +1,433 @@
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use mongodb::Database;
+use mongodb::bson::{doc, oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use crate::api::handlers::auth_handlers::{account_locked, apply_unverified_login_policy, issue_login_token, register_login_failure};
+use crate::api::authenticated::Authenticated;
+use crate::auth::{totp, verify_mfa_challenge_token, MfaChallengeClaims};
+use crate::auth::recovery_codes::{generate_recovery_codes, hash_recovery_code};
+use crate::config::{EmailVerificationConfig, MfaConfig, SessionCookieConfig};
+use crate::models::{AuditLog, LoginLog, User};
+use crate::services::{AuditService, LogService, MailService, MailTemplate, RateLimitService, UserService};
+use crate::utils::request_info::{get_client_ip, get_user_agent};
+
+// Wrong codes allowed per challenge token, on top of the account lockout
+const MAX_CHALLENGE_ATTEMPTS: i64 = 5;
+// Codes checked per user in 10 minutes when changing MFA settings, on top of the account lockout
+const MAX_SETTINGS_ATTEMPTS: i64 = 5;
+
+#[derive(Deserialize)]
+pub struct TotpCodeRequest {
+    code: String,
+}
+
+// Either a TOTP code from the current secret or one of the recovery codes; the recovery
+// code is the way back in for someone who lost their authenticator
+#[derive(Deserialize)]
+pub struct MfaCodeRequest {
+    code: Option<String>,
+    recovery_code: Option<String>,
+}
+
+// Either a TOTP code or one of the recovery codes
+#[derive(Deserialize)]
+pub struct MfaLoginRequest {
+    challenge_token: String,
+    code: Option<String>,
+    recovery_code: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct TotpEnrollmentResponse {
+    secret: String,
+    otpauth_uri: String,
+}
+
+#[derive(Serialize)]
+pub struct MfaStatusResponse {
+    mfa_enabled: bool,
+    // Only present when a new set was just generated; they are never shown again
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recovery_codes: Option<Vec<String>>,
+}
+
+#[derive(Serialize)]
+pub struct RecoveryCodesResponse {
+    recovery_codes: Vec<String>,
+}
+
+// MFA settings can only be changed by the account owner, never through impersonation
+pub fn direct_user(auth: Authenticated, db: &Database) -> std::result::Result<(User, UserService), HttpResponse> {
+    if auth.claims.is_impersonated() {
+        return Err(HttpResponse::Forbidden().json("Not allowed while impersonating"));
+    }
+
+    let (user, user_service, _) = auth.stored_user(db);
+    Ok((user, user_service))
+}
+
+// Checks a code against the active secret and burns its time step
+async fn use_current_code(user: &User, user_service: &UserService, code: &str) -> std::result::Result<bool, HttpResponse> {
+    let Some(secret) = &user.totp_secret else {
+        return Ok(false);
+    };
+    let Some(step) = totp::verify_code(secret, code, DateTime::now().timestamp_millis() / 1000, user.totp_last_step) else {
+        return Ok(false);
+    };
+
+    user_service.use_totp_step(&user._id.unwrap(), step).await.map_err(|e| {
+        eprintln!("Database error: {}", e);
+        HttpResponse::InternalServerError().json("Internal server error")
+    })
+}
+
+// Checks a TOTP code, or burns a recovery code
+async fn use_second_factor(
+    user: &User,
+    user_service: &UserService,
+    code: Option<&str>,
+    recovery_code: Option<&str>,
+) -> std::result::Result<bool, HttpResponse> {
+    match (code, recovery_code) {
+        (Some(code), None) => use_current_code(user, user_service, code).await,
+        (None, Some(recovery_code)) => user_service.use_recovery_code(&user._id.unwrap(), &hash_recovery_code(recovery_code)).await.map_err(|e| {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Internal server error")
+        }),
+        _ => Err(HttpResponse::BadRequest().json("Provide either code or recovery_code")),
+    }
+}
+
+// A recovery code means the authenticator may be lost or stolen, so the owner hears about it
+async fn record_recovery_code_use(db: &Database, mail: &MailService, req: &HttpRequest, user: &User) {
+    let remaining = user.recovery_codes.len().saturating_sub(1);
+    let audit_service = AuditService::new(db.clone(), &user.tenant_id);
+    audit_service.record(&AuditLog::new(
+        &user.tenant_id,
+        user,
+        "user.recovery_code_used",
+        "user",
+        &user._id.unwrap().to_hex(),
+        Some(doc! { "remaining": remaining as i64, "ip_address": get_client_ip(req) }),
+    )).await;
+    mail.send_template(&user.email, user.locale.as_deref(), MailTemplate::SecurityAlert { event: "recovery_code_used" });
+}
+
+// Proof of the second factor for changing MFA settings. A stolen access token must not
+// allow guessing codes, so attempts are rate limited and wrong codes count toward the lockout
+async fn check_mfa_code(
+    db: &Database,
+    mail: &MailService,
+    req: &HttpRequest,
+    user: &User,
+    user_service: &UserService,
+    body: &MfaCodeRequest,
+) -> std::result::Result<(), HttpResponse> {
+    if user.is_locked(DateTime::now()) {
+        return Err(account_locked());
+    }
+
+    let rate_limiter = RateLimitService::new(db.clone());
+    let key = format!("mfa_settings:{}:{}", user.tenant_id, user._id.unwrap().to_hex());
+    match rate_limiter.check(&key, MAX_SETTINGS_ATTEMPTS, chrono::Duration::minutes(10)).await {
+        Ok(true) => {}
+        Ok(false) => return Err(HttpResponse::TooManyRequests().json("Too many attempts, try again later")),
+        Err(e) => {
+            eprintln!("Rate limit error: {}", e);
+            return Err(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    }
+
+    if !use_second_factor(user, user_service, body.code.as_deref(), body.recovery_code.as_deref()).await? {
+        if register_login_failure(db, mail, user_service, user).await {
+            return Err(account_locked());
+        }
+        return Err(HttpResponse::Unauthorized().json("Invalid code"));
+    }
+    if body.recovery_code.is_some() {
+        record_recovery_code_use(db, mail, req, user).await;
+    }
+    Ok(())
+}
+
+async fn record_mfa_change(db: &Database, mail: &MailService, req: &HttpRequest, user: &User, action: &str, event: &str) {
+    let audit_service = AuditService::new(db.clone(), &user.tenant_id);
+    audit_service.record(&AuditLog::new(
+        &user.tenant_id,
+        user,
+        action,
+        "user",
+        &user._id.unwrap().to_hex(),
+        Some(doc! { "method": "totp", "ip_address": get_client_ip(req) }),
+    )).await;
+
+    mail.send_template(&user.email, user.locale.as_deref(), MailTemplate::SecurityAlert { event });
+}
+
+// Starts (re-)enrollment; while MFA is on, a code from the current secret or a recovery code is required
+pub async fn enroll_totp(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    mail: web::Data<MailService>,
+    mfa_config: web::Data<MfaConfig>,
+    body: Option<web::Json<MfaCodeRequest>>,
+) -> Result<HttpResponse> {
+    let (user, user_service) = match direct_user(auth, &db) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    if user.mfa_enabled {
+        let Some(body) = body else {
+            return Ok(HttpResponse::Forbidden().json("Current TOTP code or a recovery code required to re-enroll"));
+        };
+        if let Err(response) = check_mfa_code(&db, &mail, &req, &user, &user_service, &body).await {
+            return Ok(response);
+        }
+    }
+
+    let secret = totp::generate_secret();
+    if let Err(e) = user_service.set_totp_pending(&user._id.unwrap(), &secret).await {
+        eprintln!("Database error: {}", e);
+        return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+    }
+
+    Ok(HttpResponse::Ok().json(TotpEnrollmentResponse {
+        otpauth_uri: totp::otpauth_uri(&mfa_config.totp_issuer, &user.email, &secret),
+        secret,
+    }))
+}
+
+// Activates the pending secret once the app shows a matching code
+pub async fn confirm_totp(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    mail: web::Data<MailService>,
+    body: web::Json<TotpCodeRequest>,
+) -> Result<HttpResponse> {
+    let (user, user_service) = match direct_user(auth, &db) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    let Some(secret) = &user.totp_pending_secret else {
+        return Ok(HttpResponse::BadRequest().json("No TOTP enrollment in progress"));
+    };
+    let Some(step) = totp::verify_code(secret, &body.code, DateTime::now().timestamp_millis() / 1000, None) else {
+        return Ok(HttpResponse::Unauthorized().json("Invalid code"));
+    };
+
+    let recovery_codes = generate_recovery_codes();
+    let recovery_code_hashes: Vec<String> = recovery_codes.iter().map(|code| hash_recovery_code(code)).collect();
+
+    match user_service.confirm_totp(&user._id.unwrap(), secret, step, &recovery_code_hashes).await {
+        Ok(true) => {}
+        Ok(false) => return Ok(HttpResponse::Conflict().json("Enrollment was replaced, start again")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    }
+
+    record_mfa_change(&db, &mail, &req, &user, "user.mfa_enabled", "mfa_enabled").await;
+    Ok(HttpResponse::Ok().json(MfaStatusResponse { mfa_enabled: true, recovery_codes: Some(recovery_codes) }))
+}
+
+// Replaces every recovery code with a new set; needs a current TOTP code or a recovery code
+pub async fn regenerate_recovery_codes(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    mail: web::Data<MailService>,
+    body: web::Json<MfaCodeRequest>,
+) -> Result<HttpResponse> {
+    let (user, user_service) = match direct_user(auth, &db) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    if !user.mfa_enabled {
+        return Ok(HttpResponse::BadRequest().json("TOTP is not enabled"));
+    }
+    if let Err(response) = check_mfa_code(&db, &mail, &req, &user, &user_service, &body).await {
+        return Ok(response);
+    }
+
+    let recovery_codes = generate_recovery_codes();
+    let recovery_code_hashes: Vec<String> = recovery_codes.iter().map(|code| hash_recovery_code(code)).collect();
+    if let Err(e) = user_service.replace_recovery_codes(&user._id.unwrap(), &recovery_code_hashes).await {
+        eprintln!("Database error: {}", e);
+        return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+    }
+
+    let audit_service = AuditService::new(db.get_ref().clone(), &user.tenant_id);
+    audit_service.record(&AuditLog::new(
+        &user.tenant_id,
+        &user,
+        "user.recovery_codes_regenerated",
+        "user",
+        &user._id.unwrap().to_hex(),
+        Some(doc! { "ip_address": get_client_ip(&req) }),
+    )).await;
+
+    Ok(HttpResponse::Ok().json(RecoveryCodesResponse { recovery_codes }))
+}
+
+pub async fn disable_totp(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    mail: web::Data<MailService>,
+    body: web::Json<MfaCodeRequest>,
+) -> Result<HttpResponse> {
+    let (user, user_service) = match direct_user(auth, &db) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    if !user.mfa_enabled {
+        return Ok(HttpResponse::BadRequest().json("TOTP is not enabled"));
+    }
+    if let Err(response) = check_mfa_code(&db, &mail, &req, &user, &user_service, &body).await {
+        return Ok(response);
+    }
+
+    if let Err(e) = user_service.disable_totp(&user._id.unwrap()).await {
+        eprintln!("Database error: {}", e);
+        return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+    }
+
+    record_mfa_change(&db, &mail, &req, &user, "user.mfa_disabled", "mfa_disabled").await;
+    Ok(HttpResponse::Ok().json(MfaStatusResponse { mfa_enabled: false, recovery_codes: None }))
+}
+
+// Second step of an MFA login: trades the challenge from /login and a TOTP or recovery code for the tokens
+pub async fn login_mfa(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    verification_config: web::Data<EmailVerificationConfig>,
+    mail: web::Data<MailService>,
+    cookie_config: web::Data<SessionCookieConfig>,
+    body: web::Json<MfaLoginRequest>,
+) -> Result<HttpResponse> {
+    let Some(challenge) = verify_mfa_challenge_token(&body.challenge_token) else {
+        return Ok(HttpResponse::Unauthorized().json("Invalid or expired challenge"));
+    };
+    if body.code.is_some() == body.recovery_code.is_some() {
+        return Ok(HttpResponse::BadRequest().json("Provide either code or recovery_code"));
+    }
+
+    let rate_limiter = RateLimitService::new(db.get_ref().clone());
+    match rate_limiter.check(&format!("mfa_challenge:{}", challenge.jti), MAX_CHALLENGE_ATTEMPTS, chrono::Duration::minutes(10)).await {
+        Ok(true) => {}
+        Ok(false) => return Ok(HttpResponse::TooManyRequests().json("Too many attempts, log in again")),
+        Err(e) => {
+            eprintln!("Rate limit error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    }
+
+    let tenant_id = challenge.tenant_id.clone();
+    let log_service = LogService::new(db.get_ref().clone(), &tenant_id);
+    let (user, user_service) = match challenge_user(&db, &challenge).await {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    let mut login_log = LoginLog::new(tenant_id.clone(), user.email.clone(), false, get_client_ip(&req), get_user_agent(&req));
+    login_log.login_method = if body.recovery_code.is_some() { "recovery_code" } else { "totp" }.to_string();
+    login_log.request_path = req.path().to_string();
+
+    if user.is_locked(DateTime::now()) {
+        login_log.set_failure("Account locked".to_string());
+        if let Err(e) = log_service.save_login_log(&login_log).await {
+            eprintln!("Failed to save login log: {}", e);
+        }
+        return Ok(account_locked());
+    }
+
+    let code_accepted = match use_second_factor(&user, &user_service, body.code.as_deref(), body.recovery_code.as_deref()).await {
+        Ok(accepted) => accepted,
+        Err(response) => return Ok(response),
+    };
+
+    // Wrong codes count towards the same lockout as wrong passwords
+    if !code_accepted {
+        let locked = register_login_failure(&db, &mail, &user_service, &user).await;
+
+        let reason = if body.recovery_code.is_some() { "Invalid recovery code" } else { "Invalid TOTP code" };
+        login_log.set_failure(if locked { format!("{}, account locked", reason) } else { reason.to_string() });
+        if let Err(e) = log_service.save_login_log(&login_log).await {
+            eprintln!("Failed to save login log: {}", e);
+        }
+
+        if locked {
+            return Ok(account_locked());
+        }
+        return Ok(HttpResponse::Unauthorized().json("Invalid code"));
+    }
+
+    let amr = if body.recovery_code.is_some() {
+        record_recovery_code_use(&db, &mail, &req, &user).await;
+        mfa_amr(&challenge, &[])
+    } else {
+        mfa_amr(&challenge, &["otp"])
+    };
+    Ok(complete_mfa_login(&db, &verification_config, &cookie_config, user, challenge.org_id.as_deref(), amr, login_log).await)
+}
+
+// The first factor's methods from the challenge, then the second factor's, then "mfa"
+pub fn mfa_amr(challenge: &MfaChallengeClaims, methods: &[&str]) -> Vec<String> {
+    let mut amr = challenge.amr.clone();
+    for method in methods.iter().chain(&["mfa"]) {
+        if !amr.iter().any(|m| m == method) {
+            amr.push(method.to_string());
+        }
+    }
+    amr
+}
+
+// The user an MFA challenge was issued to; a password change since then invalidates it
+pub async fn challenge_user(db: &Database, challenge: &MfaChallengeClaims) -> std::result::Result<(User, UserService), HttpResponse> {
+    let invalid = || HttpResponse::Unauthorized().json("Invalid or expired challenge");
+    let user_service = UserService::new(db.clone(), &challenge.tenant_id);
+
+    let user_id = ObjectId::from_str(&challenge.sub).map_err(|_| invalid())?;
+    match user_service.find_by_id(&user_id).await {
+        Ok(Some(user)) if user.is_active && !user.second_factor_methods().is_empty() && !user.token_revoked(challenge.iat) => {
+            Ok((user, user_service))
+        }
+        Ok(_) => Err(invalid()),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
+
+// Shared end of every second factor once it has been checked
+pub async fn complete_mfa_login(
+    db: &Database,
+    verification_config: &EmailVerificationConfig,
+    cookie_config: &SessionCookieConfig,
+    mut user: User,
+    org_id: Option<&str>,
+    amr: Vec<String>,
+    mut login_log: LoginLog,
+) -> HttpResponse {
+    let limited = match apply_unverified_login_policy(&mut user, verification_config) {
+        Ok(limited) => limited,
+        Err(response) => {
+            login_log.set_failure("Email not verified".to_string());
+            if let Err(e) = LogService::new(db.clone(), &user.tenant_id).save_login_log(&login_log).await {
+                eprintln!("Failed to save login log: {}", e);
+            }
+            return response;
+        }
+    };
+
+    issue_login_token(db, cookie_config, &user, org_id, limited, amr, login_log).await
+}
@@ -1,2 +1,16 @@
 pub mod auth_handlers;
-pub mod log_handlers;
\ No newline at end of file
+pub mod log_handlers;
+pub mod kong_handlers;
+pub mod org_handlers;
+pub mod group_handlers;
+pub mod impersonation_handlers;
+pub mod admin_user_handlers;
+pub mod profile_handlers;
+pub mod password_handlers;
+pub mod email_handlers;
+pub mod security_handlers;
+pub mod mfa_handlers;
+pub mod webauthn_handlers;
+pub mod magic_link_handlers;
+pub mod email_code_handlers;
+pub mod session_handlers;
\ No newline at end of file
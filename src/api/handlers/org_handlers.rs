@@ -0,0 +1,452 @@
+use actix_web::{web, HttpResponse, Result};
+use mongodb::Database;
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use crate::auth::{access_token_cookie, create_jwt_token};
+use crate::api::authenticated::Authenticated;
+use crate::api::handlers::auth_handlers::{apply_unverified_login_policy, login_token_options};
+use crate::config::{EmailVerificationConfig, SessionCookieConfig};
+use crate::models::{Membership, MembershipStatus, Organization, User};
+use crate::models::organization::validate_org_roles;
+use crate::services::{MailService, MailTemplate, OrganizationService, SessionService, UserService};
+
+#[derive(Deserialize)]
+pub struct CreateOrganizationRequest {
+    name: String,
+}
+
+#[derive(Deserialize)]
+pub struct InviteMemberRequest {
+    email: String,
+    roles: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateMemberRolesRequest {
+    roles: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct OrganizationResponse {
+    id: String,
+    name: String,
+    roles: Vec<String>,
+    status: MembershipStatus,
+}
+
+#[derive(Serialize)]
+pub struct MemberResponse {
+    user_id: String,
+    roles: Vec<String>,
+    status: MembershipStatus,
+}
+
+#[derive(Serialize)]
+pub struct OrgTokenResponse {
+    // Left out when the token is set as a cookie instead
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+    org_id: String,
+    org_roles: Vec<String>,
+}
+
+impl From<Membership> for MemberResponse {
+    fn from(membership: Membership) -> Self {
+        Self {
+            user_id: membership.user_id,
+            roles: membership.roles,
+            status: membership.status,
+        }
+    }
+}
+
+// Resolves the caller's membership in the organization from the path, or the error response to return
+async fn caller_membership(
+    org_service: &OrganizationService,
+    user: &User,
+    org_id: &str,
+) -> std::result::Result<(ObjectId, Membership), HttpResponse> {
+    let org_id = ObjectId::from_str(org_id)
+        .map_err(|_| HttpResponse::BadRequest().json("Invalid organization ID"))?;
+    let user_id = user._id.unwrap().to_hex();
+
+    match org_service.find_membership(&org_id, &user_id).await {
+        Ok(Some(membership)) => Ok((org_id, membership)),
+        Ok(None) => Err(HttpResponse::NotFound().json("Organization not found")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
+
+pub async fn create_organization(
+    auth: Authenticated,
+    db: web::Data<Database>,
+    body: web::Json<CreateOrganizationRequest>,
+) -> Result<HttpResponse> {
+    let user = auth.user;
+
+    let name = body.name.trim();
+    if name.is_empty() {
+        return Ok(HttpResponse::BadRequest().json("Organization name is required"));
+    }
+
+    let org_service = OrganizationService::new(db.get_ref().clone(), &user.tenant_id);
+    let org = Organization::new(user.tenant_id.clone(), name.to_string(), user._id.unwrap().to_hex());
+
+    match org_service.create_organization(&org).await {
+        Ok(org_id) => Ok(HttpResponse::Created().json(OrganizationResponse {
+            id: org_id.to_hex(),
+            name: org.name,
+            roles: vec!["owner".to_string()],
+            status: MembershipStatus::Active,
+        })),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Ok(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
+
+pub async fn list_my_organizations(
+    auth: Authenticated,
+    db: web::Data<Database>,
+) -> Result<HttpResponse> {
+    let user = auth.user;
+
+    let org_service = OrganizationService::new(db.get_ref().clone(), &user.tenant_id);
+
+    match org_service.list_for_user(&user._id.unwrap().to_hex()).await {
+        Ok(orgs) => {
+            let response: Vec<OrganizationResponse> = orgs.into_iter()
+                .map(|(org, membership)| OrganizationResponse {
+                    id: org._id.map(|id| id.to_hex()).unwrap_or_default(),
+                    name: org.name,
+                    roles: membership.roles,
+                    status: membership.status,
+                })
+                .collect();
+            Ok(HttpResponse::Ok().json(response))
+        }
+        Err(e) => {
+            eprintln!("Error fetching organizations: {}", e);
+            Ok(HttpResponse::InternalServerError().json("Failed to fetch organizations"))
+        }
+    }
+}
+
+pub async fn list_members(
+    auth: Authenticated,
+    db: web::Data<Database>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let user = auth.user;
+
+    let org_service = OrganizationService::new(db.get_ref().clone(), &user.tenant_id);
+    let (org_id, membership) = match caller_membership(&org_service, &user, &path).await {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    if !membership.is_active() {
+        return Ok(HttpResponse::Forbidden().json("Organization membership required"));
+    }
+
+    match org_service.list_members(&org_id).await {
+        Ok(members) => {
+            let response: Vec<MemberResponse> = members.into_iter().map(MemberResponse::from).collect();
+            Ok(HttpResponse::Ok().json(response))
+        }
+        Err(e) => {
+            eprintln!("Error fetching members: {}", e);
+            Ok(HttpResponse::InternalServerError().json("Failed to fetch members"))
+        }
+    }
+}
+
+pub async fn invite_member(
+    auth: Authenticated,
+    db: web::Data<Database>,
+    mail: web::Data<MailService>,
+    path: web::Path<String>,
+    body: web::Json<InviteMemberRequest>,
+) -> Result<HttpResponse> {
+    let user = auth.user;
+
+    let org_service = OrganizationService::new(db.get_ref().clone(), &user.tenant_id);
+    let (org_id, membership) = match caller_membership(&org_service, &user, &path).await {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    if !membership.can_manage_members() {
+        return Ok(HttpResponse::Forbidden().json("Organization admin access required"));
+    }
+
+    if let Err(e) = validate_org_roles(&body.roles) {
+        return Ok(HttpResponse::BadRequest().json(e));
+    }
+
+    // Only owners can hand out ownership
+    if body.roles.iter().any(|r| r == "owner") && !membership.is_owner() {
+        return Ok(HttpResponse::Forbidden().json("Only owners can grant the owner role"));
+    }
+
+    let user_service = UserService::new(db.get_ref().clone(), &user.tenant_id);
+    let invitee = match user_service.find_by_email(&body.email).await {
+        Ok(Some(invitee)) => invitee,
+        Ok(None) => return Ok(HttpResponse::NotFound().json("User not found")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+
+    let invitation = Membership::new(
+        user.tenant_id.clone(),
+        org_id,
+        invitee._id.unwrap().to_hex(),
+        body.roles.clone(),
+        MembershipStatus::Invited,
+        Some(user._id.unwrap().to_hex()),
+    );
+
+    match org_service.add_membership(&invitation).await {
+        Ok(_) => {
+            match org_service.find_by_id(&org_id).await {
+                Ok(Some(org)) => mail.send_template(&invitee.email, invitee.locale.as_deref(), MailTemplate::OrganizationInvitation {
+                    link: &mail.url(&format!("/orgs/{}", org_id.to_hex())),
+                    org_name: &org.name,
+                    inviter: &user.email,
+                }),
+                Ok(None) => {}
+                Err(e) => eprintln!("Failed to load organization for invitation email: {}", e),
+            }
+
+            Ok(HttpResponse::Created().json(MemberResponse::from(invitation)))
+        }
+        Err(e) => {
+            eprintln!("Error inviting member: {}", e);
+            Ok(HttpResponse::Conflict().json("User is already a member of this organization"))
+        }
+    }
+}
+
+pub async fn accept_invitation(
+    auth: Authenticated,
+    db: web::Data<Database>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let user = auth.user;
+
+    let org_service = OrganizationService::new(db.get_ref().clone(), &user.tenant_id);
+    let (org_id, _) = match caller_membership(&org_service, &user, &path).await {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    match org_service.accept_invitation(&org_id, &user._id.unwrap().to_hex()).await {
+        Ok(true) => Ok(HttpResponse::Ok().json("Invitation accepted")),
+        Ok(false) => Ok(HttpResponse::Conflict().json("No pending invitation")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Ok(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
+
+pub async fn update_member_roles(
+    auth: Authenticated,
+    db: web::Data<Database>,
+    path: web::Path<(String, String)>,
+    body: web::Json<UpdateMemberRolesRequest>,
+) -> Result<HttpResponse> {
+    let user = auth.user;
+
+    let (org_id, member_id) = path.into_inner();
+    let org_service = OrganizationService::new(db.get_ref().clone(), &user.tenant_id);
+    let (org_id, membership) = match caller_membership(&org_service, &user, &org_id).await {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    if !membership.can_manage_members() {
+        return Ok(HttpResponse::Forbidden().json("Organization admin access required"));
+    }
+
+    if let Err(e) = validate_org_roles(&body.roles) {
+        return Ok(HttpResponse::BadRequest().json(e));
+    }
+
+    let target = match org_service.find_membership(&org_id, &member_id).await {
+        Ok(Some(target)) => target,
+        Ok(None) => return Ok(HttpResponse::NotFound().json("Member not found")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+
+    let grants_owner = body.roles.iter().any(|r| r == "owner");
+    if (grants_owner || target.is_owner()) && !membership.is_owner() {
+        return Ok(HttpResponse::Forbidden().json("Only owners can change ownership"));
+    }
+
+    // Keep at least one owner in the organization
+    if target.is_owner() && target.is_active() && !grants_owner {
+        match org_service.count_owners(&org_id).await {
+            Ok(owners) if owners <= 1 => {
+                return Ok(HttpResponse::Conflict().json("Organization must keep at least one owner"));
+            }
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+            }
+        }
+    }
+
+    match org_service.set_member_roles(&org_id, &member_id, &body.roles).await {
+        Ok(_) => Ok(HttpResponse::Ok().json(MemberResponse {
+            user_id: member_id,
+            roles: body.roles.clone(),
+            status: target.status,
+        })),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Ok(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
+
+pub async fn remove_member(
+    auth: Authenticated,
+    db: web::Data<Database>,
+    path: web::Path<(String, String)>,
+) -> Result<HttpResponse> {
+    let user = auth.user;
+
+    let (org_id, member_id) = path.into_inner();
+    let org_service = OrganizationService::new(db.get_ref().clone(), &user.tenant_id);
+    let (org_id, membership) = match caller_membership(&org_service, &user, &org_id).await {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    // Members may leave on their own; removing anyone else takes an org admin
+    let is_self = member_id == membership.user_id;
+    if !is_self && !membership.can_manage_members() {
+        return Ok(HttpResponse::Forbidden().json("Organization admin access required"));
+    }
+
+    let target = match org_service.find_membership(&org_id, &member_id).await {
+        Ok(Some(target)) => target,
+        Ok(None) => return Ok(HttpResponse::NotFound().json("Member not found")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+
+    if target.is_owner() && target.is_active() {
+        if !is_self && !membership.is_owner() {
+            return Ok(HttpResponse::Forbidden().json("Only owners can remove an owner"));
+        }
+
+        match org_service.count_owners(&org_id).await {
+            Ok(owners) if owners <= 1 => {
+                return Ok(HttpResponse::Conflict().json("Organization must keep at least one owner"));
+            }
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+            }
+        }
+    }
+
+    match org_service.remove_membership(&org_id, &member_id).await {
+        Ok(_) => Ok(HttpResponse::NoContent().finish()),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Ok(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
+
+// Issues a new access token with the organization as the active one
+pub async fn switch_organization(
+    auth: Authenticated,
+    db: web::Data<Database>,
+    verification_config: web::Data<EmailVerificationConfig>,
+    cookie_config: web::Data<SessionCookieConfig>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let claims = auth.claims;
+
+    // An impersonation keeps its actor and original expiry across organization switches
+    let act = claims.act.clone();
+    let amr = claims.amr.clone();
+    let sid = claims.sid.clone();
+    let remaining = chrono::Duration::seconds(claims.exp as i64 - chrono::Utc::now().timestamp());
+
+    // Build the token from the stored user so it carries up-to-date data
+    let mut user = auth.stored;
+
+    let org_service = OrganizationService::new(db.get_ref().clone(), &user.tenant_id);
+    let (org_id, membership) = match caller_membership(&org_service, &user, &path).await {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    if !membership.is_active() {
+        return Ok(HttpResponse::Forbidden().json("Organization membership required"));
+    }
+
+    // Same roles as a login would give, so a limited unverified login stays limited
+    let limited = match apply_unverified_login_policy(&mut user, &verification_config) {
+        Ok(limited) => limited,
+        Err(response) => return Ok(response),
+    };
+    let mut options = match login_token_options(&db, &user, Some(&org_id.to_hex()), limited, amr).await {
+        Ok(options) => options,
+        Err((_, response)) => return Ok(response),
+    };
+    options.lifetime = act.as_ref().map(|_| remaining);
+    options.act = act;
+    options.sid = sid.clone();
+
+    // Later refreshes of the session keep the organization
+    if let Some(sid) = &sid {
+        if let Err(e) = SessionService::new(db.get_ref().clone(), &user.tenant_id).set_org(sid, &org_id.to_hex()).await {
+            eprintln!("Failed to update session: {}", e);
+        }
+    }
+
+    match create_jwt_token(&user, &options) {
+        Ok(token) => {
+            // Impersonation tokens are never kept in the admin's cookies
+            let mut response = HttpResponse::Ok();
+            let cookie = options.act.is_none().then(|| access_token_cookie(&cookie_config, &token)).flatten();
+            let token = match cookie {
+                Some(cookie) => {
+                    response.cookie(cookie);
+                    None
+                }
+                None => Some(token),
+            };
+
+            Ok(response.json(OrgTokenResponse {
+                token,
+                org_id: org_id.to_hex(),
+                org_roles: membership.roles,
+            }))
+        }
+        Err(e) => {
+            eprintln!("Token generation error: {}", e);
+            Ok(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use mongodb::Database;
+use mongodb::bson::{doc, DateTime};
+use serde::Deserialize;
+use crate::auth::{resolve_tenant, HashingPool, PasswordContext, PasswordPolicy, PasswordPolicyError};
+use crate::config::TenantConfig;
+use crate::models::AuditLog;
+use crate::services::{AuditService, MailService, MailTemplate, SessionService, UserService};
+use crate::utils::request_info::get_client_ip;
+use crate::utils::secure_token::{generate_token, hash_token};
+
+const PASSWORD_RESET_TTL_MINUTES: i64 = 30;
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordRequest {
+    email: String,
+    tenant: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordRequest {
+    token: String,
+    new_password: String,
+    tenant: Option<String>,
+}
+
+// Always answers the same way so the endpoint cannot be used to probe for accounts
+pub async fn forgot_password(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    mail: web::Data<MailService>,
+    tenant_config: web::Data<TenantConfig>,
+    body: web::Json<ForgotPasswordRequest>,
+) -> Result<HttpResponse> {
+    let tenant_id = match resolve_tenant(&req, &tenant_config, body.tenant.as_deref()) {
+        Ok(tenant_id) => tenant_id,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(e)),
+    };
+
+    let user_service = UserService::new(db.get_ref().clone(), &tenant_id);
+    let accepted = HttpResponse::Accepted().json("If the account exists, a reset link has been sent");
+
+    let user = match user_service.find_by_email(&body.email).await {
+        Ok(Some(user)) if user.is_active => user,
+        Ok(_) => return Ok(accepted),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+
+    // A new request replaces any previous token
+    let token = generate_token();
+    let expiry = DateTime::from_millis(
+        DateTime::now().timestamp_millis() + PASSWORD_RESET_TTL_MINUTES * 60 * 1000,
+    );
+
+    if let Err(e) = user_service.set_password_reset(&user._id.unwrap(), &hash_token(&token), expiry).await {
+        eprintln!("Database error: {}", e);
+        return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+    }
+
+    mail.send_template(&user.email, user.locale.as_deref(), MailTemplate::PasswordReset {
+        link: &mail.link("/reset-password", &token),
+        ttl_minutes: PASSWORD_RESET_TTL_MINUTES,
+    });
+
+    Ok(accepted)
+}
+
+pub async fn reset_password(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    mail: web::Data<MailService>,
+    tenant_config: web::Data<TenantConfig>,
+    password_policy: web::Data<PasswordPolicy>,
+    password_hasher: web::Data<HashingPool>,
+    body: web::Json<ResetPasswordRequest>,
+) -> Result<HttpResponse> {
+    let tenant_id = match resolve_tenant(&req, &tenant_config, body.tenant.as_deref()) {
+        Ok(tenant_id) => tenant_id,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(e)),
+    };
+
+    let user_service = UserService::new(db.get_ref().clone(), &tenant_id);
+    let token_hash = hash_token(&body.token);
+
+    // Inactive accounts get the same answer as an unknown token, as in forgot_password
+    let user = match user_service.find_by_reset_token(&token_hash).await {
+        Ok(Some(user)) if user.is_active => user,
+        Ok(_) => return Ok(HttpResponse::BadRequest().json("Invalid or expired token")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+
+    let password_context = PasswordContext { email: &user.email, name: user.name.as_deref() };
+    if let Err(violations) = password_policy.check(&body.new_password, &password_context) {
+        return Ok(HttpResponse::BadRequest().json(PasswordPolicyError::from(violations)));
+    }
+
+    let hashed_password = match password_hasher.hash(&body.new_password).await {
+        Ok(h) => h,
+        Err(e) => {
+            eprintln!("Password hashing error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+
+    let user_id = user._id.unwrap();
+    match user_service.reset_password(&user_id, &token_hash, &hashed_password).await {
+        Ok(true) => {}
+        Ok(false) => return Ok(HttpResponse::BadRequest().json("Invalid or expired token")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    }
+
+    let session_service = SessionService::new(db.get_ref().clone(), &tenant_id);
+    if let Err(e) = session_service.revoke_all(&user_id.to_hex(), None, "password_reset").await {
+        eprintln!("Failed to revoke sessions: {}", e);
+    }
+
+    let audit_service = AuditService::new(db.get_ref().clone(), &tenant_id);
+    audit_service.record(&AuditLog::new(
+        &tenant_id,
+        &user,
+        "user.password_reset",
+        "user",
+        &user_id.to_hex(),
+        Some(doc! { "ip_address": get_client_ip(&req) }),
+    )).await;
+
+    mail.send_template(&user.email, user.locale.as_deref(), MailTemplate::SecurityAlert { event: "password_changed" });
+
+    Ok(HttpResponse::Ok().json("Password has been reset"))
+}
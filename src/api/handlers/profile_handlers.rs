@@ -0,0 +1,189 @@
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use mongodb::Database;
+use mongodb::bson::{doc, DateTime};
+use serde::{Deserialize, Serialize};
+use crate::auth::{access_token_cookie, create_jwt_token, HashingPool, PasswordContext, PasswordPolicy, PasswordPolicyError, TokenOptions};
+use crate::config::SessionCookieConfig;
+use crate::models::{AuditLog, ProfileUpdate, User};
+use crate::api::authenticated::Authenticated;
+use crate::api::handlers::auth_handlers::{account_locked, register_login_failure};
+use crate::services::{AuditService, GroupService, MailService, MailTemplate, SessionService};
+use crate::utils::request_info::get_client_ip;
+
+#[derive(Serialize)]
+pub struct ProfileResponse {
+    id: String,
+    email: String,
+    name: Option<String>,
+    avatar_url: Option<String>,
+    locale: Option<String>,
+    timezone: Option<String>,
+    roles: Vec<String>,
+    email_verified: bool,
+    mfa_enabled: bool,
+    recovery_codes_remaining: usize,
+    created_at: Option<String>,
+    updated_at: Option<String>,
+}
+
+impl From<&User> for ProfileResponse {
+    fn from(user: &User) -> Self {
+        Self {
+            id: user._id.map(|id| id.to_hex()).unwrap_or_default(),
+            email: user.email.clone(),
+            name: user.name.clone(),
+            avatar_url: user.avatar_url.clone(),
+            locale: user.locale.clone(),
+            timezone: user.timezone.clone(),
+            roles: user.roles.clone().unwrap_or_default(),
+            email_verified: user.email_verified,
+            mfa_enabled: user.mfa_enabled,
+            recovery_codes_remaining: user.recovery_codes.len(),
+            created_at: user.created_at.and_then(|dt| dt.try_to_rfc3339_string().ok()),
+            updated_at: user.updated_at.and_then(|dt| dt.try_to_rfc3339_string().ok()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ChangePasswordRequest {
+    current_password: String,
+    new_password: String,
+}
+
+#[derive(Serialize)]
+pub struct ChangePasswordResponse {
+    // Left out when the token is set as a cookie instead
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+}
+
+pub async fn get_me(auth: Authenticated) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(ProfileResponse::from(&auth.stored)))
+}
+
+pub async fn update_me(
+    auth: Authenticated,
+    db: web::Data<Database>,
+    body: web::Json<ProfileUpdate>,
+) -> Result<HttpResponse> {
+    let (user, user_service, _) = auth.stored_user(&db);
+
+    if let Err(e) = body.validate() {
+        return Ok(HttpResponse::BadRequest().json(e));
+    }
+
+    match user_service.update_profile(&user._id.unwrap(), &body).await {
+        Ok(Some(user)) => Ok(HttpResponse::Ok().json(ProfileResponse::from(&user))),
+        Ok(None) => Ok(HttpResponse::Unauthorized().json("Invalid token")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Ok(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
+
+// Changes the caller's password; every other token and refresh token stops working
+#[allow(clippy::too_many_arguments)]
+pub async fn change_password(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    mail: web::Data<MailService>,
+    password_policy: web::Data<PasswordPolicy>,
+    password_hasher: web::Data<HashingPool>,
+    cookie_config: web::Data<SessionCookieConfig>,
+    body: web::Json<ChangePasswordRequest>,
+) -> Result<HttpResponse> {
+    if auth.claims.is_impersonated() {
+        return Ok(HttpResponse::Forbidden().json("Not allowed while impersonating"));
+    }
+
+    let (user, user_service, claims) = auth.stored_user(&db);
+
+    // Wrong current passwords count toward the lockout, so a hijacked session can't guess it
+    if user.is_locked(DateTime::now()) {
+        return Ok(account_locked());
+    }
+    match password_hasher.verify(&body.current_password, &user.password).await {
+        Ok(true) => {}
+        Ok(false) => {
+            if register_login_failure(&db, &mail, &user_service, &user).await {
+                return Ok(account_locked());
+            }
+            return Ok(HttpResponse::Unauthorized().json("Current password is incorrect"));
+        }
+        Err(e) => {
+            eprintln!("Password verification error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    }
+    if body.new_password == body.current_password {
+        return Ok(HttpResponse::BadRequest().json("New password must be different from the current one"));
+    }
+    let password_context = PasswordContext { email: &user.email, name: user.name.as_deref() };
+    if let Err(violations) = password_policy.check(&body.new_password, &password_context) {
+        return Ok(HttpResponse::BadRequest().json(PasswordPolicyError::from(violations)));
+    }
+
+    let hashed_password = match password_hasher.hash(&body.new_password).await {
+        Ok(h) => h,
+        Err(e) => {
+            eprintln!("Password hashing error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+
+    let user_id = user._id.unwrap();
+    if let Err(e) = user_service.change_password(&user_id, &hashed_password).await {
+        eprintln!("Database error: {}", e);
+        return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+    }
+
+    let audit_service = AuditService::new(db.get_ref().clone(), &user.tenant_id);
+    audit_service.record(&AuditLog::new(
+        &user.tenant_id,
+        &user,
+        "user.password_changed",
+        "user",
+        &user_id.to_hex(),
+        Some(doc! { "ip_address": get_client_ip(&req) }),
+    )).await;
+
+    mail.send_template(&user.email, user.locale.as_deref(), MailTemplate::SecurityAlert { event: "password_changed" });
+
+    let session_service = SessionService::new(db.get_ref().clone(), &user.tenant_id);
+    if let Err(e) = session_service.revoke_all(&user_id.to_hex(), claims.sid.as_deref(), "password_changed").await {
+        eprintln!("Failed to revoke sessions: {}", e);
+    }
+
+    // Fresh token for this session, keeping the active organization
+    let group_service = GroupService::new(db.get_ref().clone(), &user.tenant_id);
+    let group_roles = match group_service.roles_for_user(&user_id.to_hex()).await {
+        Ok(roles) => roles,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+
+    let options = TokenOptions {
+        org_id: claims.org_id,
+        org_roles: claims.org_roles,
+        group_roles,
+        amr: claims.amr,
+        sid: claims.sid,
+        ..TokenOptions::default()
+    };
+
+    match create_jwt_token(&user, &options) {
+        Ok(token) => match access_token_cookie(&cookie_config, &token) {
+            Some(cookie) => Ok(HttpResponse::Ok().cookie(cookie).json(ChangePasswordResponse { token: None })),
+            None => Ok(HttpResponse::Ok().json(ChangePasswordResponse { token: Some(token) })),
+        },
+        Err(e) => {
+            eprintln!("Token generation error: {}", e);
+            Ok(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
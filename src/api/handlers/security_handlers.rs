@@ -0,0 +1,278 @@
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use mongodb::Database;
+use mongodb::bson::{doc, DateTime};
+use serde::{Deserialize, Serialize};
+use crate::api::authenticated::Authenticated;
+use crate::auth::{admin_scope, HashingPool, PasswordPolicy};
+use crate::config::TenantConfig;
+use crate::models::{AuditLog, LockoutPolicy, SessionPolicy, User};
+use crate::services::{AuditService, SettingsService};
+
+// Security settings are changed by a directly authenticated admin, one tenant at a time
+fn settings_admin(req: &HttpRequest, auth: Authenticated, tenant_config: &TenantConfig) -> std::result::Result<(User, String), HttpResponse> {
+    let admin = auth.direct_user()?;
+
+    if !admin.is_admin() {
+        return Err(HttpResponse::Forbidden().json("Admin access required"));
+    }
+
+    let scope = admin_scope(req, tenant_config, &admin)
+        .map_err(|e| HttpResponse::BadRequest().json(e))?;
+
+    match scope.tenant_id() {
+        Some(tenant_id) => {
+            let tenant_id = tenant_id.to_string();
+            Ok((admin, tenant_id))
+        }
+        None => Err(HttpResponse::BadRequest().json("Security settings are managed one tenant at a time")),
+    }
+}
+
+pub async fn get_lockout_policy(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
+) -> Result<HttpResponse> {
+    let (_, tenant_id) = match settings_admin(&req, auth, &tenant_config) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    match SettingsService::new(db.get_ref().clone(), &tenant_id).get().await {
+        Ok(settings) => Ok(HttpResponse::Ok().json(settings.lockout)),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Ok(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
+
+pub async fn update_lockout_policy(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
+    body: web::Json<LockoutPolicy>,
+) -> Result<HttpResponse> {
+    let (admin, tenant_id) = match settings_admin(&req, auth, &tenant_config) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    let policy = body.into_inner();
+    if let Err(e) = policy.validate() {
+        return Ok(HttpResponse::BadRequest().json(e));
+    }
+
+    let settings_service = SettingsService::new(db.get_ref().clone(), &tenant_id);
+    let mut settings = match settings_service.get().await {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+
+    let previous = settings.lockout.clone();
+    settings.lockout = policy;
+    settings.updated_at = DateTime::now();
+    settings.updated_by = admin._id.map(|id| id.to_hex());
+
+    if let Err(e) = settings_service.save(&settings).await {
+        eprintln!("Database error: {}", e);
+        return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+    }
+
+    let audit_service = AuditService::new(db.get_ref().clone(), &tenant_id);
+    audit_service.record(&AuditLog::new(
+        &tenant_id,
+        &admin,
+        "settings.lockout_updated",
+        "settings",
+        &tenant_id,
+        Some(doc! {
+            "previous": mongodb::bson::to_bson(&previous).ok(),
+            "current": mongodb::bson::to_bson(&settings.lockout).ok(),
+        }),
+    )).await;
+
+    Ok(HttpResponse::Ok().json(settings.lockout))
+}
+
+pub async fn get_session_policy(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
+) -> Result<HttpResponse> {
+    let (_, tenant_id) = match settings_admin(&req, auth, &tenant_config) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    match SettingsService::new(db.get_ref().clone(), &tenant_id).get().await {
+        Ok(settings) => Ok(HttpResponse::Ok().json(settings.sessions)),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Ok(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
+
+// Applies to logins made after the change; existing sessions keep the idle timeout they started with
+pub async fn update_session_policy(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
+    body: web::Json<SessionPolicy>,
+) -> Result<HttpResponse> {
+    let (admin, tenant_id) = match settings_admin(&req, auth, &tenant_config) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    let policy = body.into_inner();
+    if let Err(e) = policy.validate() {
+        return Ok(HttpResponse::BadRequest().json(e));
+    }
+
+    let settings_service = SettingsService::new(db.get_ref().clone(), &tenant_id);
+    let mut settings = match settings_service.get().await {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+
+    let previous = settings.sessions.clone();
+    settings.sessions = policy;
+    settings.updated_at = DateTime::now();
+    settings.updated_by = admin._id.map(|id| id.to_hex());
+
+    if let Err(e) = settings_service.save(&settings).await {
+        eprintln!("Database error: {}", e);
+        return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+    }
+
+    let audit_service = AuditService::new(db.get_ref().clone(), &tenant_id);
+    audit_service.record(&AuditLog::new(
+        &tenant_id,
+        &admin,
+        "settings.sessions_updated",
+        "settings",
+        &tenant_id,
+        Some(doc! {
+            "previous": mongodb::bson::to_bson(&previous).ok(),
+            "current": mongodb::bson::to_bson(&settings.sessions).ok(),
+        }),
+    )).await;
+
+    Ok(HttpResponse::Ok().json(settings.sessions))
+}
+
+#[derive(Deserialize)]
+pub struct BreachCheckRequest {
+    check_on_login: bool,
+}
+
+#[derive(Serialize)]
+pub struct BreachCheckResponse {
+    check_on_login: bool,
+    // Hashes in the loaded breach list; 0 means no list is configured
+    list_size: usize,
+}
+
+pub async fn get_breach_check(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
+    password_policy: web::Data<PasswordPolicy>,
+) -> Result<HttpResponse> {
+    let (_, tenant_id) = match settings_admin(&req, auth, &tenant_config) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    match SettingsService::new(db.get_ref().clone(), &tenant_id).get().await {
+        Ok(settings) => Ok(HttpResponse::Ok().json(BreachCheckResponse {
+            check_on_login: settings.check_breached_on_login,
+            list_size: password_policy.breached.len(),
+        })),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Ok(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
+
+// Turns on flagging of users whose current password is in the breach list, checked at their next login
+pub async fn update_breach_check(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
+    password_policy: web::Data<PasswordPolicy>,
+    body: web::Json<BreachCheckRequest>,
+) -> Result<HttpResponse> {
+    let (admin, tenant_id) = match settings_admin(&req, auth, &tenant_config) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    if body.check_on_login && password_policy.breached.is_empty() {
+        return Ok(HttpResponse::Conflict().json("No breached password list is loaded"));
+    }
+
+    let settings_service = SettingsService::new(db.get_ref().clone(), &tenant_id);
+    let mut settings = match settings_service.get().await {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+
+    settings.check_breached_on_login = body.check_on_login;
+    settings.updated_at = DateTime::now();
+    settings.updated_by = admin._id.map(|id| id.to_hex());
+
+    if let Err(e) = settings_service.save(&settings).await {
+        eprintln!("Database error: {}", e);
+        return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+    }
+
+    let audit_service = AuditService::new(db.get_ref().clone(), &tenant_id);
+    audit_service.record(&AuditLog::new(
+        &tenant_id,
+        &admin,
+        "settings.breach_check_updated",
+        "settings",
+        &tenant_id,
+        Some(doc! { "check_on_login": body.check_on_login }),
+    )).await;
+
+    Ok(HttpResponse::Ok().json(BreachCheckResponse {
+        check_on_login: settings.check_breached_on_login,
+        list_size: password_policy.breached.len(),
+    }))
+}
+
+// Load on the password hashing pool; the pool is shared by every tenant
+pub async fn get_hashing_stats(
+    auth: Authenticated,
+    password_hasher: web::Data<HashingPool>,
+) -> Result<HttpResponse> {
+    let admin = match auth.direct_user() {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
+    if !admin.is_super_admin() {
+        return Ok(HttpResponse::Forbidden().json("Super admin access required"));
+    }
+
+    Ok(HttpResponse::Ok().json(password_hasher.stats()))
+}
@@ -0,0 +1,338 @@
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use mongodb::Database;
+use mongodb::bson::{doc, oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use crate::api::handlers::auth_handlers::{apply_unverified_login_policy, login_token_options};
+use crate::api::authenticated::Authenticated;
+use crate::auth::{check_csrf, clear_session_cookies, create_jwt_token, create_refresh_token, session_cookies, verify_jwt_claims, verify_refresh_token_string, Claims};
+use crate::auth::session_cookies::REFRESH_TOKEN_COOKIE;
+use crate::config::{EmailVerificationConfig, SessionCookieConfig};
+use crate::models::{AuditLog, Session, SessionSummary, User};
+use crate::services::{AuditService, SessionService, UserService};
+use crate::utils::request_info::get_client_ip;
+use crate::utils::secure_token::hash_token;
+
+#[derive(Deserialize)]
+pub struct RefreshTokenRequest {
+    refresh_token: String,
+}
+
+#[derive(Serialize)]
+pub struct RefreshTokenResponse {
+    // Left out when the token is set as a cookie instead
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    refresh_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RevokeSessionsQuery {
+    // Keep the session the request is made from
+    #[serde(default)]
+    keep_current: bool,
+}
+
+#[derive(Serialize)]
+pub struct RevokedSessionsResponse {
+    revoked: u64,
+}
+
+// The caller from a direct (not impersonated) token, with their claims
+fn session_owner(auth: Authenticated) -> std::result::Result<(User, Claims), HttpResponse> {
+    if auth.claims.is_impersonated() {
+        return Err(HttpResponse::Forbidden().json("Not allowed while impersonating"));
+    }
+
+    Ok((auth.stored, auth.claims))
+}
+
+async fn record_session_audit(db: &Database, actor: &User, user: &User, action: &str, details: mongodb::bson::Document) {
+    let audit_service = AuditService::new(db.clone(), &user.tenant_id);
+    audit_service.record(&AuditLog::new(
+        &user.tenant_id,
+        actor,
+        action,
+        "user",
+        &user._id.unwrap().to_hex(),
+        Some(details),
+    )).await;
+}
+
+// The refresh token from the body, or else from the cookie in cookie mode, which needs a CSRF token
+fn presented_refresh_token(
+    req: &HttpRequest,
+    cookie_config: &SessionCookieConfig,
+    body: Option<web::Json<RefreshTokenRequest>>,
+) -> std::result::Result<Option<String>, HttpResponse> {
+    if let Some(body) = body {
+        return Ok(Some(body.into_inner().refresh_token));
+    }
+    if !cookie_config.enabled {
+        return Ok(None);
+    }
+    let Some(cookie) = req.cookie(REFRESH_TOKEN_COOKIE) else {
+        return Ok(None);
+    };
+    check_csrf(req).map_err(|e| HttpResponse::Forbidden().json(e))?;
+    Ok(Some(cookie.value().to_string()))
+}
+
+// Trades a refresh token for a new access token and a new refresh token. A refresh
+// token presented twice means it leaked, so its whole session is revoked
+pub async fn refresh_token(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    verification_config: web::Data<EmailVerificationConfig>,
+    cookie_config: web::Data<SessionCookieConfig>,
+    body: Option<web::Json<RefreshTokenRequest>>,
+) -> Result<HttpResponse> {
+    let invalid = || HttpResponse::Unauthorized().json("Invalid refresh token");
+    let presented = match presented_refresh_token(&req, &cookie_config, body) {
+        Ok(Some(token)) => token,
+        Ok(None) => return Ok(invalid()),
+        Err(response) => return Ok(response),
+    };
+    let Some(claims) = verify_refresh_token_string(&presented) else {
+        return Ok(invalid());
+    };
+
+    let user_service = UserService::new(db.get_ref().clone(), &claims.tenant_id);
+    let user_id = match ObjectId::from_str(&claims.sub) {
+        Ok(user_id) => user_id,
+        Err(_) => return Ok(invalid()),
+    };
+    let mut user = match user_service.find_by_id(&user_id).await {
+        Ok(Some(user)) if user.is_active => user,
+        Ok(_) => return Ok(invalid()),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+
+    let session_service = SessionService::new(db.get_ref().clone(), &claims.tenant_id);
+    let session = match session_service.find_active(&claims.sid).await {
+        Ok(Some(session)) if session.user_id == claims.sub => session,
+        Ok(_) => {
+            session_service.expire_idle_sessions(&user).await;
+            return Ok(invalid());
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+
+    // Roles, groups and memberships are read again, so changes apply at the next refresh
+    let limited = match apply_unverified_login_policy(&mut user, &verification_config) {
+        Ok(limited) => limited,
+        Err(response) => return Ok(response),
+    };
+    let mut token_options = match login_token_options(&db, &user, session.org_id.as_deref(), limited, session.amr.clone()).await {
+        Ok(options) => options,
+        Err((_, response)) => return Ok(response),
+    };
+    token_options.sid = Some(session.id.clone());
+
+    let (new_refresh_token, new_jti) = create_refresh_token(&user, &session.id, session.expires_at);
+    let idle_expires_at = session.idle_deadline(DateTime::now());
+    match session_service.rotate_refresh_token(&session.id, &hash_token(&claims.jti), &hash_token(&new_jti), get_client_ip(&req), idle_expires_at).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            if let Err(e) = session_service.revoke(&session.user_id, &session.id, "refresh_token_reused").await {
+                eprintln!("Failed to revoke session: {}", e);
+            }
+            record_session_audit(&db, &user, &user, "user.refresh_token_reused", doc! {
+                "session_id": &session.id,
+                "ip_address": get_client_ip(&req),
+            }).await;
+            return Ok(invalid());
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    }
+
+    match create_jwt_token(&user, &token_options) {
+        Ok(token) => {
+            let mut response = HttpResponse::Ok();
+            if cookie_config.enabled {
+                for cookie in session_cookies(&cookie_config, &token, &new_refresh_token, session.expires_at) {
+                    response.cookie(cookie);
+                }
+            }
+
+            Ok(response.json(RefreshTokenResponse {
+                token: (!cookie_config.access_token_cookie()).then_some(token),
+                refresh_token: (!cookie_config.enabled).then_some(new_refresh_token),
+            }))
+        }
+        Err(e) => {
+            eprintln!("Token generation error: {}", e);
+            Ok(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
+
+// Ends the caller's session, found from the access token or the refresh token, and
+// clears the session cookies
+pub async fn logout(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    cookie_config: web::Data<SessionCookieConfig>,
+    body: Option<web::Json<RefreshTokenRequest>>,
+) -> Result<HttpResponse> {
+    let session = match verify_jwt_claims(&req) {
+        Ok(claims) => claims.sid.map(|sid| (claims.tenant_id, claims.sub, sid)),
+        Err(_) => match presented_refresh_token(&req, &cookie_config, body) {
+            Ok(token) => token
+                .and_then(|token| verify_refresh_token_string(&token))
+                .map(|claims| (claims.tenant_id, claims.sub, claims.sid)),
+            Err(response) => return Ok(response),
+        },
+    };
+
+    if let Some((tenant_id, user_id, sid)) = session {
+        let session_service = SessionService::new(db.get_ref().clone(), &tenant_id);
+        if let Err(e) = session_service.revoke(&user_id, &sid, "logout").await {
+            eprintln!("Failed to revoke session: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    }
+
+    let mut response = HttpResponse::NoContent();
+    if cookie_config.enabled {
+        for cookie in clear_session_cookies(&cookie_config) {
+            response.cookie(cookie);
+        }
+    }
+    Ok(response.finish())
+}
+
+pub fn session_summaries(sessions: &[Session], current_session_id: Option<&str>) -> Vec<SessionSummary> {
+    sessions.iter().map(|session| SessionSummary::new(session, current_session_id)).collect()
+}
+
+pub async fn list_my_sessions(auth: Authenticated, db: web::Data<Database>) -> Result<HttpResponse> {
+    let (user, claims) = match session_owner(auth) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    let session_service = SessionService::new(db.get_ref().clone(), &user.tenant_id);
+    match session_service.list_active(&user._id.unwrap().to_hex()).await {
+        Ok(sessions) => Ok(HttpResponse::Ok().json(session_summaries(&sessions, claims.sid.as_deref()))),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Ok(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
+
+pub async fn revoke_my_session(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let (user, _) = match session_owner(auth) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    let session_service = SessionService::new(db.get_ref().clone(), &user.tenant_id);
+    match session_service.revoke(&user._id.unwrap().to_hex(), &path, "user").await {
+        Ok(true) => {
+            record_session_audit(&db, &user, &user, "user.session_revoked", doc! {
+                "session_id": path.as_str(),
+                "ip_address": get_client_ip(&req),
+            }).await;
+            Ok(HttpResponse::NoContent().finish())
+        }
+        Ok(false) => Ok(HttpResponse::NotFound().json("Session not found")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Ok(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
+
+pub async fn revoke_my_sessions(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    query: web::Query<RevokeSessionsQuery>,
+) -> Result<HttpResponse> {
+    let (user, claims) = match session_owner(auth) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    let keep = claims.sid.as_deref().filter(|_| query.keep_current);
+    let session_service = SessionService::new(db.get_ref().clone(), &user.tenant_id);
+    match session_service.revoke_all(&user._id.unwrap().to_hex(), keep, "user").await {
+        Ok(revoked) => {
+            record_session_audit(&db, &user, &user, "user.sessions_revoked", doc! {
+                "revoked": revoked as i64,
+                "ip_address": get_client_ip(&req),
+            }).await;
+            Ok(HttpResponse::Ok().json(RevokedSessionsResponse { revoked }))
+        }
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Ok(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
+
+// Admin side; the user is already loaded within the admin's scope
+pub async fn list_sessions_for(db: &Database, user: &User) -> HttpResponse {
+    let session_service = SessionService::new(db.clone(), &user.tenant_id);
+    match session_service.list_active(&user._id.unwrap().to_hex()).await {
+        Ok(sessions) => HttpResponse::Ok().json(session_summaries(&sessions, None)),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            HttpResponse::InternalServerError().json("Internal server error")
+        }
+    }
+}
+
+// Revokes one session of the user, or all of them when session_id is None
+pub async fn revoke_sessions_for(req: &HttpRequest, db: &Database, admin: &User, user: &User, session_id: Option<&str>) -> HttpResponse {
+    let session_service = SessionService::new(db.clone(), &user.tenant_id);
+    let user_id = user._id.unwrap().to_hex();
+    let ip_address = get_client_ip(req);
+
+    match session_id {
+        Some(session_id) => match session_service.revoke(&user_id, session_id, "admin").await {
+            Ok(true) => {
+                record_session_audit(db, admin, user, "user.session_revoked", doc! {
+                    "session_id": session_id,
+                    "ip_address": ip_address,
+                }).await;
+                HttpResponse::NoContent().finish()
+            }
+            Ok(false) => HttpResponse::NotFound().json("Session not found"),
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                HttpResponse::InternalServerError().json("Internal server error")
+            }
+        },
+        None => match session_service.revoke_all(&user_id, None, "admin").await {
+            Ok(revoked) => {
+                record_session_audit(db, admin, user, "user.sessions_revoked", doc! {
+                    "revoked": revoked as i64,
+                    "ip_address": ip_address,
+                }).await;
+                HttpResponse::Ok().json(RevokedSessionsResponse { revoked })
+            }
+            Err(e) => {
+                eprintln!("Database error: {}", e);
+                HttpResponse::InternalServerError().json("Internal server error")
+            }
+        },
+    }
+}
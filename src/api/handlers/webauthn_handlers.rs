@@ -0,0 +1,516 @@
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use mongodb::Database;
+use mongodb::bson::{doc, DateTime};
+use serde::{Deserialize, Serialize};
+use data_encoding::BASE64URL_NOPAD;
+use crate::api::handlers::auth_handlers::{account_locked, apply_unverified_login_policy, issue_login_token, register_login_failure};
+use crate::api::authenticated::Authenticated;
+use crate::api::handlers::mfa_handlers::{challenge_user, complete_mfa_login, direct_user, mfa_amr};
+use crate::auth::webauthn::{client_data_challenge, verify_assertion, verify_registration, AssertionCredential, CredentialDescriptor, RegistrationCredential, WebauthnError, COSE_ALG_ES256};
+use crate::auth::{resolve_tenant, verify_mfa_challenge_token};
+use crate::config::{EmailVerificationConfig, SessionCookieConfig, TenantConfig, WebauthnConfig};
+use crate::models::{AuditLog, LoginLog, User, WebauthnCredential, WebauthnCredentialSummary};
+use crate::services::{AuditService, LogService, MailService, MailTemplate, UserService, WebauthnService};
+use crate::utils::request_info::{get_client_ip, get_user_agent};
+
+const CEREMONY_TIMEOUT_MS: u64 = 5 * 60 * 1000;
+const MAX_PASSKEY_NAME_LENGTH: usize = 64;
+
+// PublicKeyCredentialCreationOptions, in the JSON form accepted by
+// PublicKeyCredential.parseCreationOptionsFromJSON()
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationOptions {
+    challenge: String,
+    rp: RelyingParty,
+    user: UserEntity,
+    pub_key_cred_params: Vec<CredentialParameter>,
+    timeout: u64,
+    attestation: &'static str,
+    exclude_credentials: Vec<CredentialDescriptor>,
+    authenticator_selection: AuthenticatorSelection,
+}
+
+#[derive(Serialize)]
+pub struct RelyingParty {
+    id: String,
+    name: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserEntity {
+    id: String,
+    name: String,
+    display_name: String,
+}
+
+#[derive(Serialize)]
+pub struct CredentialParameter {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    alg: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelection {
+    resident_key: &'static str,
+    user_verification: &'static str,
+}
+
+// PublicKeyCredentialRequestOptions
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionOptions {
+    challenge: String,
+    rp_id: String,
+    timeout: u64,
+    user_verification: &'static str,
+    allow_credentials: Vec<CredentialDescriptor>,
+}
+
+#[derive(Deserialize)]
+pub struct PasskeyRegistrationRequest {
+    name: Option<String>,
+    credential: RegistrationCredential,
+}
+
+#[derive(Deserialize)]
+pub struct PasskeyLoginBeginRequest {
+    tenant: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PasskeyLoginRequest {
+    tenant: Option<String>,
+    org_id: Option<String>,
+    credential: AssertionCredential,
+}
+
+#[derive(Deserialize)]
+pub struct MfaPasskeyBeginRequest {
+    challenge_token: String,
+}
+
+#[derive(Deserialize)]
+pub struct MfaPasskeyRequest {
+    challenge_token: String,
+    credential: AssertionCredential,
+}
+
+fn assertion_options(config: &WebauthnConfig, challenge: String, user_verification: &'static str, credentials: &[WebauthnCredential]) -> AssertionOptions {
+    AssertionOptions {
+        challenge,
+        rp_id: config.rp_id.clone(),
+        timeout: CEREMONY_TIMEOUT_MS,
+        user_verification,
+        allow_credentials: credentials.iter()
+            .map(|c| CredentialDescriptor::new(&c.credential_id, &c.transports))
+            .collect(),
+    }
+}
+
+// Looks up the pending ceremony a response answers and removes it
+async fn take_ceremony(
+    webauthn_service: &WebauthnService,
+    client_data_json: &str,
+    purpose: &str,
+    user_id: Option<&str>,
+) -> std::result::Result<String, HttpResponse> {
+    let invalid = || HttpResponse::Unauthorized().json("Invalid or expired challenge");
+    let challenge = client_data_challenge(client_data_json).map_err(|_| invalid())?;
+
+    match webauthn_service.take_challenge(&challenge, purpose).await {
+        Ok(Some(stored)) if user_id.is_none() || stored.user_id.as_deref() == user_id => Ok(stored.challenge),
+        Ok(_) => Err(invalid()),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
+
+async fn save_log(log_service: &LogService, login_log: &LoginLog) {
+    if let Err(e) = log_service.save_login_log(login_log).await {
+        eprintln!("Failed to save login log: {}", e);
+    }
+}
+
+// Checks an assertion against the user's stored passkey and advances its counter.
+// Ok tells whether the authenticator verified the user; Err carries the failure
+// reason for the login log
+async fn check_passkey(
+    config: &WebauthnConfig,
+    db: &Database,
+    user_service: &UserService,
+    user: &User,
+    challenge: &str,
+    credential: &AssertionCredential,
+    require_user_verification: bool,
+) -> std::result::Result<bool, String> {
+    let stored = user.webauthn_credential(&credential.id).ok_or("Unknown passkey")?;
+
+    let verified = match verify_assertion(config, challenge, credential, &stored.public_key, stored.sign_count, require_user_verification) {
+        Ok(verified) => verified,
+        Err(WebauthnError::CounterRegression) => {
+            let audit_service = AuditService::new(db.clone(), &user.tenant_id);
+            audit_service.record(&AuditLog::new(
+                &user.tenant_id,
+                user,
+                "user.passkey_clone_suspected",
+                "user",
+                &user._id.unwrap().to_hex(),
+                Some(doc! { "credential_id": &stored.credential_id }),
+            )).await;
+            return Err(WebauthnError::CounterRegression.to_string());
+        }
+        Err(e) => return Err(e.to_string()),
+    };
+
+    match user_service.record_webauthn_use(&user._id.unwrap(), &stored.credential_id, stored.sign_count, verified.sign_count).await {
+        Ok(true) => Ok(verified.user_verified),
+        Ok(false) => Err("Passkey was used concurrently".to_string()),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Err("Database error".to_string())
+        }
+    }
+}
+
+pub async fn begin_passkey_registration(
+    auth: Authenticated,
+    db: web::Data<Database>,
+    config: web::Data<WebauthnConfig>,
+) -> Result<HttpResponse> {
+    let (user, _) = match direct_user(auth, &db) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    let user_id = user._id.unwrap();
+    let webauthn_service = WebauthnService::new(db.get_ref().clone(), &user.tenant_id);
+    let challenge = match webauthn_service.create_challenge("register", Some(user_id.to_hex())).await {
+        Ok(challenge) => challenge,
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+
+    Ok(HttpResponse::Ok().json(RegistrationOptions {
+        challenge: challenge.challenge,
+        rp: RelyingParty { id: config.rp_id.clone(), name: config.rp_name.clone() },
+        user: UserEntity {
+            id: BASE64URL_NOPAD.encode(&user_id.bytes()),
+            name: user.email.clone(),
+            display_name: user.display_name(),
+        },
+        pub_key_cred_params: vec![CredentialParameter { kind: "public-key", alg: COSE_ALG_ES256 }],
+        timeout: CEREMONY_TIMEOUT_MS,
+        attestation: "none",
+        exclude_credentials: user.webauthn_credentials.iter()
+            .map(|c| CredentialDescriptor::new(&c.credential_id, &c.transports))
+            .collect(),
+        // Discoverable credentials make passwordless login possible
+        authenticator_selection: AuthenticatorSelection { resident_key: "preferred", user_verification: "preferred" },
+    }))
+}
+
+pub async fn finish_passkey_registration(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    config: web::Data<WebauthnConfig>,
+    mail: web::Data<MailService>,
+    body: web::Json<PasskeyRegistrationRequest>,
+) -> Result<HttpResponse> {
+    let (user, user_service) = match direct_user(auth, &db) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+    let user_id = user._id.unwrap();
+
+    let name = body.name.as_deref().map(str::trim).filter(|name| !name.is_empty());
+    if name.is_some_and(|name| name.chars().count() > MAX_PASSKEY_NAME_LENGTH || name.chars().any(char::is_control)) {
+        return Ok(HttpResponse::BadRequest().json(format!("Passkey name must be at most {} characters", MAX_PASSKEY_NAME_LENGTH)));
+    }
+
+    let webauthn_service = WebauthnService::new(db.get_ref().clone(), &user.tenant_id);
+    let challenge = match take_ceremony(&webauthn_service, &body.credential.response.client_data_json, "register", Some(&user_id.to_hex())).await {
+        Ok(challenge) => challenge,
+        Err(response) => return Ok(response),
+    };
+
+    let verified = match verify_registration(&config, &challenge, &body.credential, false) {
+        Ok(verified) => verified,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(e.to_string())),
+    };
+
+    match user_service.find_by_webauthn_credential(&verified.credential_id).await {
+        Ok(None) => {}
+        Ok(Some(_)) => return Ok(HttpResponse::Conflict().json("Passkey already registered")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    }
+
+    let credential = WebauthnCredential {
+        credential_id: verified.credential_id.clone(),
+        public_key: verified.public_key.clone(),
+        sign_count: verified.sign_count,
+        transports: body.credential.response.transports.clone(),
+        name: name.map(str::to_string),
+        created_at: DateTime::now(),
+        last_used_at: None,
+    };
+
+    match user_service.add_webauthn_credential(&user_id, &credential).await {
+        Ok(true) => {}
+        Ok(false) => return Ok(HttpResponse::Conflict().json("Passkey already registered")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    }
+
+    let audit_service = AuditService::new(db.get_ref().clone(), &user.tenant_id);
+    audit_service.record(&AuditLog::new(
+        &user.tenant_id,
+        &user,
+        "user.passkey_added",
+        "user",
+        &user_id.to_hex(),
+        Some(doc! {
+            "credential_id": &credential.credential_id,
+            "user_verified": verified.user_verified,
+            "ip_address": get_client_ip(&req),
+        }),
+    )).await;
+    mail.send_template(&user.email, user.locale.as_deref(), MailTemplate::SecurityAlert { event: "passkey_added" });
+
+    Ok(HttpResponse::Created().json(WebauthnCredentialSummary::from(&credential)))
+}
+
+pub async fn list_passkeys(auth: Authenticated, db: web::Data<Database>) -> Result<HttpResponse> {
+    match direct_user(auth, &db) {
+        Ok((user, _)) => Ok(HttpResponse::Ok().json(
+            user.webauthn_credentials.iter().map(WebauthnCredentialSummary::from).collect::<Vec<_>>()
+        )),
+        Err(response) => Ok(response),
+    }
+}
+
+pub async fn delete_passkey(
+    req: HttpRequest,
+    auth: Authenticated,
+    db: web::Data<Database>,
+    mail: web::Data<MailService>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let (user, user_service) = match direct_user(auth, &db) {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+    let credential_id = path.into_inner();
+
+    match user_service.remove_webauthn_credential(&user._id.unwrap(), &credential_id).await {
+        Ok(true) => {}
+        Ok(false) => return Ok(HttpResponse::NotFound().json("Passkey not found")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    }
+
+    let audit_service = AuditService::new(db.get_ref().clone(), &user.tenant_id);
+    audit_service.record(&AuditLog::new(
+        &user.tenant_id,
+        &user,
+        "user.passkey_removed",
+        "user",
+        &user._id.unwrap().to_hex(),
+        Some(doc! { "credential_id": &credential_id, "ip_address": get_client_ip(&req) }),
+    )).await;
+    mail.send_template(&user.email, user.locale.as_deref(), MailTemplate::SecurityAlert { event: "passkey_removed" });
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Passwordless login, step 1: the browser picks one of the user's discoverable passkeys
+pub async fn begin_passkey_login(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
+    config: web::Data<WebauthnConfig>,
+    body: Option<web::Json<PasskeyLoginBeginRequest>>,
+) -> Result<HttpResponse> {
+    let tenant = body.as_ref().and_then(|b| b.tenant.as_deref());
+    let tenant_id = match resolve_tenant(&req, &tenant_config, tenant) {
+        Ok(tenant_id) => tenant_id,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(e)),
+    };
+
+    let webauthn_service = WebauthnService::new(db.get_ref().clone(), &tenant_id);
+    match webauthn_service.create_challenge("login", None).await {
+        Ok(challenge) => Ok(HttpResponse::Ok().json(assertion_options(&config, challenge.challenge, "required", &[]))),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Ok(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
+
+// Passwordless login, step 2: a user-verified passkey replaces both password and second factor
+pub async fn finish_passkey_login(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    tenant_config: web::Data<TenantConfig>,
+    config: web::Data<WebauthnConfig>,
+    verification_config: web::Data<EmailVerificationConfig>,
+    cookie_config: web::Data<SessionCookieConfig>,
+    body: web::Json<PasskeyLoginRequest>,
+) -> Result<HttpResponse> {
+    let tenant_id = match resolve_tenant(&req, &tenant_config, body.tenant.as_deref()) {
+        Ok(tenant_id) => tenant_id,
+        Err(e) => return Ok(HttpResponse::BadRequest().json(e)),
+    };
+
+    let webauthn_service = WebauthnService::new(db.get_ref().clone(), &tenant_id);
+    let challenge = match take_ceremony(&webauthn_service, &body.credential.response.client_data_json, "login", None).await {
+        Ok(challenge) => challenge,
+        Err(response) => return Ok(response),
+    };
+
+    let user_service = UserService::new(db.get_ref().clone(), &tenant_id);
+    let mut user = match user_service.find_by_webauthn_credential(&body.credential.id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return Ok(HttpResponse::Unauthorized().json("Invalid credentials")),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            return Ok(HttpResponse::InternalServerError().json("Internal server error"));
+        }
+    };
+
+    // A discoverable credential names its owner; it must be the user holding that credential
+    let user_handle = BASE64URL_NOPAD.encode(&user._id.unwrap().bytes());
+    if body.credential.response.user_handle.as_ref().is_some_and(|handle| *handle != user_handle) {
+        return Ok(HttpResponse::Unauthorized().json("Invalid credentials"));
+    }
+
+    let log_service = LogService::new(db.get_ref().clone(), &tenant_id);
+    let mut login_log = LoginLog::new(tenant_id.clone(), user.email.clone(), false, get_client_ip(&req), get_user_agent(&req));
+    login_log.login_method = "passkey".to_string();
+    login_log.request_path = req.path().to_string();
+
+    if user.is_locked(DateTime::now()) {
+        login_log.set_failure("Account locked".to_string());
+        save_log(&log_service, &login_log).await;
+        return Ok(account_locked());
+    }
+    if !user.is_active {
+        login_log.set_failure("Account inactive".to_string());
+        save_log(&log_service, &login_log).await;
+        return Ok(HttpResponse::Unauthorized().json("Invalid credentials"));
+    }
+
+    if let Err(reason) = check_passkey(&config, &db, &user_service, &user, &challenge, &body.credential, true).await {
+        login_log.set_failure(reason);
+        save_log(&log_service, &login_log).await;
+        return Ok(HttpResponse::Unauthorized().json("Invalid credentials"));
+    }
+
+    let limited = match apply_unverified_login_policy(&mut user, &verification_config) {
+        Ok(limited) => limited,
+        Err(response) => {
+            login_log.set_failure("Email not verified".to_string());
+            save_log(&log_service, &login_log).await;
+            return Ok(response);
+        }
+    };
+
+    // Possession of the key plus the authenticator's PIN or biometric
+    let amr = ["hwk", "user", "mfa"].iter().map(|m| m.to_string()).collect();
+    Ok(issue_login_token(&db, &cookie_config, &user, body.org_id.as_deref(), limited, amr, login_log).await)
+}
+
+// Passkey as second factor, step 1: options limited to the user's own passkeys
+pub async fn begin_mfa_passkey(
+    db: web::Data<Database>,
+    config: web::Data<WebauthnConfig>,
+    body: web::Json<MfaPasskeyBeginRequest>,
+) -> Result<HttpResponse> {
+    let Some(mfa_challenge) = verify_mfa_challenge_token(&body.challenge_token) else {
+        return Ok(HttpResponse::Unauthorized().json("Invalid or expired challenge"));
+    };
+    let (user, _) = match challenge_user(&db, &mfa_challenge).await {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+    if user.webauthn_credentials.is_empty() {
+        return Ok(HttpResponse::BadRequest().json("No passkeys registered"));
+    }
+
+    let webauthn_service = WebauthnService::new(db.get_ref().clone(), &user.tenant_id);
+    match webauthn_service.create_challenge("mfa", Some(mfa_challenge.sub.clone())).await {
+        Ok(challenge) => Ok(HttpResponse::Ok().json(assertion_options(&config, challenge.challenge, "preferred", &user.webauthn_credentials))),
+        Err(e) => {
+            eprintln!("Database error: {}", e);
+            Ok(HttpResponse::InternalServerError().json("Internal server error"))
+        }
+    }
+}
+
+// Passkey as second factor, step 2: completes a password login like a TOTP code would
+pub async fn finish_mfa_passkey(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    config: web::Data<WebauthnConfig>,
+    verification_config: web::Data<EmailVerificationConfig>,
+    cookie_config: web::Data<SessionCookieConfig>,
+    mail: web::Data<MailService>,
+    body: web::Json<MfaPasskeyRequest>,
+) -> Result<HttpResponse> {
+    let Some(mfa_challenge) = verify_mfa_challenge_token(&body.challenge_token) else {
+        return Ok(HttpResponse::Unauthorized().json("Invalid or expired challenge"));
+    };
+    let (user, user_service) = match challenge_user(&db, &mfa_challenge).await {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    let webauthn_service = WebauthnService::new(db.get_ref().clone(), &user.tenant_id);
+    let challenge = match take_ceremony(&webauthn_service, &body.credential.response.client_data_json, "mfa", Some(&mfa_challenge.sub)).await {
+        Ok(challenge) => challenge,
+        Err(response) => return Ok(response),
+    };
+
+    let log_service = LogService::new(db.get_ref().clone(), &user.tenant_id);
+    let mut login_log = LoginLog::new(user.tenant_id.clone(), user.email.clone(), false, get_client_ip(&req), get_user_agent(&req));
+    login_log.login_method = "webauthn".to_string();
+    login_log.request_path = req.path().to_string();
+
+    if user.is_locked(DateTime::now()) {
+        login_log.set_failure("Account locked".to_string());
+        save_log(&log_service, &login_log).await;
+        return Ok(account_locked());
+    }
+
+    let user_verified = match check_passkey(&config, &db, &user_service, &user, &challenge, &body.credential, false).await {
+        Ok(user_verified) => user_verified,
+        // A failed second factor counts towards the same lockout as a wrong password
+        Err(reason) => {
+            let locked = register_login_failure(&db, &mail, &user_service, &user).await;
+            login_log.set_failure(if locked { format!("{}, account locked", reason) } else { reason });
+            save_log(&log_service, &login_log).await;
+            if locked {
+                return Ok(account_locked());
+            }
+            return Ok(HttpResponse::Unauthorized().json("Invalid passkey"));
+        }
+    };
+
+    let amr = mfa_amr(&mfa_challenge, if user_verified { &["hwk", "user"] } else { &["hwk"] });
+    Ok(complete_mfa_login(&db, &verification_config, &cookie_config, user, mfa_challenge.org_id.as_deref(), amr, login_log).await)
+}
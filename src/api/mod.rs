@@ -1 +1,2 @@
-pub mod handlers;
\ No newline at end of file
+pub mod authenticated;
+pub mod handlers;
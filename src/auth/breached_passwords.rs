@@ -0,0 +1,253 @@
+use sha1::{Digest, Sha1};
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const SHA1_LEN: usize = 20;
+const FILTER_MAGIC: &[u8; 8] = b"KSBLOOM1";
+// A false positive rejects a password that was never breached; a breached one is never missed
+const FALSE_POSITIVE_RATE: f64 = 1e-6;
+
+// Bloom filter over the SHA-1 hashes of passwords known from breaches, checked without any
+// network call. It takes about 3.6 bytes per hash instead of the 20 of the hash itself.
+// Loaded from either
+// - a Have I Been Pwned style text dump: one `HEX_SHA1[:count]` per line, or
+// - a `.bin` file holding a filter written by `build` (see `build-breached-filter` in main)
+#[derive(Default)]
+pub struct BreachedPasswords {
+    bits: Vec<u64>,
+    num_hashes: u32,
+    len: u64,
+}
+
+impl fmt::Debug for BreachedPasswords {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BreachedPasswords")
+            .field("len", &self.len)
+            .field("bytes", &(self.bits.len() * 8))
+            .field("num_hashes", &self.num_hashes)
+            .finish()
+    }
+}
+
+impl BreachedPasswords {
+    // Empty filter sized for `capacity` hashes at FALSE_POSITIVE_RATE
+    fn with_capacity(capacity: u64) -> Self {
+        let n = capacity.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-n * FALSE_POSITIVE_RATE.ln() / (ln2 * ln2)).ceil() as u64;
+        let words = num_bits.div_ceil(64) as usize;
+        let num_hashes = ((words as f64 * 64.0 / n) * ln2).round().max(1.0) as u32;
+
+        Self { bits: vec![0; words], num_hashes, len: 0 }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        if path.extension().is_some_and(|ext| ext == "bin") {
+            return Self::read_filter(BufReader::new(fs::File::open(path)?));
+        }
+
+        // The dump is read twice so the filter can be sized before any hash is kept
+        let capacity = Self::read_text(BufReader::new(fs::File::open(path)?), |_| {})?;
+        let mut list = Self::with_capacity(capacity);
+        Self::read_text(BufReader::new(fs::File::open(path)?), |hash| list.insert(&hash))?;
+        Ok(list)
+    }
+
+    // Converts a text dump into the `.bin` filter; returns the number of hashes
+    pub fn build(dump: &Path, out: &Path) -> io::Result<u64> {
+        let list = Self::load(dump)?;
+        let mut writer = BufWriter::new(fs::File::create(out)?);
+        list.write_filter(&mut writer)?;
+        writer.flush()?;
+        Ok(list.len)
+    }
+
+    // Calls `found` with every hash of the dump and returns how many there were
+    fn read_text(reader: impl BufRead, mut found: impl FnMut([u8; SHA1_LEN])) -> io::Result<u64> {
+        let mut count = 0;
+
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            let hex_hash = line.split(':').next().unwrap_or_default().trim();
+            if hex_hash.is_empty() {
+                continue;
+            }
+
+            let mut hash = [0u8; SHA1_LEN];
+            hex::decode_to_slice(hex_hash, &mut hash).map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", number + 1, e))
+            })?;
+            found(hash);
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    // Layout: magic, number of hashes (u32), number of entries (u64), number of 64-bit
+    // words (u64), then the words; all little-endian
+    fn write_filter(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(FILTER_MAGIC)?;
+        writer.write_all(&self.num_hashes.to_le_bytes())?;
+        writer.write_all(&self.len.to_le_bytes())?;
+        writer.write_all(&(self.bits.len() as u64).to_le_bytes())?;
+        for word in &self.bits {
+            writer.write_all(&word.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn read_filter(mut reader: impl Read) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != FILTER_MAGIC {
+            return Err(invalid("not a breached password filter; build one with build-breached-filter"));
+        }
+
+        let mut word = [0u8; 8];
+        let mut num_hashes = [0u8; 4];
+        reader.read_exact(&mut num_hashes)?;
+        reader.read_exact(&mut word)?;
+        let len = u64::from_le_bytes(word);
+        reader.read_exact(&mut word)?;
+        let words = u64::from_le_bytes(word) as usize;
+
+        let num_hashes = u32::from_le_bytes(num_hashes);
+        if num_hashes == 0 || words == 0 {
+            return Err(invalid("breached password filter is empty"));
+        }
+
+        let mut bits = Vec::with_capacity(words);
+        for _ in 0..words {
+            reader.read_exact(&mut word)?;
+            bits.push(u64::from_le_bytes(word));
+        }
+        if reader.read(&mut word)? != 0 {
+            return Err(invalid("trailing data after breached password filter"));
+        }
+
+        Ok(Self { bits, num_hashes, len })
+    }
+
+    // SHA-1 output is already uniform, so two slices of it seed the double hashing
+    fn bit_positions(&self, hash: &[u8; SHA1_LEN]) -> impl Iterator<Item = usize> {
+        let h1 = u64::from_le_bytes(hash[0..8].try_into().unwrap());
+        let h2 = u64::from_le_bytes(hash[8..16].try_into().unwrap()) | 1;
+        let num_bits = self.bits.len() as u64 * 64;
+
+        (0..self.num_hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % num_bits) as usize)
+    }
+
+    fn insert(&mut self, hash: &[u8; SHA1_LEN]) {
+        for bit in self.bit_positions(hash) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+        self.len += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn contains(&self, password: &str) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+
+        let hash: [u8; SHA1_LEN] = Sha1::digest(password.as_bytes()).into();
+        self.bit_positions(&hash).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn sha1(password: &str) -> [u8; SHA1_LEN] {
+        Sha1::digest(password.as_bytes()).into()
+    }
+
+    fn filter_of(passwords: &[&str]) -> BreachedPasswords {
+        let mut list = BreachedPasswords::with_capacity(passwords.len() as u64);
+        for password in passwords {
+            list.insert(&sha1(password));
+        }
+        list
+    }
+
+    fn temp_path(extension: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("breached-{}.{}", uuid::Uuid::new_v4(), extension))
+    }
+
+    #[test]
+    fn test_text_dump() {
+        let dump = format!("{}:3861493\n\n{}:12\n", hex::encode_upper(sha1("password")), hex::encode_upper(sha1("hunter2")));
+        let path = temp_path("txt");
+        fs::write(&path, dump).unwrap();
+        let list = BreachedPasswords::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(list.len(), 2);
+        assert!(list.contains("password"));
+        assert!(list.contains("hunter2"));
+        assert!(!list.contains("correct horse battery staple"));
+
+        assert!(BreachedPasswords::read_text(Cursor::new("not-a-hash:1"), |_| {}).is_err());
+    }
+
+    #[test]
+    fn test_filter_file_round_trip() {
+        let dump = temp_path("txt");
+        let out = temp_path("bin");
+        fs::write(&dump, format!("{}\n{}\n", hex::encode(sha1("letmein")), hex::encode(sha1("qwerty")))).unwrap();
+
+        assert_eq!(BreachedPasswords::build(&dump, &out).unwrap(), 2);
+        let list = BreachedPasswords::load(&out).unwrap();
+        fs::remove_file(&dump).unwrap();
+        fs::remove_file(&out).unwrap();
+
+        assert_eq!(list.len(), 2);
+        assert!(list.contains("letmein"));
+        assert!(list.contains("qwerty"));
+        assert!(!list.contains("letmein2"));
+    }
+
+    #[test]
+    fn test_rejects_files_that_are_not_filters() {
+        let raw_hashes = [sha1("letmein"), sha1("qwerty")].concat();
+        assert!(BreachedPasswords::read_filter(Cursor::new(raw_hashes)).is_err());
+
+        let mut truncated = Vec::new();
+        filter_of(&["letmein"]).write_filter(&mut truncated).unwrap();
+        truncated.pop();
+        assert!(BreachedPasswords::read_filter(Cursor::new(truncated)).is_err());
+    }
+
+    #[test]
+    fn test_false_positives_are_rare() {
+        let breached: Vec<String> = (0..20_000).map(|i| format!("breached-{}", i)).collect();
+        let list = filter_of(&breached.iter().map(String::as_str).collect::<Vec<_>>());
+
+        assert!(breached.iter().all(|password| list.contains(password)));
+        // Far more bits than hashes: 1e-6 per lookup
+        assert!(list.bits.len() * 64 > breached.len() * 28);
+        let false_positives = (0..200_000).filter(|i| list.contains(&format!("fresh-{}", i))).count();
+        assert!(false_positives <= 2, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn test_empty_list_contains_nothing() {
+        let list = BreachedPasswords::default();
+        assert!(list.is_empty());
+        assert!(!list.contains("password"));
+    }
+}
@@ -0,0 +1,183 @@
+use serde::Serialize;
+use std::env;
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use crate::auth::password_hash::PasswordHasher;
+
+// Runs password hashing and verification on tokio's blocking threads, at most
+// `max_concurrency` at a time, so a burst of logins never ties up the actix workers
+pub struct HashingPool {
+    hasher: Arc<PasswordHasher>,
+    permits: Arc<Semaphore>,
+    max_concurrency: usize,
+    metrics: Arc<HashingMetrics>,
+}
+
+#[derive(Default)]
+struct HashingMetrics {
+    waiting: AtomicUsize,
+    running: AtomicUsize,
+    completed: AtomicU64,
+    queue_micros_total: AtomicU64,
+    queue_micros_max: AtomicU64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HashingStats {
+    pub max_concurrency: usize,
+    // Jobs waiting for a permit right now
+    pub waiting: usize,
+    pub running: usize,
+    pub completed: u64,
+    pub avg_queue_ms: f64,
+    pub max_queue_ms: f64,
+}
+
+// Decrements a gauge when dropped, including when the request is cancelled
+struct GaugeGuard<'a>(&'a AtomicUsize);
+
+impl<'a> GaugeGuard<'a> {
+    fn enter(gauge: &'a AtomicUsize) -> Self {
+        gauge.fetch_add(1, Ordering::Relaxed);
+        Self(gauge)
+    }
+}
+
+impl Drop for GaugeGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl HashingPool {
+    pub fn new(hasher: PasswordHasher, max_concurrency: usize) -> Self {
+        let max_concurrency = max_concurrency.max(1);
+        Self {
+            hasher: Arc::new(hasher),
+            permits: Arc::new(Semaphore::new(max_concurrency)),
+            max_concurrency,
+            metrics: Arc::default(),
+        }
+    }
+
+    // HASHING_MAX_CONCURRENCY defaults to the number of CPUs
+    pub fn from_env(hasher: PasswordHasher) -> Self {
+        let max_concurrency = env::var("HASHING_MAX_CONCURRENCY").ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_else(|| std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4));
+
+        Self::new(hasher, max_concurrency)
+    }
+
+    pub async fn hash(&self, password: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let password = password.to_string();
+        self.run(move |hasher| hasher.hash(&password)).await?
+    }
+
+    pub async fn verify(&self, password: &str, stored_hash: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let (password, stored_hash) = (password.to_string(), stored_hash.to_string());
+        self.run(move |hasher| hasher.verify(&password, &stored_hash)).await
+    }
+
+    // Only parses the hash, so it stays on the caller's thread
+    pub fn needs_rehash(&self, stored_hash: &str) -> bool {
+        self.hasher.needs_rehash(stored_hash)
+    }
+
+    async fn run<T, F>(&self, job: F) -> Result<T, Box<dyn Error + Send + Sync>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&PasswordHasher) -> T + Send + 'static,
+    {
+        let queued_at = Instant::now();
+        let permit = {
+            let _waiting = GaugeGuard::enter(&self.metrics.waiting);
+            self.permits.clone().acquire_owned().await?
+        };
+        self.metrics.record_queue_time(queued_at.elapsed());
+
+        let hasher = self.hasher.clone();
+        let metrics = self.metrics.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            // Keeps counting against the limit even if the request goes away
+            let _permit = permit;
+            let _running = GaugeGuard::enter(&metrics.running);
+            let output = job(&hasher);
+            metrics.completed.fetch_add(1, Ordering::Relaxed);
+            output
+        }).await?;
+
+        Ok(result)
+    }
+
+    pub fn stats(&self) -> HashingStats {
+        let completed = self.metrics.completed.load(Ordering::Relaxed);
+        let queue_micros_total = self.metrics.queue_micros_total.load(Ordering::Relaxed);
+
+        HashingStats {
+            max_concurrency: self.max_concurrency,
+            waiting: self.metrics.waiting.load(Ordering::Relaxed),
+            running: self.metrics.running.load(Ordering::Relaxed),
+            completed,
+            avg_queue_ms: if completed == 0 { 0.0 } else { queue_micros_total as f64 / completed as f64 / 1000.0 },
+            max_queue_ms: self.metrics.queue_micros_max.load(Ordering::Relaxed) as f64 / 1000.0,
+        }
+    }
+}
+
+impl HashingMetrics {
+    fn record_queue_time(&self, waited: Duration) {
+        let micros = waited.as_micros() as u64;
+        self.queue_micros_total.fetch_add(micros, Ordering::Relaxed);
+        self.queue_micros_max.fetch_max(micros, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fast_hasher() -> PasswordHasher {
+        PasswordHasher { argon2_memory_kib: 1024, argon2_iterations: 1, ..PasswordHasher::default() }
+    }
+
+    #[tokio::test]
+    async fn test_hash_and_verify() {
+        let pool = HashingPool::new(fast_hasher(), 2);
+        let hash = pool.hash("correct horse battery").await.unwrap();
+
+        assert!(pool.verify("correct horse battery", &hash).await.unwrap());
+        assert!(!pool.verify("wrong horse battery", &hash).await.unwrap());
+        assert_eq!(pool.stats().completed, 3);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_concurrency_limit() {
+        let pool = Arc::new(HashingPool::new(fast_hasher(), 2));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        let jobs: Vec<_> = (0..6).map(|_| {
+            let (pool, peak) = (pool.clone(), peak.clone());
+            tokio::spawn(async move {
+                let metrics = pool.metrics.clone();
+                pool.run(move |_| {
+                    peak.fetch_max(metrics.running.load(Ordering::Relaxed), Ordering::Relaxed);
+                    std::thread::sleep(Duration::from_millis(20));
+                }).await.unwrap();
+            })
+        }).collect();
+        for job in jobs {
+            job.await.unwrap();
+        }
+
+        let stats = pool.stats();
+        assert!(peak.load(Ordering::Relaxed) <= 2);
+        assert_eq!(stats.completed, 6);
+        assert_eq!((stats.waiting, stats.running), (0, 0));
+        // Four jobs had to wait behind the first two
+        assert!(stats.max_queue_ms >= 15.0);
+    }
+}
@@ -1,15 +1,126 @@
 use chrono::{Utc, Duration};
-use jsonwebtoken::{encode, decode, Header, Validation, EncodingKey, DecodingKey, Algorithm, TokenData};
+use jsonwebtoken::{encode, decode, decode_header, Header, Validation, EncodingKey, DecodingKey, Algorithm, TokenData};
 use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
 use uuid::Uuid;
+use crate::models::tenant::default_tenant_id;
 use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::sync::{OnceLock, RwLock};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,
     pub email: String,
     pub roles: Vec<String>,
     pub is_active: bool,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub iat: usize,
+    pub exp: usize,
+    pub jti: String,
+    pub aud: String,
+    pub iss: String,
+    #[serde(default = "default_tenant_id")]
+    pub tenant_id: String,
+    // Active organization and the roles held in it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub org_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub org_roles: Vec<String>,
+    // Set when an admin is acting as this user (RFC 8693 actor claim)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
+    // Kong jwt plugin credential key (see services::kong_service)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    // Authentication methods used to log in (RFC 8176), e.g. ["pwd", "otp", "mfa"]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub amr: Vec<String>,
+    // Session the token belongs to; absent for impersonation tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+}
+
+impl Claims {
+    pub fn new(
+        user_id: &str,
+        tenant_id: &str,
+        email: &str,
+        roles: Vec<String>,
+        is_active: bool,
+        aud: &str,
+        iss: &str,
+    ) -> Self {
+        let iat = Utc::now().timestamp() as usize;
+        let exp = (Utc::now() + Duration::hours(2)).timestamp() as usize;
+        let jti = Uuid::new_v4().to_string();
+
+        Self {
+            sub: user_id.to_string(),
+            email: email.to_string(),
+            roles,
+            is_active,
+            email_verified: false,
+            iat,
+            exp,
+            jti,
+            aud: aud.to_string(),
+            iss: iss.to_string(),
+            tenant_id: tenant_id.to_string(),
+            org_id: None,
+            org_roles: Vec::new(),
+            act: None,
+            key: None,
+            amr: Vec::new(),
+            sid: None,
+        }
+    }
+
+    pub fn is_impersonated(&self) -> bool {
+        self.act.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Actor {
+    pub sub: String,
+    pub email: String,
+}
+
+// Proof that the password step of a login succeeded; only accepted by the MFA endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MfaChallengeClaims {
+    pub sub: String,
+    pub tenant_id: String,
+    // Organization requested at login, activated once the second factor is checked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub org_id: Option<String>,
+    // Methods of the first factor, e.g. ["pwd"]; the second factor's are added to them
+    #[serde(default = "password_amr")]
+    pub amr: Vec<String>,
+    pub iat: usize,
+    pub exp: usize,
+    pub jti: String,
+    pub aud: String,
+    pub iss: String,
+}
+
+fn password_amr() -> Vec<String> {
+    vec!["pwd".to_string()]
+}
+
+const MFA_CHALLENGE_AUDIENCE: &str = "kong-security-mfa";
+const MFA_CHALLENGE_MINUTES: i64 = 5;
+
+// Login link sent by email; the jti makes it single-use
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MagicLinkClaims {
+    pub sub: String,
+    pub tenant_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub org_id: Option<String>,
     pub iat: usize,
     pub exp: usize,
     pub jti: String,
@@ -17,68 +128,196 @@ pub struct Claims {
     pub iss: String,
 }
 
+const MAGIC_LINK_AUDIENCE: &str = "kong-security-magic-link";
+
+// Trades for a new access token while its session is active; each one works once
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RefreshTokenClaims {
     pub sub: String,
+    pub tenant_id: String,
+    pub sid: String,
     pub jti: String,
     pub iat: usize,
     pub exp: usize,
+    pub aud: String,
+    pub iss: String,
 }
 
-fn get_private_key() -> Vec<u8> {
-    include_bytes!("../../private.pem").to_vec()
+const REFRESH_TOKEN_AUDIENCE: &str = "kong-security-refresh";
+
+#[derive(Debug, Clone)]
+pub struct SigningKey {
+    pub kid: String,
+    // Empty for a previous key loaded from config, which only verifies
+    pub private_pem: Vec<u8>,
+    pub public_pem: Vec<u8>,
 }
 
-fn get_public_key() -> Vec<u8> {
-    include_bytes!("../../public.pem").to_vec()
+impl SigningKey {
+    fn new(private_pem: Vec<u8>, public_pem: Vec<u8>) -> Self {
+        let digest = Sha256::digest(&public_pem);
+        Self {
+            kid: hex::encode(&digest[..8]),
+            private_pem,
+            public_pem,
+        }
+    }
+
+    fn verifying(public_pem: Vec<u8>) -> Self {
+        Self::new(Vec::new(), public_pem)
+    }
+
+    pub fn public_pem_string(&self) -> String {
+        String::from_utf8_lossy(&self.public_pem).to_string()
+    }
 }
 
-// Gera JWT com RS256
-pub fn generate_jwt(
-    user_id: &str,
-    email: &str,
-    roles: Vec<String>,
-    is_active: bool,
-    aud: &str,
-    iss: &str
-) -> String {
-    let iat = Utc::now().timestamp() as usize;
-    let exp = (Utc::now() + Duration::hours(2)).timestamp() as usize;
-    let jti = Uuid::new_v4().to_string();
-
-    let claims = Claims {
-        sub: user_id.to_string(),
-        email: email.to_string(),
-        roles,
-        is_active,
-        iat,
-        exp,
-        jti,
-        aud: aud.to_string(),
-        iss: iss.to_string(),
+struct SigningKeys {
+    current: SigningKey,
+    // Kept after a rotation so tokens signed with the old key stay valid until they expire
+    previous: Option<SigningKey>,
+}
+
+impl SigningKeys {
+    // Tokens without a kid predate key ids and were signed with the current key
+    fn for_kid(&self, kid: Option<&str>) -> Option<&SigningKey> {
+        match kid {
+            None => Some(&self.current),
+            Some(kid) if kid == self.current.kid => Some(&self.current),
+            Some(kid) => self.previous.as_ref().filter(|key| key.kid == kid),
+        }
+    }
+
+    fn decoding_key(&self, token: &str) -> Option<DecodingKey> {
+        let kid = decode_header(token).ok()?.kid;
+        DecodingKey::from_rsa_pem(&self.for_kid(kid.as_deref())?.public_pem).ok()
+    }
+
+    fn rotate(&mut self, new_key: SigningKey) -> Result<(), String> {
+        if new_key.kid == self.current.kid {
+            return Err("Signing key on disk is unchanged".to_string());
+        }
+
+        let old_key = std::mem::replace(&mut self.current, new_key);
+        self.previous = Some(old_key);
+        Ok(())
+    }
+}
+
+static SIGNING_KEYS: OnceLock<RwLock<SigningKeys>> = OnceLock::new();
+
+// Loads the key pair from JWT_PRIVATE_KEY_PATH / JWT_PUBLIC_KEY_PATH, falling back to the embedded pems
+fn load_signing_key() -> Result<SigningKey, String> {
+    match (env::var("JWT_PRIVATE_KEY_PATH"), env::var("JWT_PUBLIC_KEY_PATH")) {
+        (Ok(private_path), Ok(public_path)) => {
+            let private_pem = fs::read(&private_path)
+                .map_err(|e| format!("Failed to read {}: {}", private_path, e))?;
+            let public_pem = fs::read(&public_path)
+                .map_err(|e| format!("Failed to read {}: {}", public_path, e))?;
+            EncodingKey::from_rsa_pem(&private_pem).map_err(|e| format!("Invalid private key: {}", e))?;
+            DecodingKey::from_rsa_pem(&public_pem).map_err(|e| format!("Invalid public key: {}", e))?;
+            Ok(SigningKey::new(private_pem, public_pem))
+        }
+        _ => Ok(SigningKey::new(
+            include_bytes!("../../private.pem").to_vec(),
+            include_bytes!("../../public.pem").to_vec(),
+        )),
+    }
+}
+
+// The public key replaced by the last rotation, from JWT_PREVIOUS_PUBLIC_KEY_PATH, so the
+// rotation grace period survives restarts and holds on every instance
+fn load_previous_key() -> Result<Option<SigningKey>, String> {
+    let Ok(path) = env::var("JWT_PREVIOUS_PUBLIC_KEY_PATH") else {
+        return Ok(None);
     };
+    let public_pem = match fs::read(&path) {
+        Ok(pem) => pem,
+        // Nothing has been rotated yet
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Failed to read {}: {}", path, e)),
+    };
+    DecodingKey::from_rsa_pem(&public_pem).map_err(|e| format!("Invalid previous public key: {}", e))?;
+    Ok(Some(SigningKey::verifying(public_pem)))
+}
 
-    encode(
-        &Header::new(Algorithm::RS256),
-        &claims,
-        &EncodingKey::from_rsa_pem(&get_private_key()).expect("Invalid private key")
-    ).expect("Failed to generate JWT token")
+fn signing_keys() -> &'static RwLock<SigningKeys> {
+    SIGNING_KEYS.get_or_init(|| {
+        let current = load_signing_key().expect("Failed to load JWT signing key");
+        let previous = load_previous_key()
+            .expect("Failed to load previous JWT public key")
+            .filter(|previous| previous.kid != current.kid);
+        RwLock::new(SigningKeys { current, previous })
+    })
+}
+
+pub fn current_signing_key() -> SigningKey {
+    signing_keys().read().expect("Signing key lock poisoned").current.clone()
+}
+
+pub fn previous_signing_key() -> Option<SigningKey> {
+    signing_keys().read().expect("Signing key lock poisoned").previous.clone()
 }
 
-pub fn generate_refresh_token(user_id: &str) -> String {
-    let iat = Utc::now().timestamp() as usize;
-    let exp = (Utc::now() + Duration::days(30)).timestamp() as usize;
-    let jti = Uuid::new_v4().to_string();
+// Reloads the key pair from disk and makes it the current signing key. The replaced
+// public key is written to JWT_PREVIOUS_PUBLIC_KEY_PATH, if set, before the switch
+pub fn rotate_signing_key() -> Result<SigningKey, String> {
+    let new_key = load_signing_key()?;
+    let mut keys = signing_keys().write().map_err(|_| "Signing key lock poisoned")?;
 
-    let claims = RefreshTokenClaims { sub: user_id.to_string(), jti, iat, exp };
+    if new_key.kid != keys.current.kid {
+        if let Ok(path) = env::var("JWT_PREVIOUS_PUBLIC_KEY_PATH") {
+            fs::write(&path, &keys.current.public_pem)
+                .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+        }
+    }
+
+    keys.rotate(new_key.clone())?;
+    Ok(new_key)
+}
+
+fn decoding_key_for(token: &str) -> Option<DecodingKey> {
+    signing_keys().read().ok()?.decoding_key(token)
+}
+
+fn sign_with<T: Serialize>(key: &SigningKey, claims: &T) -> String {
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some(key.kid.clone());
 
     encode(
-        &Header::new(Algorithm::RS256),
-        &claims,
-        &EncodingKey::from_rsa_pem(&get_private_key()).expect("Invalid private key")
+        &header,
+        claims,
+        &EncodingKey::from_rsa_pem(&key.private_pem).expect("Invalid private key")
     ).expect("Failed to generate JWT token")
 }
 
+fn sign<T: Serialize>(claims: &T) -> String {
+    sign_with(&current_signing_key(), claims)
+}
+
+// Gera JWT com RS256
+pub fn generate_jwt(claims: &Claims) -> String {
+    sign(claims)
+}
+
+// Expires with its session rather than a fixed time after issue
+pub fn generate_refresh_token(user_id: &str, tenant_id: &str, sid: &str, exp: usize, iss: &str) -> RefreshTokenClaims {
+    RefreshTokenClaims {
+        sub: user_id.to_string(),
+        tenant_id: tenant_id.to_string(),
+        sid: sid.to_string(),
+        jti: Uuid::new_v4().to_string(),
+        iat: Utc::now().timestamp() as usize,
+        exp,
+        aud: REFRESH_TOKEN_AUDIENCE.to_string(),
+        iss: iss.to_string(),
+    }
+}
+
+pub fn sign_refresh_token(claims: &RefreshTokenClaims) -> String {
+    sign(claims)
+}
+
 pub fn verify_jwt(token: &str, aud: &str, iss: &str) -> Option<Claims> {
     let mut validation = Validation::new(Algorithm::RS256);
     validation.set_audience(&[aud]);
@@ -89,17 +328,146 @@ pub fn verify_jwt(token: &str, aud: &str, iss: &str) -> Option<Claims> {
 
     decode::<Claims>(
         token,
-        &DecodingKey::from_rsa_pem(&get_public_key()).ok()?,
+        &decoding_key_for(token)?,
+        &validation
+    ).ok().map(|TokenData { claims, .. }| claims)
+}
+
+// Uses its own audience so a challenge can never pass for an access token
+pub fn generate_mfa_challenge(user_id: &str, tenant_id: &str, org_id: Option<String>, amr: Vec<String>, iss: &str) -> String {
+    let now = Utc::now();
+    let claims = MfaChallengeClaims {
+        sub: user_id.to_string(),
+        tenant_id: tenant_id.to_string(),
+        org_id,
+        amr,
+        iat: now.timestamp() as usize,
+        exp: (now + Duration::minutes(MFA_CHALLENGE_MINUTES)).timestamp() as usize,
+        jti: Uuid::new_v4().to_string(),
+        aud: MFA_CHALLENGE_AUDIENCE.to_string(),
+        iss: iss.to_string(),
+    };
+
+    sign(&claims)
+}
+
+pub fn verify_mfa_challenge(token: &str, iss: &str) -> Option<MfaChallengeClaims> {
+    let mut validation = Validation::new(Algorithm::RS256);
+    validation.set_audience(&[MFA_CHALLENGE_AUDIENCE]);
+
+    let mut issuers = HashSet::new();
+    issuers.insert(iss.to_string());
+    validation.iss = Some(issuers);
+
+    decode::<MfaChallengeClaims>(
+        token,
+        &decoding_key_for(token)?,
         &validation
     ).ok().map(|TokenData { claims, .. }| claims)
 }
 
-pub fn verify_refresh_token(token: &str) -> Option<RefreshTokenClaims> {
-    let validation = Validation::new(Algorithm::RS256);
+pub fn generate_magic_link(user_id: &str, tenant_id: &str, org_id: Option<String>, lifetime: Duration, iss: &str) -> MagicLinkClaims {
+    let now = Utc::now();
+    MagicLinkClaims {
+        sub: user_id.to_string(),
+        tenant_id: tenant_id.to_string(),
+        org_id,
+        iat: now.timestamp() as usize,
+        exp: (now + lifetime).timestamp() as usize,
+        jti: Uuid::new_v4().to_string(),
+        aud: MAGIC_LINK_AUDIENCE.to_string(),
+        iss: iss.to_string(),
+    }
+}
+
+pub fn sign_magic_link(claims: &MagicLinkClaims) -> String {
+    sign(claims)
+}
+
+pub fn verify_magic_link(token: &str, iss: &str) -> Option<MagicLinkClaims> {
+    let mut validation = Validation::new(Algorithm::RS256);
+    validation.set_audience(&[MAGIC_LINK_AUDIENCE]);
+
+    let mut issuers = HashSet::new();
+    issuers.insert(iss.to_string());
+    validation.iss = Some(issuers);
+
+    decode::<MagicLinkClaims>(
+        token,
+        &decoding_key_for(token)?,
+        &validation
+    ).ok().map(|TokenData { claims, .. }| claims)
+}
+
+pub fn verify_refresh_token(token: &str, iss: &str) -> Option<RefreshTokenClaims> {
+    let mut validation = Validation::new(Algorithm::RS256);
+    validation.set_audience(&[REFRESH_TOKEN_AUDIENCE]);
+
+    let mut issuers = HashSet::new();
+    issuers.insert(iss.to_string());
+    validation.iss = Some(issuers);
 
     decode::<RefreshTokenClaims>(
         token,
-        &DecodingKey::from_rsa_pem(&get_public_key()).ok()?,
+        &decoding_key_for(token)?,
         &validation
     ).ok().map(|TokenData { claims, .. }| claims)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn original_key() -> SigningKey {
+        SigningKey::new(include_bytes!("../../private.pem").to_vec(), include_bytes!("../../public.pem").to_vec())
+    }
+
+    fn rotated_key() -> SigningKey {
+        SigningKey::new(
+            include_bytes!("testdata/rotated_private.pem").to_vec(),
+            include_bytes!("testdata/rotated_public.pem").to_vec(),
+        )
+    }
+
+    fn token_claims() -> Claims {
+        Claims::new("user-1", "acme", "jane@example.com", vec!["user".to_string()], true, "kong-security-api", "kong-security-service")
+    }
+
+    fn verify_with(keys: &SigningKeys, token: &str) -> Option<Claims> {
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_audience(&["kong-security-api"]);
+        decode::<Claims>(token, &keys.decoding_key(token)?, &validation).ok().map(|data| data.claims)
+    }
+
+    #[test]
+    fn test_kid_selects_signing_key() {
+        let keys = SigningKeys { current: rotated_key(), previous: Some(SigningKey::verifying(original_key().public_pem)) };
+
+        assert_eq!(keys.for_kid(None).unwrap().kid, keys.current.kid);
+        assert_eq!(keys.for_kid(Some(&keys.current.kid)).unwrap().kid, keys.current.kid);
+        assert_eq!(keys.for_kid(Some(&original_key().kid)).unwrap().kid, original_key().kid);
+        assert!(keys.for_kid(Some("0000000000000000")).is_none());
+        assert_ne!(original_key().kid, rotated_key().kid);
+    }
+
+    #[test]
+    fn test_previous_key_verifies_after_rotation() {
+        let mut keys = SigningKeys { current: original_key(), previous: None };
+        let old_token = sign_with(&original_key(), &token_claims());
+
+        keys.rotate(rotated_key()).unwrap();
+        assert!(keys.rotate(rotated_key()).is_err());
+        let new_token = sign_with(&keys.current, &token_claims());
+
+        assert!(verify_with(&keys, &old_token).is_some());
+        assert!(verify_with(&keys, &new_token).is_some());
+
+        // After a restart the previous key comes back from config as a public key only
+        let restarted = SigningKeys { current: rotated_key(), previous: Some(SigningKey::verifying(original_key().public_pem)) };
+        assert_eq!(verify_with(&restarted, &old_token).unwrap().sub, "user-1");
+
+        // Without it the old kid is unknown
+        let without_previous = SigningKeys { current: rotated_key(), previous: None };
+        assert!(verify_with(&without_previous, &old_token).is_none());
+    }
+}
@@ -1,65 +1,298 @@
-use actix_web::HttpRequest;
+use actix_web::{web, HttpRequest};
 use crate::models::User;
-use crate::auth::jwt::{verify_jwt, Claims};
+use crate::auth::jwt::{current_signing_key, generate_jwt, generate_magic_link, generate_mfa_challenge, generate_refresh_token, sign_magic_link, sign_refresh_token, verify_jwt, verify_magic_link, verify_mfa_challenge, verify_refresh_token, Actor, Claims, MagicLinkClaims, MfaChallengeClaims, RefreshTokenClaims};
+use crate::auth::session_cookies::{check_csrf, ACCESS_TOKEN_COOKIE};
+use crate::config::SessionCookieConfig;
+use crate::services::kong_service::kong_credential_key;
 use mongodb::bson::oid::ObjectId;
 use std::str::FromStr;
 
-pub fn create_jwt_token(user: &User) -> Result<String, String> {
+// Request-specific context stamped into an access token on top of the user's own data
+#[derive(Debug, Default, Clone)]
+pub struct TokenOptions {
+    pub org_id: Option<String>,
+    pub org_roles: Vec<String>,
+    // Roles inherited from the user's groups
+    pub group_roles: Vec<String>,
+    // Admin acting as the user; such tokens are barred from sensitive actions
+    pub act: Option<Actor>,
+    // Overrides the default access token lifetime
+    pub lifetime: Option<chrono::Duration>,
+    // Authentication methods behind the token, stamped as the amr claim
+    pub amr: Vec<String>,
+    // Session the token belongs to, stamped as the sid claim
+    pub sid: Option<String>,
+}
+
+// The user's own roles plus everything inherited from groups
+pub fn effective_roles(user: &User, group_roles: &[String]) -> Vec<String> {
+    let mut roles = user.roles.clone().unwrap_or_default();
+    for role in group_roles {
+        if !roles.contains(role) {
+            roles.push(role.clone());
+        }
+    }
+    roles
+}
+
+pub fn create_jwt_token(user: &User, options: &TokenOptions) -> Result<String, String> {
     let user_id = user._id.as_ref()
         .ok_or("User ID is required")?
         .to_hex();
     
     let aud = "kong-security-api";
     let iss = "kong-security-service";
-    
-    let token = crate::auth::jwt::generate_jwt(
+
+
+    let mut claims = Claims::new(
         &user_id,
+        &user.tenant_id,
         &user.email,
-        user.roles.clone().unwrap_or_default(),
+        effective_roles(user, &options.group_roles),
         user.is_active,
         aud,
         iss
     );
+
+    claims.org_id = options.org_id.clone();
+    claims.org_roles = options.org_roles.clone();
+    claims.act = options.act.clone();
+    claims.email_verified = user.email_verified;
+    claims.amr = options.amr.clone();
+    claims.sid = options.sid.clone();
+
+    if let Some(lifetime) = options.lifetime {
+        claims.exp = claims.iat + lifetime.num_seconds() as usize;
+    }
+
+    // Consumers synced to Kong get the key of their jwt credential for the current signing key
+    claims.key = user.kong_consumer_id.as_ref()
+        .map(|_| kong_credential_key(&user_id, &current_signing_key().kid));
     
-    Ok(token)
+    Ok(generate_jwt(&claims))
 }
 
-pub fn verify_jwt_token(req: &HttpRequest) -> Result<User, String> {
-    let auth_header = req.headers()
-        .get("Authorization")
-        .ok_or("Authorization header missing")?
-        .to_str()
-        .map_err(|_| "Invalid authorization header")?;
+// amr lists the methods of the first factor the user already passed
+pub fn create_mfa_challenge(user: &User, org_id: Option<String>, amr: &[&str]) -> String {
+    let user_id = user._id.map(|id| id.to_hex()).unwrap_or_default();
+    let amr = amr.iter().map(|m| m.to_string()).collect();
+    generate_mfa_challenge(&user_id, &user.tenant_id, org_id, amr, "kong-security-service")
+}
 
-    if !auth_header.starts_with("Bearer ") {
-        return Err("Invalid authorization format".to_string());
-    }
+// Returns the signed link token and its jti, which the caller stores to make the link single-use
+pub fn create_magic_link(user: &User, org_id: Option<String>, lifetime: chrono::Duration) -> (String, String) {
+    let user_id = user._id.map(|id| id.to_hex()).unwrap_or_default();
+    let claims = generate_magic_link(&user_id, &user.tenant_id, org_id, lifetime, "kong-security-service");
+    (sign_magic_link(&claims), claims.jti)
+}
+
+// Returns the signed refresh token and its jti, which the session stores
+pub fn create_refresh_token(user: &User, sid: &str, expires_at: mongodb::bson::DateTime) -> (String, String) {
+    let user_id = user._id.map(|id| id.to_hex()).unwrap_or_default();
+    let exp = (expires_at.timestamp_millis() / 1000) as usize;
+    let claims = generate_refresh_token(&user_id, &user.tenant_id, sid, exp, "kong-security-service");
+    (sign_refresh_token(&claims), claims.jti)
+}
+
+pub fn verify_refresh_token_string(token: &str) -> Option<RefreshTokenClaims> {
+    verify_refresh_token(token, "kong-security-service")
+}
+
+pub fn verify_magic_link_token(token: &str) -> Option<MagicLinkClaims> {
+    verify_magic_link(token, "kong-security-service")
+}
+
+pub fn verify_mfa_challenge_token(token: &str) -> Option<MfaChallengeClaims> {
+    verify_mfa_challenge(token, "kong-security-service")
+}
+
+pub fn verify_jwt_claims(req: &HttpRequest) -> Result<Claims, String> {
+    let token = match req.headers().get("Authorization") {
+        Some(header) => {
+            let auth_header = header.to_str().map_err(|_| "Invalid authorization header")?;
+            if !auth_header.starts_with("Bearer ") {
+                return Err("Invalid authorization format".to_string());
+            }
+            auth_header[7..].to_string() // Remove "Bearer " prefix
+        }
+        None => {
+            // The cookie only counts when the server itself hands out access token cookies
+            let cookie_mode = req.app_data::<web::Data<SessionCookieConfig>>()
+                .is_some_and(|config| config.access_token_cookie());
+            if !cookie_mode {
+                return Err("Authorization header missing".to_string());
+            }
+
+            // Cookie mode: browsers send the cookie on their own, so it needs a CSRF token
+            let cookie = req.cookie(ACCESS_TOKEN_COOKIE).ok_or("Authorization header missing")?;
+            check_csrf(req)?;
+            cookie.value().to_string()
+        }
+    };
 
-    let token = &auth_header[7..]; // Remove "Bearer " prefix
     let aud = "kong-security-api";
     let iss = "kong-security-service";
 
-    let claims = verify_jwt(token, aud, iss)
-        .ok_or("Invalid token")?;
+    verify_jwt(&token, aud, iss)
+        .ok_or_else(|| "Invalid token".to_string())
+}
 
+pub fn user_from_claims(claims: Claims) -> Result<User, String> {
     // Convert claims back to User struct
     let user_id = ObjectId::from_str(&claims.sub)
         .map_err(|_| "Invalid user ID in token")?;
 
     let user = User {
         _id: Some(user_id),
+        tenant_id: claims.tenant_id,
         email: claims.email,
         password: String::new(), // Don't include password in token verification
+        name: None,
+        avatar_url: None,
+        locale: None,
+        timezone: None,
         roles: Some(claims.roles),
         created_at: Some(mongodb::bson::DateTime::now()),
         updated_at: Some(mongodb::bson::DateTime::now()),
         is_active: claims.is_active,
         last_login: None,
-        email_verified: false,
+        email_verified: claims.email_verified,
+        password_changed_at: None,
+        tokens_revoked_at: None,
+        password_breached: false,
+        mfa_enabled: false,
+        totp_secret: None,
+        totp_pending_secret: None,
+        totp_last_step: None,
+        recovery_codes: Vec::new(),
+        webauthn_credentials: Vec::new(),
+        failed_login_count: 0,
+        first_failed_login_at: None,
+        locked_until: None,
+        lockout_count: 0,
+        email_verification_token: None,
+        email_verification_expiry: None,
         password_reset_token: None,
         password_reset_expiry: None,
+        magic_link_token: None,
+        email_otp_hash: None,
+        email_otp_expiry: None,
+        email_otp_attempts: 0,
         refresh_tokens: None,
+        kong_consumer_id: None,
     };
 
     Ok(user)
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_magic_link_round_trip() {
+        let user = User::new("acme".to_string(), "jane@example.com".to_string(), String::new());
+        let (token, jti) = create_magic_link(&user, Some("org-1".to_string()), chrono::Duration::minutes(15));
+
+        let claims = verify_magic_link_token(&token).unwrap();
+        assert_eq!(claims.sub, user._id.unwrap().to_hex());
+        assert_eq!(claims.tenant_id, "acme");
+        assert_eq!(claims.org_id.as_deref(), Some("org-1"));
+        assert_eq!(claims.jti, jti);
+
+        // Each kind of token only passes its own check
+        assert!(verify_mfa_challenge_token(&token).is_none());
+        assert!(verify_jwt(&token, "kong-security-api", "kong-security-service").is_none());
+        assert!(verify_magic_link_token(&create_mfa_challenge(&user, None, &["pwd"])).is_none());
+
+        let (expired, _) = create_magic_link(&user, None, chrono::Duration::minutes(-5));
+        assert!(verify_magic_link_token(&expired).is_none());
+    }
+
+    fn decode_access_token(token: &str) -> Claims {
+        verify_jwt(token, "kong-security-api", "kong-security-service").unwrap()
+    }
+
+    #[test]
+    fn test_claims_carry_roles_of_the_active_org_only() {
+        let mut user = User::new("acme".to_string(), "jane@example.com".to_string(), String::new());
+        user.roles = Some(vec!["user".to_string()]);
+        let org_token = |org_id: &str, roles: &[&str]| create_jwt_token(&user, &TokenOptions {
+            org_id: Some(org_id.to_string()),
+            org_roles: roles.iter().map(|r| r.to_string()).collect(),
+            ..TokenOptions::default()
+        }).unwrap();
+
+        // Admin in one organization, viewer in the other
+        let claims = decode_access_token(&org_token("org-a", &["admin"]));
+        assert_eq!(claims.org_id.as_deref(), Some("org-a"));
+        assert_eq!(claims.org_roles, vec!["admin"]);
+        assert_eq!(claims.roles, vec!["user"]);
+
+        let claims = decode_access_token(&org_token("org-b", &["viewer"]));
+        assert_eq!(claims.org_id.as_deref(), Some("org-b"));
+        assert_eq!(claims.org_roles, vec!["viewer"]);
+
+        let claims = decode_access_token(&create_jwt_token(&user, &TokenOptions::default()).unwrap());
+        assert!(claims.org_id.is_none());
+        assert!(claims.org_roles.is_empty());
+    }
+
+    #[test]
+    fn test_token_roles_combine_direct_and_group_roles() {
+        let mut user = User::new("acme".to_string(), "jane@example.com".to_string(), String::new());
+        user.roles = Some(vec!["user".to_string(), "support".to_string()]);
+        let group_roles = vec!["support".to_string(), "pager".to_string()];
+
+        assert_eq!(effective_roles(&user, &group_roles), vec!["user", "support", "pager"]);
+
+        let token = create_jwt_token(&user, &TokenOptions { group_roles, ..TokenOptions::default() }).unwrap();
+        assert_eq!(decode_access_token(&token).roles, vec!["user", "support", "pager"]);
+
+        user.roles = None;
+        assert_eq!(effective_roles(&user, &["pager".to_string()]), vec!["pager"]);
+    }
+
+    #[test]
+    fn test_refresh_token_round_trip() {
+        let user = User::new("acme".to_string(), "jane@example.com".to_string(), String::new());
+        let expires_at = mongodb::bson::DateTime::from_millis(mongodb::bson::DateTime::now().timestamp_millis() + 60_000);
+        let (token, jti) = create_refresh_token(&user, "session-1", expires_at);
+
+        let claims = verify_refresh_token_string(&token).unwrap();
+        assert_eq!(claims.sid, "session-1");
+        assert_eq!(claims.tenant_id, "acme");
+        assert_eq!(claims.jti, jti);
+        assert_eq!(claims.exp as i64, expires_at.timestamp_millis() / 1000);
+
+        assert!(verify_jwt(&token, "kong-security-api", "kong-security-service").is_none());
+        assert!(verify_refresh_token_string(&create_mfa_challenge(&user, None, &["pwd"])).is_none());
+    }
+
+    #[test]
+    fn test_access_cookie_only_read_in_cookie_mode() {
+        use actix_web::cookie::Cookie;
+        use actix_web::test::TestRequest;
+
+        let user = User::new("acme".to_string(), "jane@example.com".to_string(), String::new());
+        let token = create_jwt_token(&user, &TokenOptions::default()).unwrap();
+        let request = |enabled: bool| {
+            TestRequest::get()
+                .cookie(Cookie::new(ACCESS_TOKEN_COOKIE, token.clone()))
+                .app_data(web::Data::new(SessionCookieConfig {
+                    enabled,
+                    access_token: true,
+                    domain: None,
+                    path: "/api".to_string(),
+                    same_site: "strict".to_string(),
+                }))
+                .to_http_request()
+        };
+
+        assert_eq!(verify_jwt_claims(&request(true)).unwrap().sub, user._id.unwrap().to_hex());
+        assert!(verify_jwt_claims(&request(false)).is_err());
+        // Without a cookie config the server is not in cookie mode either
+        assert!(verify_jwt_claims(&TestRequest::get()
+            .cookie(Cookie::new(ACCESS_TOKEN_COOKIE, token.clone()))
+            .to_http_request()).is_err());
+    }
+}
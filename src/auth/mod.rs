@@ -1,5 +1,19 @@
 pub mod jwt;
 pub mod middleware;
+pub mod tenant;
+pub mod password_policy;
+pub mod breached_passwords;
+pub mod password_hash;
+pub mod hashing_pool;
+pub mod totp;
+pub mod recovery_codes;
+pub mod webauthn;
+pub mod session_cookies;
 
 pub use jwt::*;
-pub use middleware::*;
\ No newline at end of file
+pub use middleware::*;
+pub use tenant::*;
+pub use password_policy::*;
+pub use password_hash::PasswordHasher;
+pub use hashing_pool::HashingPool;
+pub use session_cookies::{access_token_cookie, check_csrf, clear_session_cookies, session_cookies};
@@ -0,0 +1,174 @@
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher as _, PasswordVerifier as _, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use std::env;
+use std::error::Error;
+
+// Algorithm used for new hashes; existing hashes of the other kind still verify
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Argon2id,
+    Bcrypt,
+}
+
+// Hashes are stored in their self-describing form, so the algorithm and its
+// parameters travel with each hash: `$argon2id$v=19$m=...,t=...,p=...$salt$hash` or `$2b$12$...`
+#[derive(Debug, Clone)]
+pub struct PasswordHasher {
+    pub algorithm: HashAlgorithm,
+    pub argon2_memory_kib: u32,
+    pub argon2_iterations: u32,
+    pub argon2_parallelism: u32,
+    pub bcrypt_cost: u32,
+}
+
+impl Default for PasswordHasher {
+    fn default() -> Self {
+        // OWASP recommended minimum for Argon2id
+        Self {
+            algorithm: HashAlgorithm::Argon2id,
+            argon2_memory_kib: 19 * 1024,
+            argon2_iterations: 2,
+            argon2_parallelism: 1,
+            bcrypt_cost: bcrypt::DEFAULT_COST,
+        }
+    }
+}
+
+impl PasswordHasher {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let number = |name: &str, default: u32| env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default);
+
+        let hasher = Self {
+            algorithm: match env::var("PASSWORD_HASH_ALGORITHM").as_deref() {
+                Ok("bcrypt") => HashAlgorithm::Bcrypt,
+                _ => HashAlgorithm::Argon2id,
+            },
+            argon2_memory_kib: number("ARGON2_MEMORY_KIB", defaults.argon2_memory_kib),
+            argon2_iterations: number("ARGON2_ITERATIONS", defaults.argon2_iterations),
+            argon2_parallelism: number("ARGON2_PARALLELISM", defaults.argon2_parallelism),
+            bcrypt_cost: number("BCRYPT_COST", defaults.bcrypt_cost),
+        };
+
+        hasher.argon2_params().expect("Invalid Argon2 parameters");
+        hasher
+    }
+
+    fn argon2_params(&self) -> Result<Params, argon2::Error> {
+        Params::new(self.argon2_memory_kib, self.argon2_iterations, self.argon2_parallelism, None)
+    }
+
+    pub fn hash(&self, password: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+        match self.algorithm {
+            HashAlgorithm::Argon2id => {
+                let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, self.argon2_params()?);
+                let salt = SaltString::generate(&mut OsRng);
+                Ok(argon2.hash_password(password.as_bytes(), &salt)?.to_string())
+            }
+            HashAlgorithm::Bcrypt => Ok(bcrypt::hash(password, self.bcrypt_cost)?),
+        }
+    }
+
+    // Checks a password against a stored hash of either algorithm
+    pub fn verify(&self, password: &str, stored_hash: &str) -> bool {
+        if is_bcrypt(stored_hash) {
+            return bcrypt::verify(password, stored_hash).unwrap_or(false);
+        }
+
+        // The parameters are read from the hash itself
+        match PasswordHash::new(stored_hash) {
+            Ok(parsed) => Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    // Whether a verified hash should be replaced by one with the current algorithm and parameters
+    pub fn needs_rehash(&self, stored_hash: &str) -> bool {
+        match self.algorithm {
+            HashAlgorithm::Bcrypt => bcrypt_cost(stored_hash) != Some(self.bcrypt_cost),
+            HashAlgorithm::Argon2id => {
+                let Ok(parsed) = PasswordHash::new(stored_hash) else {
+                    return true;
+                };
+                if parsed.algorithm != argon2::ARGON2ID_IDENT || parsed.version != Some(Version::V0x13.into()) {
+                    return true;
+                }
+
+                match Params::try_from(&parsed) {
+                    Ok(params) => {
+                        params.m_cost() != self.argon2_memory_kib
+                            || params.t_cost() != self.argon2_iterations
+                            || params.p_cost() != self.argon2_parallelism
+                    }
+                    Err(_) => true,
+                }
+            }
+        }
+    }
+}
+
+fn is_bcrypt(stored_hash: &str) -> bool {
+    ["$2a$", "$2b$", "$2x$", "$2y$"].iter().any(|prefix| stored_hash.starts_with(prefix))
+}
+
+// "$2b$12$..." -> 12
+fn bcrypt_cost(stored_hash: &str) -> Option<u32> {
+    if !is_bcrypt(stored_hash) {
+        return None;
+    }
+    stored_hash.split('$').nth(2)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Small parameters keep the tests fast
+    fn argon2_hasher() -> PasswordHasher {
+        PasswordHasher { argon2_memory_kib: 1024, argon2_iterations: 1, bcrypt_cost: 4, ..PasswordHasher::default() }
+    }
+
+    #[test]
+    fn test_argon2id_round_trip() {
+        let hasher = argon2_hasher();
+        let hash = hasher.hash("correct horse battery").unwrap();
+
+        assert!(hash.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+        assert!(hasher.verify("correct horse battery", &hash));
+        assert!(!hasher.verify("wrong horse battery", &hash));
+        assert!(!hasher.needs_rehash(&hash));
+    }
+
+    #[test]
+    fn test_bcrypt_hash_is_upgraded() {
+        let hasher = argon2_hasher();
+        let legacy = bcrypt::hash("correct horse battery", 4).unwrap();
+
+        assert!(hasher.verify("correct horse battery", &legacy));
+        assert!(!hasher.verify("wrong horse battery", &legacy));
+        assert!(hasher.needs_rehash(&legacy));
+    }
+
+    #[test]
+    fn test_outdated_argon2_parameters_need_rehash() {
+        let old = argon2_hasher();
+        let hash = old.hash("correct horse battery").unwrap();
+
+        let stronger = PasswordHasher { argon2_memory_kib: 2048, ..old.clone() };
+        assert!(stronger.verify("correct horse battery", &hash));
+        assert!(stronger.needs_rehash(&hash));
+
+        let bcrypt_hasher = PasswordHasher { algorithm: HashAlgorithm::Bcrypt, ..old };
+        assert!(bcrypt_hasher.needs_rehash(&hash));
+        assert!(!bcrypt_hasher.needs_rehash(&bcrypt::hash("x", 4).unwrap()));
+    }
+
+    #[test]
+    fn test_garbage_hash_never_verifies() {
+        let hasher = argon2_hasher();
+        assert!(!hasher.verify("password", "not-a-hash"));
+        assert!(!hasher.verify("password", ""));
+        assert!(hasher.needs_rehash("not-a-hash"));
+    }
+}
@@ -0,0 +1,247 @@
+use serde::Serialize;
+use std::env;
+use std::path::Path;
+use std::sync::Arc;
+use crate::auth::breached_passwords::BreachedPasswords;
+
+// bcrypt only hashes the first 72 bytes of a password
+pub const MAX_PASSWORD_BYTES: usize = 72;
+
+// Shortest name or email fragment that counts as personal information
+const MIN_PERSONAL_FRAGMENT: usize = 3;
+
+// Most common passwords from public breach statistics
+const COMMON_PASSWORDS: &[&str] = &[
+    "123456", "123456789", "12345678", "1234567890", "12345", "1234567", "password", "password1",
+    "password123", "qwerty", "qwerty123", "qwertyuiop", "abc123", "111111", "123123", "000000",
+    "iloveyou", "1q2w3e4r", "1qaz2wsx", "qazwsx", "admin", "admin123", "administrator", "welcome",
+    "welcome1", "letmein", "monkey", "dragon", "football", "baseball", "sunshine", "princess",
+    "master", "shadow", "superman", "michael", "trustno1", "passw0rd", "p@ssw0rd", "p@ssword",
+    "changeme", "secret", "login", "starwars", "whatever", "zaq12wsx", "asdfghjkl", "asdfgh",
+    "1234qwer", "q1w2e3r4", "654321", "666666", "888888", "987654321", "121212", "aa123456",
+    "charlie", "jennifer", "hunter2", "freedom", "computer", "internet", "summer2024", "winter2024",
+    "spring2024", "autumn2024", "company123", "default", "guest", "test1234", "testtest", "access",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PasswordRule {
+    MinLength,
+    MaxLength,
+    Lowercase,
+    Uppercase,
+    Digit,
+    Symbol,
+    CharacterClasses,
+    ContainsEmail,
+    ContainsName,
+    CommonPassword,
+    Breached,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct PasswordViolation {
+    pub rule: PasswordRule,
+    pub message: String,
+}
+
+// Body of the 400 response listing every rule the password broke
+#[derive(Debug, Serialize)]
+pub struct PasswordPolicyError {
+    pub error: &'static str,
+    pub violations: Vec<PasswordViolation>,
+}
+
+impl From<Vec<PasswordViolation>> for PasswordPolicyError {
+    fn from(violations: Vec<PasswordViolation>) -> Self {
+        Self { error: "weak_password", violations }
+    }
+}
+
+// Who the password belongs to, for the personal information checks
+pub struct PasswordContext<'a> {
+    pub email: &'a str,
+    pub name: Option<&'a str>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    // How many of lowercase, uppercase, digits and symbols must appear
+    pub min_character_classes: usize,
+    pub breached: Arc<BreachedPasswords>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+            min_character_classes: 2,
+            breached: Arc::default(),
+        }
+    }
+}
+
+impl PasswordPolicy {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let flag = |name: &str| env::var(name).map(|v| v == "true" || v == "1").unwrap_or(false);
+
+        Self {
+            min_length: env::var("PASSWORD_MIN_LENGTH").ok().and_then(|v| v.parse().ok()).unwrap_or(defaults.min_length),
+            require_lowercase: flag("PASSWORD_REQUIRE_LOWERCASE"),
+            require_uppercase: flag("PASSWORD_REQUIRE_UPPERCASE"),
+            require_digit: flag("PASSWORD_REQUIRE_DIGIT"),
+            require_symbol: flag("PASSWORD_REQUIRE_SYMBOL"),
+            min_character_classes: env::var("PASSWORD_MIN_CHARACTER_CLASSES").ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(defaults.min_character_classes),
+            breached: Arc::new(Self::load_breached_list()),
+        }
+    }
+
+    // Optional local breach corpus from BREACHED_PASSWORDS_PATH
+    fn load_breached_list() -> BreachedPasswords {
+        match env::var("BREACHED_PASSWORDS_PATH").ok().filter(|path| !path.is_empty()) {
+            Some(path) => match BreachedPasswords::load(Path::new(&path)) {
+                Ok(list) => list,
+                Err(e) => {
+                    eprintln!("Failed to load breached password list {}, screening is disabled: {}", path, e);
+                    BreachedPasswords::default()
+                }
+            },
+            None => BreachedPasswords::default(),
+        }
+    }
+
+    // Checks every rule and reports all violations at once
+    pub fn check(&self, password: &str, context: &PasswordContext) -> Result<(), Vec<PasswordViolation>> {
+        let mut violations = Vec::new();
+        let mut violate = |rule, message: String| violations.push(PasswordViolation { rule, message });
+
+        if password.chars().count() < self.min_length {
+            violate(PasswordRule::MinLength, format!("Password must be at least {} characters", self.min_length));
+        }
+        if password.len() > MAX_PASSWORD_BYTES {
+            violate(PasswordRule::MaxLength, format!("Password must be at most {} bytes", MAX_PASSWORD_BYTES));
+        }
+
+        let has_lowercase = password.chars().any(char::is_lowercase);
+        let has_uppercase = password.chars().any(char::is_uppercase);
+        let has_digit = password.chars().any(|c| c.is_ascii_digit());
+        let has_symbol = password.chars().any(|c| !c.is_alphanumeric());
+
+        if self.require_lowercase && !has_lowercase {
+            violate(PasswordRule::Lowercase, "Password must contain a lowercase letter".to_string());
+        }
+        if self.require_uppercase && !has_uppercase {
+            violate(PasswordRule::Uppercase, "Password must contain an uppercase letter".to_string());
+        }
+        if self.require_digit && !has_digit {
+            violate(PasswordRule::Digit, "Password must contain a digit".to_string());
+        }
+        if self.require_symbol && !has_symbol {
+            violate(PasswordRule::Symbol, "Password must contain a symbol".to_string());
+        }
+
+        let classes = [has_lowercase, has_uppercase, has_digit, has_symbol].iter().filter(|c| **c).count();
+        if classes < self.min_character_classes {
+            violate(PasswordRule::CharacterClasses, format!(
+                "Password must mix at least {} of lowercase letters, uppercase letters, digits and symbols",
+                self.min_character_classes,
+            ));
+        }
+
+        let lowered = password.to_lowercase();
+        let email = context.email.to_lowercase();
+        let local_part = email.split('@').next().unwrap_or_default();
+        if lowered.contains(&email) || (local_part.len() >= MIN_PERSONAL_FRAGMENT && lowered.contains(local_part)) {
+            violate(PasswordRule::ContainsEmail, "Password cannot contain your email address".to_string());
+        }
+
+        let name = context.name.unwrap_or_default().to_lowercase();
+        if name.split_whitespace().any(|part| part.chars().count() >= MIN_PERSONAL_FRAGMENT && lowered.contains(part)) {
+            violate(PasswordRule::ContainsName, "Password cannot contain your name".to_string());
+        }
+
+        if COMMON_PASSWORDS.contains(&lowered.as_str()) {
+            violate(PasswordRule::CommonPassword, "Password is too common".to_string());
+        }
+        if self.breached.contains(password) {
+            violate(PasswordRule::Breached, "Password appears in a known data breach".to_string());
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(result: Result<(), Vec<PasswordViolation>>) -> Vec<PasswordRule> {
+        result.err().unwrap_or_default().into_iter().map(|v| v.rule).collect()
+    }
+
+    const JANE: PasswordContext = PasswordContext { email: "jane.doe@example.com", name: Some("Jane Doe") };
+
+    #[test]
+    fn test_default_policy() {
+        let policy = PasswordPolicy::default();
+
+        assert!(policy.check("correct horse battery", &JANE).is_ok());
+        assert_eq!(rules(policy.check("", &JANE)), vec![PasswordRule::MinLength, PasswordRule::CharacterClasses]);
+        assert_eq!(rules(policy.check(&"a1".repeat(37), &JANE)), vec![PasswordRule::MaxLength]);
+        assert_eq!(rules(policy.check("Password1", &JANE)), vec![PasswordRule::CommonPassword]);
+    }
+
+    #[test]
+    fn test_personal_information() {
+        let policy = PasswordPolicy::default();
+
+        assert_eq!(rules(policy.check("my-jane.doe-pass", &PasswordContext { email: "jane.doe@example.com", name: None })), vec![PasswordRule::ContainsEmail]);
+        assert_eq!(rules(policy.check("xJANE.DOEx99", &JANE)), vec![PasswordRule::ContainsEmail, PasswordRule::ContainsName]);
+        assert_eq!(rules(policy.check("does-it-matter", &JANE)), vec![PasswordRule::ContainsName]);
+    }
+
+    #[test]
+    fn test_required_character_classes() {
+        let policy = PasswordPolicy {
+            require_uppercase: true,
+            require_digit: true,
+            require_symbol: true,
+            min_character_classes: 4,
+            ..PasswordPolicy::default()
+        };
+
+        assert!(policy.check("Tr0ub4dor&3", &JANE).is_ok());
+        assert_eq!(
+            rules(policy.check("troubadour", &JANE)),
+            vec![PasswordRule::Uppercase, PasswordRule::Digit, PasswordRule::Symbol, PasswordRule::CharacterClasses],
+        );
+    }
+
+    #[test]
+    fn test_breached_password() {
+        let path = std::env::temp_dir().join(format!("breached-{}.txt", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "B1B3773A05C0ED0176787A4F1574FF0075F7521E:1\n").unwrap(); // sha1("qwerty")
+        let breached = BreachedPasswords::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let policy = PasswordPolicy { breached: Arc::new(breached), ..PasswordPolicy::default() };
+        assert!(rules(policy.check("qwerty", &JANE)).contains(&PasswordRule::Breached));
+        assert!(policy.check("correct horse battery", &JANE).is_ok());
+    }
+}
@@ -0,0 +1,57 @@
+use rand::Rng;
+use crate::utils::secure_token::hash_token;
+
+pub const RECOVERY_CODE_COUNT: usize = 10;
+const CODE_LENGTH: usize = 10;
+// No 0/o, 1/l/i, so codes survive being read aloud or written down
+const ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+// Single-use codes shown to the user once, as "xxxxx-xxxxx"
+pub fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = rand::thread_rng();
+
+    (0..RECOVERY_CODE_COUNT).map(|_| {
+        let code: String = (0..CODE_LENGTH)
+            .map(|_| ALPHABET[rng.gen_range(0..ALPHABET.len())] as char)
+            .collect();
+        format!("{}-{}", &code[..CODE_LENGTH / 2], &code[CODE_LENGTH / 2..])
+    }).collect()
+}
+
+// Stored form; case, spaces and dashes typed by the user don't matter
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .flat_map(char::to_lowercase)
+        .collect();
+    hash_token(&normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_codes() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+
+        for code in &codes {
+            assert_eq!(code.len(), CODE_LENGTH + 1);
+            assert_eq!(code.find('-'), Some(CODE_LENGTH / 2));
+        }
+
+        let mut unique = codes.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), codes.len());
+    }
+
+    #[test]
+    fn test_hash_ignores_formatting() {
+        let hash = hash_recovery_code("abcde-fgh23");
+        assert_eq!(hash_recovery_code("ABCDE FGH23"), hash);
+        assert_eq!(hash_recovery_code(" abcdefgh23 "), hash);
+        assert_ne!(hash_recovery_code("abcde-fgh24"), hash);
+    }
+}
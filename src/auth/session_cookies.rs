@@ -0,0 +1,144 @@
+use actix_web::cookie::{time::Duration, Cookie, SameSite};
+use actix_web::http::Method;
+use actix_web::HttpRequest;
+use mongodb::bson::DateTime;
+use crate::config::SessionCookieConfig;
+use crate::utils::secure_token::{generate_token, hash_token};
+
+pub const ACCESS_TOKEN_COOKIE: &str = "ks_access";
+pub const REFRESH_TOKEN_COOKIE: &str = "ks_refresh";
+// Readable by scripts, which echo it in CSRF_HEADER (double-submit)
+pub const CSRF_COOKIE: &str = "ks_csrf";
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+fn same_site(config: &SessionCookieConfig) -> SameSite {
+    match config.same_site.as_str() {
+        "lax" => SameSite::Lax,
+        "none" => SameSite::None,
+        _ => SameSite::Strict,
+    }
+}
+
+fn build_cookie(config: &SessionCookieConfig, name: &'static str, value: String, path: &str, http_only: bool) -> Cookie<'static> {
+    let mut cookie = Cookie::build(name, value)
+        .path(path.to_string())
+        .secure(true)
+        .http_only(http_only)
+        .same_site(same_site(config))
+        .finish();
+    if let Some(domain) = &config.domain {
+        cookie.set_domain(domain.clone());
+    }
+    cookie
+}
+
+// Cookies for a new or refreshed session: the refresh token, the access token if
+// configured, and a fresh CSRF token
+pub fn session_cookies(config: &SessionCookieConfig, access_token: &str, refresh_token: &str, expires_at: DateTime) -> Vec<Cookie<'static>> {
+    let max_age = Duration::milliseconds(expires_at.timestamp_millis() - DateTime::now().timestamp_millis());
+
+    let mut refresh = build_cookie(config, REFRESH_TOKEN_COOKIE, refresh_token.to_string(), &config.path, true);
+    refresh.set_max_age(max_age);
+    // The CSRF cookie must be readable from every page of the app, not only under the API path
+    let mut csrf = build_cookie(config, CSRF_COOKIE, generate_token(), "/", false);
+    csrf.set_max_age(max_age);
+
+    let mut cookies = vec![refresh, csrf];
+    if let Some(access) = access_token_cookie(config, access_token) {
+        cookies.push(access);
+    }
+    cookies
+}
+
+// The access token cookie on its own, for responses that only issue a new access token
+pub fn access_token_cookie(config: &SessionCookieConfig, access_token: &str) -> Option<Cookie<'static>> {
+    config.access_token_cookie()
+        .then(|| build_cookie(config, ACCESS_TOKEN_COOKIE, access_token.to_string(), &config.path, true))
+}
+
+// Expired copies of every session cookie, sent on logout
+pub fn clear_session_cookies(config: &SessionCookieConfig) -> Vec<Cookie<'static>> {
+    [(ACCESS_TOKEN_COOKIE, config.path.as_str()), (REFRESH_TOKEN_COOKIE, config.path.as_str()), (CSRF_COOKIE, "/")]
+        .into_iter()
+        .map(|(name, path)| {
+            let mut cookie = build_cookie(config, name, String::new(), path, name != CSRF_COOKIE);
+            cookie.make_removal();
+            cookie
+        })
+        .collect()
+}
+
+// Requests authenticated by cookie that can change state must echo the CSRF cookie in
+// the CSRF header; another site can make the browser send the cookie but can't read it
+pub fn check_csrf(req: &HttpRequest) -> Result<(), String> {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return Ok(());
+    }
+
+    let cookie = req.cookie(CSRF_COOKIE).ok_or("CSRF cookie missing")?;
+    let header = req.headers()
+        .get(CSRF_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .ok_or("CSRF token missing")?;
+
+    // Comparing hashes keeps the comparison time independent of the token
+    if cookie.value().is_empty() || hash_token(cookie.value()) != hash_token(header) {
+        return Err("Invalid CSRF token".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn config() -> SessionCookieConfig {
+        SessionCookieConfig {
+            enabled: true,
+            access_token: true,
+            domain: Some("example.com".to_string()),
+            path: "/api".to_string(),
+            same_site: "lax".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_session_cookie_attributes() {
+        let expires_at = DateTime::from_millis(DateTime::now().timestamp_millis() + 60 * 60 * 1000);
+        let cookies = session_cookies(&config(), "access", "refresh", expires_at);
+        let find = |name: &str| cookies.iter().find(|cookie| cookie.name() == name).unwrap();
+
+        let refresh = find(REFRESH_TOKEN_COOKIE);
+        assert_eq!(refresh.value(), "refresh");
+        assert_eq!(refresh.http_only(), Some(true));
+        assert_eq!(refresh.secure(), Some(true));
+        assert_eq!(refresh.same_site(), Some(SameSite::Lax));
+        assert_eq!(refresh.domain(), Some("example.com"));
+        assert_eq!(refresh.path(), Some("/api"));
+        assert!(refresh.max_age().is_some());
+
+        assert_eq!(find(ACCESS_TOKEN_COOKIE).value(), "access");
+        assert_eq!(find(CSRF_COOKIE).http_only(), Some(false));
+        assert_eq!(find(CSRF_COOKIE).path(), Some("/"));
+
+        let refresh_only = SessionCookieConfig { access_token: false, ..config() };
+        assert_eq!(session_cookies(&refresh_only, "access", "refresh", expires_at).len(), 2);
+    }
+
+    #[test]
+    fn test_csrf_double_submit() {
+        let cookie = Cookie::new(CSRF_COOKIE, "abc123");
+
+        assert!(check_csrf(&TestRequest::get().cookie(cookie.clone()).to_http_request()).is_ok());
+        assert!(check_csrf(&TestRequest::post().cookie(cookie.clone()).to_http_request()).is_err());
+        assert!(check_csrf(&TestRequest::post()
+            .cookie(cookie.clone())
+            .insert_header((CSRF_HEADER, "wrong"))
+            .to_http_request()).is_err());
+        assert!(check_csrf(&TestRequest::post()
+            .cookie(cookie)
+            .insert_header((CSRF_HEADER, "abc123"))
+            .to_http_request()).is_ok());
+    }
+}
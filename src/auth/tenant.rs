@@ -0,0 +1,88 @@
+use actix_web::HttpRequest;
+use crate::config::TenantConfig;
+use crate::models::{TenantScope, User, DEFAULT_TENANT_ID};
+
+fn normalize_tenant(tenant: &str) -> Result<String, String> {
+    let tenant = tenant.trim().to_lowercase();
+
+    if tenant.is_empty()
+        || tenant.len() > 64
+        || !tenant.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err("Invalid tenant".to_string());
+    }
+
+    Ok(tenant)
+}
+
+fn tenant_from_header(req: &HttpRequest, config: &TenantConfig) -> Option<String> {
+    req.headers()
+        .get(config.header_name.as_str())
+        .and_then(|value| value.to_str().ok())
+        .map(|s| s.to_string())
+}
+
+fn tenant_from_host(host: &str, config: &TenantConfig) -> Option<String> {
+    let suffix = config.host_suffix.as_ref()?;
+    let host = host.split(':').next().unwrap_or(host);
+
+    host.strip_suffix(suffix.as_str())
+        .and_then(|prefix| prefix.strip_suffix('.'))
+        .filter(|prefix| !prefix.is_empty() && !prefix.contains('.'))
+        .map(|prefix| prefix.to_string())
+}
+
+// Resolves the tenant of an unauthenticated request: login payload, then header, then hostname
+pub fn resolve_tenant(req: &HttpRequest, config: &TenantConfig, payload_tenant: Option<&str>) -> Result<String, String> {
+    let host = req.connection_info().host().to_string();
+
+    match payload_tenant
+        .map(|t| t.to_string())
+        .or_else(|| tenant_from_header(req, config))
+        .or_else(|| tenant_from_host(&host, config))
+    {
+        Some(tenant) => normalize_tenant(&tenant),
+        None => Ok(DEFAULT_TENANT_ID.to_string()),
+    }
+}
+
+// Tenant an admin request operates on. Admins are confined to their own tenant; super admins
+// may target another tenant through the tenant header, or every tenant with "*".
+pub fn admin_scope(req: &HttpRequest, config: &TenantConfig, admin: &User) -> Result<TenantScope, String> {
+    if !admin.is_super_admin() {
+        return Ok(TenantScope::Tenant(admin.tenant_id.clone()));
+    }
+
+    match tenant_from_header(req, config) {
+        Some(tenant) if tenant.trim() == "*" => Ok(TenantScope::AllTenants),
+        Some(tenant) => Ok(TenantScope::Tenant(normalize_tenant(&tenant)?)),
+        None => Ok(TenantScope::Tenant(admin.tenant_id.clone())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> TenantConfig {
+        TenantConfig {
+            header_name: "X-Tenant-ID".to_string(),
+            host_suffix: Some("auth.example.com".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_tenant_from_host() {
+        assert_eq!(tenant_from_host("acme.auth.example.com:8080", &config()), Some("acme".to_string()));
+        assert_eq!(tenant_from_host("auth.example.com", &config()), None);
+        assert_eq!(tenant_from_host("a.b.auth.example.com", &config()), None);
+        assert_eq!(tenant_from_host("acme.other.com", &config()), None);
+    }
+
+    #[test]
+    fn test_normalize_tenant() {
+        assert_eq!(normalize_tenant(" Acme-Corp "), Ok("acme-corp".to_string()));
+        assert!(normalize_tenant("acme/../other").is_err());
+        assert!(normalize_tenant("").is_err());
+    }
+}
@@ -0,0 +1,123 @@
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha1::Sha1;
+
+// RFC 6238 defaults understood by every authenticator app
+const DIGITS: u32 = 6;
+const STEP_SECONDS: i64 = 30;
+const SECRET_BYTES: usize = 20;
+// Codes from one step before or after are accepted to absorb clock drift
+const ALLOWED_DRIFT_STEPS: i64 = 1;
+
+// Random 160-bit secret, base32 encoded as authenticator apps expect
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; SECRET_BYTES];
+    rand::thread_rng().fill_bytes(&mut secret);
+    BASE32_NOPAD.encode(&secret)
+}
+
+// Enrollment URI, usually rendered as a QR code
+pub fn otpauth_uri(issuer: &str, account: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        percent_encode(issuer),
+        percent_encode(account),
+        secret,
+        percent_encode(issuer),
+        DIGITS,
+        STEP_SECONDS,
+    )
+}
+
+pub fn time_step(unix_seconds: i64) -> i64 {
+    unix_seconds.div_euclid(STEP_SECONDS)
+}
+
+// HOTP (RFC 4226) with dynamic truncation
+fn hotp(key: &[u8], counter: u64, digits: u32) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([digest[offset], digest[offset + 1], digest[offset + 2], digest[offset + 3]]) & 0x7fff_ffff;
+    binary % 10u32.pow(digits)
+}
+
+// Returns the time step the code belongs to, so the caller can refuse to accept it twice.
+// Steps up to `last_used_step` are rejected
+pub fn verify_code(secret: &str, code: &str, unix_seconds: i64, last_used_step: Option<i64>) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let key = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+
+    let current = time_step(unix_seconds);
+    (current - ALLOWED_DRIFT_STEPS..=current + ALLOWED_DRIFT_STEPS)
+        .filter(|step| *step >= 0 && last_used_step.is_none_or(|last| *step > last))
+        .find(|step| hotp(&key, *step as u64, DIGITS) == code)
+}
+
+fn percent_encode(value: &str) -> String {
+    value.bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+        _ => format!("%{:02X}", b),
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 6238 appendix B, SHA-1 key
+    const RFC_KEY: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn test_rfc6238_vectors() {
+        let vectors = [
+            (59, 94287082),
+            (1111111109, 7081804),
+            (1111111111, 14050471),
+            (1234567890, 89005924),
+            (2000000000, 69279037),
+            (20000000000, 65353130),
+        ];
+
+        for (time, expected) in vectors {
+            assert_eq!(hotp(RFC_KEY, time_step(time) as u64, 8), expected, "time {}", time);
+        }
+    }
+
+    #[test]
+    fn test_verify_code() {
+        let secret = BASE32_NOPAD.encode(RFC_KEY);
+        let now = 1111111109;
+        let code = format!("{:06}", hotp(RFC_KEY, time_step(now) as u64, 6));
+        let previous = format!("{:06}", hotp(RFC_KEY, time_step(now) as u64 - 1, 6));
+        let stale = format!("{:06}", hotp(RFC_KEY, time_step(now) as u64 - 5, 6));
+
+        assert_eq!(verify_code(&secret, &code, now, None), Some(time_step(now)));
+        assert_eq!(verify_code(&secret, &previous, now, None), Some(time_step(now) - 1));
+        assert_eq!(verify_code(&secret, &stale, now, None), None);
+        assert_eq!(verify_code(&secret, "12345", now, None), None);
+        assert_eq!(verify_code(&secret, "abcdef", now, None), None);
+
+        // A code cannot be replayed once its step has been used
+        assert_eq!(verify_code(&secret, &code, now, Some(time_step(now))), None);
+    }
+
+    #[test]
+    fn test_secret_and_uri() {
+        let secret = generate_secret();
+        assert_eq!(BASE32_NOPAD.decode(secret.as_bytes()).unwrap().len(), SECRET_BYTES);
+
+        let uri = otpauth_uri("Acme Auth", "jane@example.com", "JBSWY3DPEHPK3PXP");
+        assert_eq!(
+            uri,
+            "otpauth://totp/Acme%20Auth:jane%40example.com?secret=JBSWY3DPEHPK3PXP&issuer=Acme%20Auth&algorithm=SHA1&digits=6&period=30",
+        );
+    }
+}
@@ -0,0 +1,523 @@
+use ciborium::Value;
+use data_encoding::BASE64URL_NOPAD;
+use p256::ecdsa::signature::Verifier;
+use p256::ecdsa::{Signature, VerifyingKey};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::io::Cursor;
+use crate::config::WebauthnConfig;
+
+// Minimal WebAuthn relying party: ES256 (P-256) credentials, "none" attestation
+// or "packed" self attestation, as produced by platform and roaming passkeys
+
+// COSE algorithm identifier for ECDSA with P-256 and SHA-256
+pub const COSE_ALG_ES256: i64 = -7;
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+#[derive(Debug, PartialEq)]
+pub enum WebauthnError {
+    InvalidEncoding(&'static str),
+    ClientData(&'static str),
+    RpIdMismatch,
+    UserNotPresent,
+    UserNotVerified,
+    UnsupportedAttestation(String),
+    UnsupportedKey,
+    BadSignature,
+    // The authenticator's counter went backwards: the credential may have been cloned
+    CounterRegression,
+}
+
+impl fmt::Display for WebauthnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebauthnError::InvalidEncoding(what) => write!(f, "Invalid {}", what),
+            WebauthnError::ClientData(what) => write!(f, "Client data {}", what),
+            WebauthnError::RpIdMismatch => write!(f, "Credential is for another relying party"),
+            WebauthnError::UserNotPresent => write!(f, "User presence was not confirmed"),
+            WebauthnError::UserNotVerified => write!(f, "User verification is required"),
+            WebauthnError::UnsupportedAttestation(fmt) => write!(f, "Unsupported attestation format {}", fmt),
+            WebauthnError::UnsupportedKey => write!(f, "Only ES256 credentials are supported"),
+            WebauthnError::BadSignature => write!(f, "Signature verification failed"),
+            WebauthnError::CounterRegression => write!(f, "Signature counter did not increase"),
+        }
+    }
+}
+
+// Browser output of navigator.credentials.create(), as serialized by PublicKeyCredential.toJSON()
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationCredential {
+    pub id: String,
+    pub response: AttestationResponse,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "attestationObject")]
+    pub attestation_object: String,
+    #[serde(default)]
+    pub transports: Vec<String>,
+}
+
+// Browser output of navigator.credentials.get()
+#[derive(Debug, Deserialize)]
+pub struct AssertionCredential {
+    pub id: String,
+    pub response: AssertionResponse,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "authenticatorData")]
+    pub authenticator_data: String,
+    pub signature: String,
+    #[serde(rename = "userHandle", default)]
+    pub user_handle: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+// Credential descriptor used in allowCredentials and excludeCredentials
+#[derive(Debug, Serialize)]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub id: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub transports: Vec<String>,
+}
+
+impl CredentialDescriptor {
+    pub fn new(id: &str, transports: &[String]) -> Self {
+        Self { kind: "public-key", id: id.to_string(), transports: transports.to_vec() }
+    }
+}
+
+#[derive(Debug)]
+pub struct VerifiedRegistration {
+    pub credential_id: String,
+    // SEC1 uncompressed P-256 point, base64url
+    pub public_key: String,
+    pub sign_count: u32,
+    pub user_verified: bool,
+}
+
+#[derive(Debug)]
+pub struct VerifiedAssertion {
+    pub sign_count: u32,
+    pub user_verified: bool,
+}
+
+struct AuthenticatorData<'a> {
+    rp_id_hash: &'a [u8],
+    flags: u8,
+    sign_count: u32,
+    credential_id: Option<&'a [u8]>,
+    credential_key: Option<Value>,
+}
+
+pub fn new_challenge() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    BASE64URL_NOPAD.encode(&bytes)
+}
+
+fn decode(value: &str, what: &'static str) -> Result<Vec<u8>, WebauthnError> {
+    // Some clients keep the base64 padding
+    BASE64URL_NOPAD.decode(value.trim_end_matches('=').as_bytes()).map_err(|_| WebauthnError::InvalidEncoding(what))
+}
+
+// The challenge a response answers, used to find the stored ceremony before verifying it
+pub fn client_data_challenge(client_data_json: &str) -> Result<String, WebauthnError> {
+    let client_data: ClientData = serde_json::from_slice(&decode(client_data_json, "clientDataJSON")?)
+        .map_err(|_| WebauthnError::InvalidEncoding("clientDataJSON"))?;
+    Ok(client_data.challenge)
+}
+
+// Checks clientDataJSON and returns its SHA-256, which the authenticator signs
+fn verify_client_data(config: &WebauthnConfig, client_data_json: &str, kind: &str, challenge: &str) -> Result<Vec<u8>, WebauthnError> {
+    let raw = decode(client_data_json, "clientDataJSON")?;
+    let client_data: ClientData = serde_json::from_slice(&raw).map_err(|_| WebauthnError::InvalidEncoding("clientDataJSON"))?;
+
+    if client_data.kind != kind {
+        return Err(WebauthnError::ClientData("has the wrong type"));
+    }
+    if client_data.challenge.trim_end_matches('=') != challenge {
+        return Err(WebauthnError::ClientData("answers another challenge"));
+    }
+    if !config.origins.contains(&client_data.origin) {
+        return Err(WebauthnError::ClientData("comes from an unexpected origin"));
+    }
+
+    Ok(Sha256::digest(&raw).to_vec())
+}
+
+fn parse_authenticator_data(data: &[u8]) -> Result<AuthenticatorData<'_>, WebauthnError> {
+    let invalid = WebauthnError::InvalidEncoding("authenticator data");
+    if data.len() < 37 {
+        return Err(invalid);
+    }
+
+    let flags = data[32];
+    let sign_count = u32::from_be_bytes([data[33], data[34], data[35], data[36]]);
+    let (mut credential_id, mut credential_key) = (None, None);
+
+    if flags & FLAG_ATTESTED_CREDENTIAL != 0 {
+        // AAGUID (16 bytes), credential ID length (2 bytes), credential ID, COSE key
+        let rest = data.get(37 + 16..).ok_or(WebauthnError::InvalidEncoding("authenticator data"))?;
+        if rest.len() < 2 {
+            return Err(invalid);
+        }
+        let id_length = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+        let id = rest.get(2..2 + id_length).ok_or(WebauthnError::InvalidEncoding("authenticator data"))?;
+
+        let mut key_bytes = Cursor::new(&rest[2 + id_length..]);
+        let key: Value = ciborium::de::from_reader(&mut key_bytes).map_err(|_| WebauthnError::InvalidEncoding("credential public key"))?;
+
+        credential_id = Some(id);
+        credential_key = Some(key);
+    }
+
+    Ok(AuthenticatorData { rp_id_hash: &data[..32], flags, sign_count, credential_id, credential_key })
+}
+
+fn check_flags(config: &WebauthnConfig, auth_data: &AuthenticatorData, require_user_verification: bool) -> Result<(), WebauthnError> {
+    if auth_data.rp_id_hash != &Sha256::digest(config.rp_id.as_bytes())[..] {
+        return Err(WebauthnError::RpIdMismatch);
+    }
+    if auth_data.flags & FLAG_USER_PRESENT == 0 {
+        return Err(WebauthnError::UserNotPresent);
+    }
+    if require_user_verification && auth_data.flags & FLAG_USER_VERIFIED == 0 {
+        return Err(WebauthnError::UserNotVerified);
+    }
+    Ok(())
+}
+
+fn map_entry<'a>(map: &'a [(Value, Value)], key: &Value) -> Option<&'a Value> {
+    map.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+}
+
+// COSE_Key (RFC 9053) -> SEC1 uncompressed point; only EC2 / P-256 / ES256 keys are accepted
+fn cose_to_sec1(key: &Value) -> Result<Vec<u8>, WebauthnError> {
+    let map = key.as_map().ok_or(WebauthnError::UnsupportedKey)?;
+    let int = |label: i64| map_entry(map, &Value::Integer(label.into())).and_then(Value::as_integer).map(i128::from);
+    let bytes = |label: i64| map_entry(map, &Value::Integer(label.into())).and_then(Value::as_bytes);
+
+    // kty = EC2, alg = ES256, crv = P-256
+    if int(1) != Some(2) || int(3) != Some(COSE_ALG_ES256 as i128) || int(-1) != Some(1) {
+        return Err(WebauthnError::UnsupportedKey);
+    }
+    let (Some(x), Some(y)) = (bytes(-2), bytes(-3)) else {
+        return Err(WebauthnError::UnsupportedKey);
+    };
+    if x.len() != 32 || y.len() != 32 {
+        return Err(WebauthnError::UnsupportedKey);
+    }
+
+    let mut point = Vec::with_capacity(65);
+    point.push(0x04);
+    point.extend_from_slice(x);
+    point.extend_from_slice(y);
+
+    VerifyingKey::from_sec1_bytes(&point).map_err(|_| WebauthnError::UnsupportedKey)?;
+    Ok(point)
+}
+
+fn verify_signature(public_key: &[u8], message: &[u8], signature: &[u8]) -> Result<(), WebauthnError> {
+    let key = VerifyingKey::from_sec1_bytes(public_key).map_err(|_| WebauthnError::UnsupportedKey)?;
+    let signature = Signature::from_der(signature).map_err(|_| WebauthnError::BadSignature)?;
+    key.verify(message, &signature).map_err(|_| WebauthnError::BadSignature)
+}
+
+// Registration ceremony (WebAuthn §7.1)
+pub fn verify_registration(
+    config: &WebauthnConfig,
+    challenge: &str,
+    credential: &RegistrationCredential,
+    require_user_verification: bool,
+) -> Result<VerifiedRegistration, WebauthnError> {
+    let client_data_hash = verify_client_data(config, &credential.response.client_data_json, "webauthn.create", challenge)?;
+
+    let attestation: Value = ciborium::de::from_reader(decode(&credential.response.attestation_object, "attestationObject")?.as_slice())
+        .map_err(|_| WebauthnError::InvalidEncoding("attestationObject"))?;
+    let attestation = attestation.as_map().ok_or(WebauthnError::InvalidEncoding("attestationObject"))?;
+    let field = |name: &str| map_entry(attestation, &Value::Text(name.to_string()));
+
+    let format = field("fmt").and_then(Value::as_text).ok_or(WebauthnError::InvalidEncoding("attestationObject"))?;
+    let raw_auth_data = field("authData").and_then(Value::as_bytes).ok_or(WebauthnError::InvalidEncoding("attestationObject"))?;
+    let statement = field("attStmt").and_then(Value::as_map).ok_or(WebauthnError::InvalidEncoding("attestationObject"))?;
+
+    let auth_data = parse_authenticator_data(raw_auth_data)?;
+    check_flags(config, &auth_data, require_user_verification)?;
+
+    let (Some(credential_id), Some(credential_key)) = (auth_data.credential_id, &auth_data.credential_key) else {
+        return Err(WebauthnError::InvalidEncoding("attested credential data"));
+    };
+    if BASE64URL_NOPAD.encode(credential_id) != credential.id {
+        return Err(WebauthnError::InvalidEncoding("credential id"));
+    }
+    let public_key = cose_to_sec1(credential_key)?;
+
+    match format {
+        "none" => {}
+        // Self attestation: signed by the credential's own key. Attestation with a
+        // certificate chain (x5c) is not requested, so it is not accepted either
+        "packed" if map_entry(statement, &Value::Text("x5c".to_string())).is_none() => {
+            let alg = map_entry(statement, &Value::Text("alg".to_string())).and_then(Value::as_integer).map(i128::from);
+            if alg != Some(COSE_ALG_ES256 as i128) {
+                return Err(WebauthnError::UnsupportedKey);
+            }
+            let signature = map_entry(statement, &Value::Text("sig".to_string()))
+                .and_then(Value::as_bytes)
+                .ok_or(WebauthnError::InvalidEncoding("attestation statement"))?;
+
+            verify_signature(&public_key, &[raw_auth_data, client_data_hash.as_slice()].concat(), signature)?;
+        }
+        other => return Err(WebauthnError::UnsupportedAttestation(other.to_string())),
+    }
+
+    Ok(VerifiedRegistration {
+        credential_id: BASE64URL_NOPAD.encode(credential_id),
+        public_key: BASE64URL_NOPAD.encode(&public_key),
+        sign_count: auth_data.sign_count,
+        user_verified: auth_data.flags & FLAG_USER_VERIFIED != 0,
+    })
+}
+
+// Authentication ceremony (WebAuthn §7.2) against a stored credential
+pub fn verify_assertion(
+    config: &WebauthnConfig,
+    challenge: &str,
+    credential: &AssertionCredential,
+    public_key: &str,
+    stored_sign_count: u32,
+    require_user_verification: bool,
+) -> Result<VerifiedAssertion, WebauthnError> {
+    let client_data_hash = verify_client_data(config, &credential.response.client_data_json, "webauthn.get", challenge)?;
+
+    let raw_auth_data = decode(&credential.response.authenticator_data, "authenticatorData")?;
+    let auth_data = parse_authenticator_data(&raw_auth_data)?;
+    check_flags(config, &auth_data, require_user_verification)?;
+
+    let public_key = decode(public_key, "stored public key")?;
+    let signature = decode(&credential.response.signature, "signature")?;
+    verify_signature(&public_key, &[raw_auth_data.as_slice(), client_data_hash.as_slice()].concat(), &signature)?;
+
+    // Authenticators without a counter always report 0
+    if (auth_data.sign_count != 0 || stored_sign_count != 0) && auth_data.sign_count <= stored_sign_count {
+        return Err(WebauthnError::CounterRegression);
+    }
+
+    Ok(VerifiedAssertion {
+        sign_count: auth_data.sign_count,
+        user_verified: auth_data.flags & FLAG_USER_VERIFIED != 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p256::ecdsa::signature::Signer;
+    use p256::ecdsa::SigningKey;
+
+    fn config() -> WebauthnConfig {
+        WebauthnConfig {
+            rp_id: "example.com".to_string(),
+            rp_name: "Example".to_string(),
+            origins: vec!["https://example.com".to_string()],
+        }
+    }
+
+    // Software authenticator producing the same bytes a real one would
+    struct SoftAuthenticator {
+        key: SigningKey,
+        credential_id: Vec<u8>,
+        rp_id: String,
+        flags: u8,
+    }
+
+    impl SoftAuthenticator {
+        fn new() -> Self {
+            Self {
+                key: SigningKey::random(&mut rand::rngs::OsRng),
+                credential_id: b"soft-credential-1".to_vec(),
+                rp_id: "example.com".to_string(),
+                flags: FLAG_USER_PRESENT | FLAG_USER_VERIFIED,
+            }
+        }
+
+        fn cose_key(&self) -> Vec<u8> {
+            let point = self.key.verifying_key().to_encoded_point(false);
+            let key = Value::Map(vec![
+                (Value::Integer(1.into()), Value::Integer(2.into())),
+                (Value::Integer(3.into()), Value::Integer(COSE_ALG_ES256.into())),
+                (Value::Integer((-1).into()), Value::Integer(1.into())),
+                (Value::Integer((-2).into()), Value::Bytes(point.x().unwrap().to_vec())),
+                (Value::Integer((-3).into()), Value::Bytes(point.y().unwrap().to_vec())),
+            ]);
+            let mut bytes = Vec::new();
+            ciborium::ser::into_writer(&key, &mut bytes).unwrap();
+            bytes
+        }
+
+        fn auth_data(&self, sign_count: u32, attested: bool) -> Vec<u8> {
+            let mut data = Sha256::digest(self.rp_id.as_bytes()).to_vec();
+            data.push(self.flags | if attested { FLAG_ATTESTED_CREDENTIAL } else { 0 });
+            data.extend_from_slice(&sign_count.to_be_bytes());
+            if attested {
+                data.extend_from_slice(&[0u8; 16]);
+                data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+                data.extend_from_slice(&self.credential_id);
+                data.extend_from_slice(&self.cose_key());
+            }
+            data
+        }
+
+        fn client_data(kind: &str, challenge: &str, origin: &str) -> Vec<u8> {
+            serde_json::to_vec(&serde_json::json!({ "type": kind, "challenge": challenge, "origin": origin })).unwrap()
+        }
+
+        fn sign(&self, auth_data: &[u8], client_data: &[u8]) -> Vec<u8> {
+            let signature: Signature = self.key.sign(&[auth_data, &Sha256::digest(client_data)[..]].concat());
+            signature.to_der().as_bytes().to_vec()
+        }
+
+        fn register(&self, challenge: &str, origin: &str, format: &str) -> RegistrationCredential {
+            let auth_data = self.auth_data(0, true);
+            let client_data = Self::client_data("webauthn.create", challenge, origin);
+
+            let statement = match format {
+                "packed" => vec![
+                    (Value::Text("alg".to_string()), Value::Integer(COSE_ALG_ES256.into())),
+                    (Value::Text("sig".to_string()), Value::Bytes(self.sign(&auth_data, &client_data))),
+                ],
+                _ => vec![],
+            };
+            let attestation = Value::Map(vec![
+                (Value::Text("fmt".to_string()), Value::Text(format.to_string())),
+                (Value::Text("attStmt".to_string()), Value::Map(statement)),
+                (Value::Text("authData".to_string()), Value::Bytes(auth_data)),
+            ]);
+            let mut attestation_object = Vec::new();
+            ciborium::ser::into_writer(&attestation, &mut attestation_object).unwrap();
+
+            RegistrationCredential {
+                id: BASE64URL_NOPAD.encode(&self.credential_id),
+                response: AttestationResponse {
+                    client_data_json: BASE64URL_NOPAD.encode(&client_data),
+                    attestation_object: BASE64URL_NOPAD.encode(&attestation_object),
+                    transports: vec!["internal".to_string()],
+                },
+            }
+        }
+
+        fn assert(&self, challenge: &str, sign_count: u32) -> AssertionCredential {
+            let auth_data = self.auth_data(sign_count, false);
+            let client_data = Self::client_data("webauthn.get", challenge, "https://example.com");
+
+            AssertionCredential {
+                id: BASE64URL_NOPAD.encode(&self.credential_id),
+                response: AssertionResponse {
+                    client_data_json: BASE64URL_NOPAD.encode(&client_data),
+                    authenticator_data: BASE64URL_NOPAD.encode(&auth_data),
+                    signature: BASE64URL_NOPAD.encode(&self.sign(&auth_data, &client_data)),
+                    user_handle: None,
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn test_registration() {
+        let authenticator = SoftAuthenticator::new();
+        let challenge = new_challenge();
+
+        for format in ["none", "packed"] {
+            let credential = authenticator.register(&challenge, "https://example.com", format);
+            assert_eq!(client_data_challenge(&credential.response.client_data_json).unwrap(), challenge);
+
+            let verified = verify_registration(&config(), &challenge, &credential, true).unwrap();
+            assert_eq!(verified.credential_id, credential.id);
+            assert_eq!(verified.sign_count, 0);
+            assert!(verified.user_verified);
+        }
+    }
+
+    #[test]
+    fn test_registration_rejections() {
+        let authenticator = SoftAuthenticator::new();
+        let challenge = new_challenge();
+
+        let phished = authenticator.register(&challenge, "https://examp1e.com", "none");
+        assert!(matches!(verify_registration(&config(), &challenge, &phished, false), Err(WebauthnError::ClientData(_))));
+
+        let stale = authenticator.register(&new_challenge(), "https://example.com", "none");
+        assert!(matches!(verify_registration(&config(), &challenge, &stale, false), Err(WebauthnError::ClientData(_))));
+
+        let other_rp = SoftAuthenticator { rp_id: "evil.com".to_string(), ..SoftAuthenticator::new() };
+        let credential = other_rp.register(&challenge, "https://example.com", "none");
+        assert_eq!(verify_registration(&config(), &challenge, &credential, false).unwrap_err(), WebauthnError::RpIdMismatch);
+
+        let credential = authenticator.register(&challenge, "https://example.com", "fido-u2f");
+        assert!(matches!(verify_registration(&config(), &challenge, &credential, false), Err(WebauthnError::UnsupportedAttestation(_))));
+    }
+
+    #[test]
+    fn test_assertion() {
+        let authenticator = SoftAuthenticator::new();
+        let registration_challenge = new_challenge();
+        let registered = verify_registration(&config(), &registration_challenge, &authenticator.register(&registration_challenge, "https://example.com", "none"), true).unwrap();
+
+        let challenge = new_challenge();
+        let verified = verify_assertion(&config(), &challenge, &authenticator.assert(&challenge, 5), &registered.public_key, 4, true).unwrap();
+        assert_eq!(verified.sign_count, 5);
+        assert!(verified.user_verified);
+
+        // Counterless authenticators are fine, a counter going backwards is not
+        assert!(verify_assertion(&config(), &challenge, &authenticator.assert(&challenge, 0), &registered.public_key, 0, true).is_ok());
+        assert_eq!(
+            verify_assertion(&config(), &challenge, &authenticator.assert(&challenge, 3), &registered.public_key, 4, true).unwrap_err(),
+            WebauthnError::CounterRegression,
+        );
+    }
+
+    #[test]
+    fn test_assertion_rejections() {
+        let authenticator = SoftAuthenticator::new();
+        let registration_challenge = new_challenge();
+        let registered = verify_registration(&config(), &registration_challenge, &authenticator.register(&registration_challenge, "https://example.com", "none"), true).unwrap();
+        let challenge = new_challenge();
+
+        // Signed by a different key
+        let impostor = SoftAuthenticator::new();
+        assert_eq!(
+            verify_assertion(&config(), &challenge, &impostor.assert(&challenge, 1), &registered.public_key, 0, false).unwrap_err(),
+            WebauthnError::BadSignature,
+        );
+
+        let presence_only = SoftAuthenticator { flags: FLAG_USER_PRESENT, ..authenticator };
+        let assertion = presence_only.assert(&challenge, 1);
+        assert_eq!(
+            verify_assertion(&config(), &challenge, &assertion, &registered.public_key, 0, true).unwrap_err(),
+            WebauthnError::UserNotVerified,
+        );
+        assert!(!verify_assertion(&config(), &challenge, &assertion, &registered.public_key, 0, false).unwrap().user_verified);
+    }
+}
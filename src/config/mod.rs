@@ -1,69 +1,195 @@
 use std::env;
+use std::path::PathBuf;
 
 #[derive(Debug, Clone)]
-pub struct AppConfig {
-    pub database: DatabaseConfig,
-    pub server: ServerConfig,
-    pub jwt: JwtConfig,
-    pub logging: LoggingConfig,
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+}
+
+impl ServerConfig {
+    pub fn from_env() -> Self {
+        ServerConfig {
+            host: env::var("SERVER_HOST").unwrap_or_else(|_| "127.0.0.1".to_string()),
+            port: env::var("SERVER_PORT").ok().and_then(|port| port.parse().ok()).unwrap_or(8080),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
-pub struct DatabaseConfig {
-    pub uri: String,
-    pub database_name: String,
+pub struct KongConfig {
+    pub admin_url: Option<String>,
+    pub admin_token: Option<String>,
+}
+
+impl KongConfig {
+    pub fn from_env() -> Self {
+        KongConfig {
+            admin_url: env::var("KONG_ADMIN_URL").ok().filter(|url| !url.is_empty()),
+            admin_token: env::var("KONG_ADMIN_TOKEN").ok(),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
-pub struct ServerConfig {
+pub struct TenantConfig {
+    pub header_name: String,
+    // e.g. "auth.example.com" resolves acme.auth.example.com to tenant "acme"
+    pub host_suffix: Option<String>,
+}
+
+impl TenantConfig {
+    pub fn from_env() -> Self {
+        TenantConfig {
+            header_name: env::var("TENANT_HEADER").unwrap_or_else(|_| "X-Tenant-ID".to_string()),
+            host_suffix: env::var("TENANT_HOST_SUFFIX").ok().filter(|suffix| !suffix.is_empty()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MailConfig {
+    pub from: String,
+    // Frontend base URL used to build links in emails
+    pub app_base_url: String,
+    pub transport: MailTransport,
+    // Delivery attempts per email before it is dropped
+    pub max_attempts: u32,
+}
+
+#[derive(Debug, Clone)]
+pub enum MailTransport {
+    // Print to stdout
+    Log,
+    // Write each email as a JSON file into a directory
+    Outbox(PathBuf),
+    Smtp(SmtpConfig),
+}
+
+#[derive(Debug, Clone)]
+pub struct SmtpConfig {
     pub host: String,
-    pub port: u16,
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    // "starttls" (default), "tls" or "none"
+    pub tls: String,
+}
+
+impl MailConfig {
+    pub fn from_env() -> Self {
+        let transport = match env::var("MAIL_TRANSPORT").unwrap_or_default().to_lowercase().as_str() {
+            "smtp" => MailTransport::Smtp(SmtpConfig {
+                host: env::var("SMTP_HOST").unwrap_or_else(|_| "localhost".to_string()),
+                port: env::var("SMTP_PORT").ok().and_then(|port| port.parse().ok()),
+                username: env::var("SMTP_USERNAME").ok().filter(|v| !v.is_empty()),
+                password: env::var("SMTP_PASSWORD").ok().filter(|v| !v.is_empty()),
+                tls: env::var("SMTP_TLS").unwrap_or_else(|_| "starttls".to_string()).to_lowercase(),
+            }),
+            "outbox" => MailTransport::Outbox(PathBuf::from(
+                env::var("MAIL_OUTBOX_DIR").unwrap_or_else(|_| "./outbox".to_string()),
+            )),
+            _ => MailTransport::Log,
+        };
+
+        MailConfig {
+            from: env::var("MAIL_FROM").unwrap_or_else(|_| "no-reply@localhost".to_string()),
+            app_base_url: env::var("APP_BASE_URL").unwrap_or_else(|_| "http://localhost:8080".to_string()),
+            transport,
+            max_attempts: env::var("MAIL_MAX_ATTEMPTS").ok().and_then(|v| v.parse().ok()).unwrap_or(5),
+        }
+    }
+}
+
+// What login does for users whose email is not verified yet
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnverifiedLoginPolicy {
+    Allow,
+    // Token without roles beyond "unverified"
+    Limited,
+    Reject,
 }
 
 #[derive(Debug, Clone)]
-pub struct JwtConfig {
-    pub secret: String,
-    pub expiration_hours: i64,
-    pub audience: String,
-    pub issuer: String,
+pub struct EmailVerificationConfig {
+    pub unverified_login: UnverifiedLoginPolicy,
+}
+
+impl EmailVerificationConfig {
+    pub fn from_env() -> Self {
+        let unverified_login = match env::var("UNVERIFIED_LOGIN_POLICY").unwrap_or_default().to_lowercase().as_str() {
+            "limited" => UnverifiedLoginPolicy::Limited,
+            "reject" => UnverifiedLoginPolicy::Reject,
+            _ => UnverifiedLoginPolicy::Allow,
+        };
+
+        EmailVerificationConfig { unverified_login }
+    }
 }
 
 #[derive(Debug, Clone)]
-pub struct LoggingConfig {
-    pub level: String,
-    pub enable_file_logging: bool,
-}
-
-impl AppConfig {
-    pub fn from_env() -> Result<Self, env::VarError> {
-        Ok(AppConfig {
-            database: DatabaseConfig {
-                uri: env::var("MONGODB_URI")?,
-                database_name: env::var("MONGODB_DB").unwrap_or_else(|_| "kong_security_api".to_string()),
-            },
-            server: ServerConfig {
-                host: env::var("SERVER_HOST").unwrap_or_else(|_| "127.0.0.1".to_string()),
-                port: env::var("SERVER_PORT")
-                    .unwrap_or_else(|_| "8080".to_string())
-                    .parse()
-                    .unwrap_or(8080),
-            },
-            jwt: JwtConfig {
-                secret: env::var("JWT_SECRET")?,
-                expiration_hours: env::var("JWT_EXPIRATION_HOURS")
-                    .unwrap_or_else(|_| "2".to_string())
-                    .parse()
-                    .unwrap_or(2),
-                audience: env::var("JWT_AUDIENCE").unwrap_or_else(|_| "kong_security_api".to_string()),
-                issuer: env::var("JWT_ISSUER").unwrap_or_else(|_| "kong_security_api".to_string()),
-            },
-            logging: LoggingConfig {
-                level: env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string()),
-                enable_file_logging: env::var("ENABLE_FILE_LOGGING")
-                    .unwrap_or_else(|_| "false".to_string())
-                    .parse()
-                    .unwrap_or(false),
-            },
-        })
+pub struct MfaConfig {
+    // Shown by authenticator apps next to the account
+    pub totp_issuer: String,
+}
+
+impl MfaConfig {
+    pub fn from_env() -> Self {
+        MfaConfig {
+            totp_issuer: env::var("TOTP_ISSUER").unwrap_or_else(|_| "Kong Security API".to_string()),
+        }
     }
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone)]
+pub struct WebauthnConfig {
+    // Domain passkeys are bound to, e.g. "example.com"
+    pub rp_id: String,
+    pub rp_name: String,
+    // Exact origins allowed to run the ceremonies, e.g. "https://app.example.com"
+    pub origins: Vec<String>,
+}
+
+impl WebauthnConfig {
+    pub fn from_env() -> Self {
+        WebauthnConfig {
+            rp_id: env::var("WEBAUTHN_RP_ID").unwrap_or_else(|_| "localhost".to_string()),
+            rp_name: env::var("WEBAUTHN_RP_NAME").unwrap_or_else(|_| "Kong Security API".to_string()),
+            origins: env::var("WEBAUTHN_ORIGINS")
+                .unwrap_or_else(|_| "http://localhost:3000".to_string())
+                .split(',')
+                .map(|origin| origin.trim().trim_end_matches('/').to_string())
+                .filter(|origin| !origin.is_empty())
+                .collect(),
+        }
+    }
+}
+
+// Cookie mode for browser apps: tokens travel in HttpOnly cookies instead of the response body
+#[derive(Debug, Clone)]
+pub struct SessionCookieConfig {
+    pub enabled: bool,
+    // Also put the access token in a cookie; otherwise only the refresh token is
+    pub access_token: bool,
+    pub domain: Option<String>,
+    pub path: String,
+    // "strict" (default), "lax" or "none"
+    pub same_site: String,
+}
+
+impl SessionCookieConfig {
+    pub fn from_env() -> Self {
+        SessionCookieConfig {
+            enabled: env::var("SESSION_COOKIES").map(|v| v == "true" || v == "1").unwrap_or(false),
+            access_token: env::var("SESSION_COOKIE_ACCESS_TOKEN").map(|v| v == "true" || v == "1").unwrap_or(false),
+            domain: env::var("SESSION_COOKIE_DOMAIN").ok().filter(|domain| !domain.is_empty()),
+            path: env::var("SESSION_COOKIE_PATH").unwrap_or_else(|_| "/api".to_string()),
+            same_site: env::var("SESSION_COOKIE_SAME_SITE").unwrap_or_else(|_| "strict".to_string()).to_lowercase(),
+        }
+    }
+
+    // The access token cookie is only used when the refresh token is in one too
+    pub fn access_token_cookie(&self) -> bool {
+        self.enabled && self.access_token
+    }
+}
@@ -11,6 +11,25 @@ use dotenv::dotenv;
 use database::connect_to_database;
 use api::handlers::auth_handlers::*;
 use api::handlers::log_handlers::*;
+use api::handlers::kong_handlers::*;
+use api::handlers::org_handlers::*;
+use api::handlers::group_handlers::*;
+use api::handlers::impersonation_handlers::*;
+use api::handlers::admin_user_handlers::*;
+use api::handlers::profile_handlers::*;
+use api::handlers::password_handlers::*;
+use api::handlers::email_handlers::*;
+use api::handlers::security_handlers::*;
+use api::handlers::mfa_handlers::*;
+use api::handlers::webauthn_handlers::*;
+use api::handlers::magic_link_handlers::*;
+use api::handlers::email_code_handlers::*;
+use api::handlers::session_handlers::*;
+use config::{EmailVerificationConfig, KongConfig, MailConfig, MfaConfig, ServerConfig, SessionCookieConfig, TenantConfig, WebauthnConfig};
+use services::{KongService, MailService};
+use auth::breached_passwords::BreachedPasswords;
+use auth::{HashingPool, PasswordHasher, PasswordPolicy};
+use std::path::Path;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -18,30 +37,125 @@ async fn main() -> std::io::Result<()> {
     dotenv().ok();
     env_logger::init();
 
+    // `build-breached-filter <dump> <out.bin>` converts a breached password dump and exits
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("build-breached-filter") {
+        let (Some(dump), Some(out)) = (args.get(2), args.get(3)) else {
+            eprintln!("Usage: {} build-breached-filter <dump> <out.bin>", args[0]);
+            std::process::exit(2);
+        };
+        let count = BreachedPasswords::build(Path::new(dump), Path::new(out))?;
+        println!("Wrote a filter of {} breached password hashes to {}", count, out);
+        return Ok(());
+    }
+
     let db = connect_to_database().await.expect("Failed to connect to database");
+    let kong = web::Data::new(KongService::new(KongConfig::from_env()));
+    let mail = web::Data::new(MailService::new(MailConfig::from_env()));
+    // Loaded once; the breached password list can be large
+    let password_policy = web::Data::new(PasswordPolicy::from_env());
+    // One pool shared by all workers so the concurrency limit is process-wide
+    let password_hasher = web::Data::new(HashingPool::from_env(PasswordHasher::from_env()));
+    let server = ServerConfig::from_env();
     println!("Server started");
 
     HttpServer::new(move || {
         App::new()
             .wrap(Logger::default())
             .app_data(web::Data::new(db.clone()))
+            .app_data(kong.clone())
+            .app_data(mail.clone())
+            .app_data(web::Data::new(TenantConfig::from_env()))
+            .app_data(web::Data::new(EmailVerificationConfig::from_env()))
+            .app_data(web::Data::new(MfaConfig::from_env()))
+            .app_data(web::Data::new(WebauthnConfig::from_env()))
+            .app_data(web::Data::new(SessionCookieConfig::from_env()))
+            .app_data(password_policy.clone())
+            .app_data(password_hasher.clone())
             .service(
                 web::scope("/api")
                     .route("/login", web::post().to(login))
+                    .route("/login/mfa", web::post().to(login_mfa))
+                    .route("/login/mfa/webauthn/begin", web::post().to(begin_mfa_passkey))
+                    .route("/login/mfa/webauthn/finish", web::post().to(finish_mfa_passkey))
+                    .route("/login/webauthn/begin", web::post().to(begin_passkey_login))
+                    .route("/login/webauthn/finish", web::post().to(finish_passkey_login))
+                    .route("/login/magic-link", web::post().to(request_magic_link))
+                    .route("/login/magic-link/consume", web::post().to(consume_magic_link))
+                    .route("/login/email-code", web::post().to(request_login_code))
+                    .route("/login/email-code/verify", web::post().to(verify_login_code))
+                    .route("/token/refresh", web::post().to(refresh_token))
+                    .route("/logout", web::post().to(logout))
                     .route("/register", web::post().to(register))
                     .route("/protected", web::get().to(protected))
+                    .route("/impersonation/end", web::post().to(end_impersonation))
+                    .route("/me", web::get().to(get_me))
+                    .route("/me", web::patch().to(update_me))
+                    .route("/me/password", web::post().to(change_password))
+                    .route("/me/sessions", web::get().to(list_my_sessions))
+                    .route("/me/sessions", web::delete().to(revoke_my_sessions))
+                    .route("/me/sessions/{session_id}", web::delete().to(revoke_my_session))
+                    .route("/me/mfa/totp", web::post().to(enroll_totp))
+                    .route("/me/mfa/totp", web::delete().to(disable_totp))
+                    .route("/me/mfa/totp/confirm", web::post().to(confirm_totp))
+                    .route("/me/mfa/recovery-codes", web::post().to(regenerate_recovery_codes))
+                    .route("/me/webauthn/register/begin", web::post().to(begin_passkey_registration))
+                    .route("/me/webauthn/register/finish", web::post().to(finish_passkey_registration))
+                    .route("/me/webauthn/credentials", web::get().to(list_passkeys))
+                    .route("/me/webauthn/credentials/{credential_id}", web::delete().to(delete_passkey))
+                    .route("/password/forgot", web::post().to(forgot_password))
+                    .route("/password/reset", web::post().to(reset_password))
+                    .route("/email/verify", web::post().to(verify_email))
+                    .route("/email/resend", web::post().to(resend_verification))
                     .service(
                         web::scope("/logs")
                             .route("/my-logins", web::get().to(get_my_logs))
                     )
+                    .service(
+                        web::scope("/orgs")
+                            .route("", web::post().to(create_organization))
+                            .route("", web::get().to(list_my_organizations))
+                            .route("/{org_id}/members", web::get().to(list_members))
+                            .route("/{org_id}/members", web::post().to(invite_member))
+                            .route("/{org_id}/members/{user_id}", web::delete().to(remove_member))
+                            .route("/{org_id}/members/{user_id}/roles", web::put().to(update_member_roles))
+                            .route("/{org_id}/accept", web::post().to(accept_invitation))
+                            .route("/{org_id}/token", web::post().to(switch_organization))
+                    )
                     .service(
                         web::scope("/admin")
                             .route("/logs", web::get().to(get_all_logs))
                             .route("/logs/stats", web::get().to(get_login_stats))
+                            .route("/kong/consumers/{user_id}", web::post().to(sync_kong_consumer))
+                            .route("/keys/rotate", web::post().to(rotate_jwt_signing_key))
+                            .route("/groups", web::post().to(create_group))
+                            .route("/groups", web::get().to(list_groups))
+                            .route("/groups/{group_id}", web::get().to(get_group))
+                            .route("/groups/{group_id}", web::patch().to(update_group))
+                            .route("/groups/{group_id}", web::delete().to(delete_group))
+                            .route("/groups/{group_id}/members", web::post().to(add_group_member))
+                            .route("/groups/{group_id}/members/{user_id}", web::delete().to(remove_group_member))
+                            .route("/audit-logs", web::get().to(get_audit_logs))
+                            .route("/impersonate/{user_id}", web::post().to(start_impersonation))
+                            .route("/users", web::get().to(list_users))
+                            .route("/users/{user_id}", web::get().to(get_user))
+                            .route("/users/{user_id}", web::patch().to(update_user))
+                            .route("/users/{user_id}", web::delete().to(delete_user))
+                            .route("/users/{user_id}/unlock", web::post().to(unlock_user))
+                            .route("/users/{user_id}/sessions", web::get().to(list_user_sessions))
+                            .route("/users/{user_id}/sessions", web::delete().to(revoke_user_sessions))
+                            .route("/users/{user_id}/sessions/{session_id}", web::delete().to(revoke_user_session))
+                            .route("/security/lockout", web::get().to(get_lockout_policy))
+                            .route("/security/lockout", web::put().to(update_lockout_policy))
+                            .route("/security/sessions", web::get().to(get_session_policy))
+                            .route("/security/sessions", web::put().to(update_session_policy))
+                            .route("/security/breached-passwords", web::get().to(get_breach_check))
+                            .route("/security/breached-passwords", web::put().to(update_breach_check))
+                            .route("/security/hashing", web::get().to(get_hashing_stats))
                     )
             )
     })
-    .bind(("127.0.0.1", 8080))
+    .bind((server.host.as_str(), server.port))
     .expect("Failed to bind server to address")
     .run()
     .await
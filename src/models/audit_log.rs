@@ -0,0 +1,54 @@
+use serde::{Serialize, Deserialize};
+use mongodb::bson::{oid::ObjectId, DateTime, Document};
+use std::time::SystemTime;
+use crate::models::User;
+
+// Record of an administrative change, kept separately from login attempts
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuditLog {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _id: Option<ObjectId>,
+    pub tenant_id: String,
+
+    // Who made the change
+    pub actor_id: String,
+    pub actor_email: String,
+
+    // What changed, e.g. "group.member_added" on target_type "group"
+    pub action: String,
+    pub target_type: String,
+    pub target_id: String,
+    pub details: Option<Document>,
+
+    pub timestamp: DateTime,
+}
+
+impl AuditLog {
+    pub fn new(
+        tenant_id: &str,
+        actor: &User,
+        action: &str,
+        target_type: &str,
+        target_id: &str,
+        details: Option<Document>,
+    ) -> Self {
+        Self {
+            _id: Some(ObjectId::new()),
+            tenant_id: tenant_id.to_string(),
+            actor_id: actor._id.map(|id| id.to_hex()).unwrap_or_default(),
+            actor_email: actor.email.clone(),
+            action: action.to_string(),
+            target_type: target_type.to_string(),
+            target_id: target_id.to_string(),
+            details,
+            timestamp: DateTime::from_system_time(SystemTime::now()),
+        }
+    }
+
+    // For changes made on someone's behalf, e.g. by an admin impersonating a user
+    pub fn with_actor(mut self, actor_id: &str, actor_email: &str) -> Self {
+        self.actor_id = actor_id.to_string();
+        self.actor_email = actor_email.to_string();
+        self
+    }
+}
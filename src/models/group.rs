@@ -0,0 +1,62 @@
+use serde::{Serialize, Deserialize};
+use mongodb::bson::{oid::ObjectId, DateTime};
+use std::time::SystemTime;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Group {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _id: Option<ObjectId>,
+    pub tenant_id: String,
+    pub name: String,
+    pub description: Option<String>,
+    // Roles every member inherits
+    pub roles: Vec<String>,
+    pub member_ids: Vec<String>,
+    pub created_at: DateTime,
+    pub updated_at: DateTime,
+}
+
+impl Group {
+    pub fn new(tenant_id: String, name: String, description: Option<String>, roles: Vec<String>) -> Self {
+        let now = DateTime::from_system_time(SystemTime::now());
+        Self {
+            _id: Some(ObjectId::new()),
+            tenant_id,
+            name,
+            description,
+            roles,
+            member_ids: Vec::new(),
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+// Union of the roles of the groups, in order of first appearance
+pub fn inherited_roles<'a>(groups: impl IntoIterator<Item = &'a Group>) -> Vec<String> {
+    let mut roles: Vec<String> = Vec::new();
+    for role in groups.into_iter().flat_map(|group| &group.roles) {
+        if !roles.contains(role) {
+            roles.push(role.clone());
+        }
+    }
+    roles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(name: &str, roles: &[&str]) -> Group {
+        Group::new("acme".to_string(), name.to_string(), None, roles.iter().map(|r| r.to_string()).collect())
+    }
+
+    #[test]
+    fn test_inherited_roles_are_the_union_of_all_groups() {
+        let groups = [group("SRE on-call", &["pager", "deployer"]), group("Support L2", &["support", "pager"])];
+
+        assert_eq!(inherited_roles(&groups), vec!["pager", "deployer", "support"]);
+        assert!(inherited_roles(&[group("Empty", &[])]).is_empty());
+        assert!(inherited_roles(&[]).is_empty());
+    }
+}
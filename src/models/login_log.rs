@@ -3,6 +3,11 @@ use mongodb::bson::{oid::ObjectId, DateTime};
 use std::time::SystemTime;
 use chrono;
 use crate::utils::user_agent_parser::UserAgentInfo;
+use crate::models::tenant::default_tenant_id;
+
+fn default_login_method() -> String {
+    "password".to_string()
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct LoginLog {
@@ -10,12 +15,22 @@ pub struct LoginLog {
     pub _id: Option<ObjectId>,
     
     // User information
+    #[serde(default = "default_tenant_id")]
+    pub tenant_id: String,
     pub user_id: Option<String>, // Some if login successful, None if failed
     pub email: String,
     
     // Login attempt details
     pub success: bool,
     pub failure_reason: Option<String>,
+    #[serde(default = "default_login_method")]
+    pub login_method: String, // password, impersonation, ...
+    // Password accepted, waiting for the second factor; the MFA step is logged separately
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub mfa_pending: bool,
+    // Admin who started an impersonated session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub impersonator_id: Option<String>,
     
     // Request information
     pub ip_address: Option<String>,
@@ -43,6 +58,7 @@ pub struct LoginLog {
 
 impl LoginLog {
     pub fn new(
+        tenant_id: String,
         email: String,
         success: bool,
         ip_address: Option<String>,
@@ -56,10 +72,14 @@ impl LoginLog {
         
         Self {
             _id: Some(ObjectId::new()),
+            tenant_id,
             user_id: None,
             email,
             success,
             failure_reason: None,
+            login_method: default_login_method(),
+            mfa_pending: false,
+            impersonator_id: None,
             ip_address,
             user_agent: user_agent.clone(),
             request_method: "POST".to_string(),
@@ -86,6 +106,13 @@ impl LoginLog {
         self.failure_reason = None;
     }
     
+    pub fn set_mfa_challenge(&mut self, user_id: String) {
+        self.user_id = Some(user_id);
+        self.success = false;
+        self.mfa_pending = true;
+        self.failure_reason = None;
+    }
+
     pub fn set_failure(&mut self, reason: String) {
         self.success = false;
         self.failure_reason = Some(reason);
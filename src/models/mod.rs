@@ -1,5 +1,19 @@
 pub mod user;
 pub mod login_log;
+pub mod tenant;
+pub mod organization;
+pub mod group;
+pub mod audit_log;
+pub mod security_settings;
+pub mod webauthn_credential;
+pub mod session;
 
-pub use user::User;
-pub use login_log::{LoginLog, LoginStats};
\ No newline at end of file
+pub use user::{User, UserSummary, ProfileUpdate};
+pub use login_log::{LoginLog, LoginStats};
+pub use tenant::{TenantScope, DEFAULT_TENANT_ID};
+pub use organization::{Organization, Membership, MembershipStatus};
+pub use group::{inherited_roles, Group};
+pub use audit_log::AuditLog;
+pub use security_settings::{LockoutPolicy, SecuritySettings, SessionLimitAction, SessionPolicy};
+pub use webauthn_credential::{WebauthnChallenge, WebauthnCredential, WebauthnCredentialSummary};
+pub use session::{Session, SessionSummary};
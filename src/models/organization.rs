@@ -0,0 +1,139 @@
+use serde::{Serialize, Deserialize};
+use mongodb::bson::{oid::ObjectId, DateTime};
+use std::time::SystemTime;
+
+// Roles a member can hold inside an organization
+pub const ORG_ROLES: [&str; 4] = ["owner", "admin", "member", "viewer"];
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Organization {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _id: Option<ObjectId>,
+    pub tenant_id: String,
+    pub name: String,
+    pub created_by: String,
+    pub created_at: DateTime,
+    pub updated_at: DateTime,
+}
+
+impl Organization {
+    pub fn new(tenant_id: String, name: String, created_by: String) -> Self {
+        let now = DateTime::from_system_time(SystemTime::now());
+        Self {
+            _id: Some(ObjectId::new()),
+            tenant_id,
+            name,
+            created_by,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MembershipStatus {
+    Invited,
+    Active,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Membership {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _id: Option<ObjectId>,
+    pub tenant_id: String,
+    pub org_id: ObjectId,
+    pub user_id: String,
+    pub roles: Vec<String>,
+    pub status: MembershipStatus,
+    pub invited_by: Option<String>,
+    pub created_at: DateTime,
+    pub updated_at: DateTime,
+}
+
+impl Membership {
+    pub fn new(
+        tenant_id: String,
+        org_id: ObjectId,
+        user_id: String,
+        roles: Vec<String>,
+        status: MembershipStatus,
+        invited_by: Option<String>,
+    ) -> Self {
+        let now = DateTime::from_system_time(SystemTime::now());
+        Self {
+            _id: Some(ObjectId::new()),
+            tenant_id,
+            org_id,
+            user_id,
+            roles,
+            status,
+            invited_by,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.status == MembershipStatus::Active
+    }
+
+    pub fn is_owner(&self) -> bool {
+        self.roles.iter().any(|r| r == "owner")
+    }
+
+    // Owners and admins can invite, remove and change roles of other members
+    pub fn can_manage_members(&self) -> bool {
+        self.is_active() && self.roles.iter().any(|r| r == "owner" || r == "admin")
+    }
+}
+
+pub fn validate_org_roles(roles: &[String]) -> Result<(), String> {
+    if roles.is_empty() {
+        return Err("At least one role is required".to_string());
+    }
+
+    match roles.iter().find(|role| !ORG_ROLES.contains(&role.as_str())) {
+        Some(role) => Err(format!("Unknown organization role: {}", role)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn membership(roles: &[&str], status: MembershipStatus) -> Membership {
+        Membership::new(
+            "acme".to_string(),
+            ObjectId::new(),
+            "user-1".to_string(),
+            roles.iter().map(|r| r.to_string()).collect(),
+            status,
+            None,
+        )
+    }
+
+    #[test]
+    fn test_membership_roles_and_status() {
+        let invited_admin = membership(&["admin"], MembershipStatus::Invited);
+        assert!(!invited_admin.is_active());
+        assert!(!invited_admin.can_manage_members());
+
+        let owner = membership(&["owner"], MembershipStatus::Active);
+        assert!(owner.is_owner());
+        assert!(owner.can_manage_members());
+
+        let viewer = membership(&["viewer"], MembershipStatus::Active);
+        assert!(viewer.is_active());
+        assert!(!viewer.is_owner());
+        assert!(!viewer.can_manage_members());
+    }
+
+    #[test]
+    fn test_validate_org_roles() {
+        assert!(validate_org_roles(&["admin".to_string(), "viewer".to_string()]).is_ok());
+        assert!(validate_org_roles(&[]).is_err());
+        assert!(validate_org_roles(&["super_admin".to_string()]).is_err());
+    }
+}
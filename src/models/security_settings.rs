@@ -0,0 +1,228 @@
+use serde::{Serialize, Deserialize};
+use mongodb::bson::DateTime;
+
+// Per-tenant security settings that admins can change at runtime
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SecuritySettings {
+    pub tenant_id: String,
+    #[serde(default)]
+    pub lockout: LockoutPolicy,
+    // Check passwords against the breached list on successful logins and flag matching users
+    #[serde(default)]
+    pub check_breached_on_login: bool,
+    #[serde(default)]
+    pub sessions: SessionPolicy,
+    pub updated_at: DateTime,
+    pub updated_by: Option<String>,
+}
+
+impl SecuritySettings {
+    pub fn new(tenant_id: String) -> Self {
+        Self {
+            tenant_id,
+            lockout: LockoutPolicy::default(),
+            check_breached_on_login: false,
+            sessions: SessionPolicy::default(),
+            updated_at: DateTime::now(),
+            updated_by: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct LockoutPolicy {
+    pub enabled: bool,
+    // Consecutive failures within the window that lock the account
+    pub max_failures: u32,
+    pub window_minutes: i64,
+    // First lockout duration; doubles with every further lockout up to max_lockout_minutes
+    pub lockout_minutes: i64,
+    pub max_lockout_minutes: i64,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_failures: 5,
+            window_minutes: 15,
+            lockout_minutes: 15,
+            max_lockout_minutes: 24 * 60,
+        }
+    }
+}
+
+impl LockoutPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_failures == 0 {
+            return Err("max_failures must be at least 1".to_string());
+        }
+        if self.window_minutes <= 0 || self.lockout_minutes <= 0 {
+            return Err("window_minutes and lockout_minutes must be positive".to_string());
+        }
+        if self.max_lockout_minutes < self.lockout_minutes {
+            return Err("max_lockout_minutes cannot be lower than lockout_minutes".to_string());
+        }
+        Ok(())
+    }
+
+    // Duration of the nth lockout in a row (starting at 1)
+    pub fn lockout_duration_minutes(&self, lockout_count: u32) -> i64 {
+        let factor = 2_i64.saturating_pow(lockout_count.saturating_sub(1));
+        self.lockout_minutes.saturating_mul(factor).min(self.max_lockout_minutes)
+    }
+
+    // Failures before this no longer count
+    pub fn window_start(&self, now: DateTime) -> DateTime {
+        DateTime::from_millis(now.timestamp_millis() - self.window_minutes * 60 * 1000)
+    }
+
+    // End of the nth lockout in a row starting at `now`
+    pub fn locked_until(&self, lockout_count: u32, now: DateTime) -> DateTime {
+        DateTime::from_millis(now.timestamp_millis() + self.lockout_duration_minutes(lockout_count) * 60 * 1000)
+    }
+}
+
+// What a login does when the user already has the maximum number of sessions
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionLimitAction {
+    #[default]
+    EvictOldest,
+    Reject,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct SessionPolicy {
+    #[serde(default)]
+    pub on_limit: SessionLimitAction,
+    // Applies to every user; a role can set a shorter one
+    #[serde(default)]
+    pub idle_timeout_minutes: Option<i64>,
+    #[serde(default)]
+    pub roles: Vec<RoleSessionPolicy>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RoleSessionPolicy {
+    pub role: String,
+    #[serde(default)]
+    pub max_sessions: Option<u32>,
+    #[serde(default)]
+    pub idle_timeout_minutes: Option<i64>,
+}
+
+// Limits that apply to one user
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SessionLimits {
+    pub max_sessions: Option<u32>,
+    pub idle_timeout_minutes: Option<i64>,
+}
+
+impl SessionPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.idle_timeout_minutes.is_some_and(|minutes| minutes <= 0) {
+            return Err("idle_timeout_minutes must be positive".to_string());
+        }
+        for (i, role) in self.roles.iter().enumerate() {
+            if role.role.trim().is_empty() {
+                return Err("role cannot be empty".to_string());
+            }
+            if self.roles[..i].iter().any(|other| other.role == role.role) {
+                return Err(format!("role {} is listed twice", role.role));
+            }
+            if role.max_sessions == Some(0) {
+                return Err("max_sessions must be at least 1".to_string());
+            }
+            if role.idle_timeout_minutes.is_some_and(|minutes| minutes <= 0) {
+                return Err("idle_timeout_minutes must be positive".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    // The strictest limits among the user's roles
+    pub fn limits_for(&self, roles: &[String]) -> SessionLimits {
+        let matching: Vec<&RoleSessionPolicy> = self.roles.iter()
+            .filter(|policy| roles.contains(&policy.role))
+            .collect();
+
+        SessionLimits {
+            max_sessions: matching.iter().filter_map(|policy| policy.max_sessions).min(),
+            idle_timeout_minutes: matching.iter()
+                .filter_map(|policy| policy.idle_timeout_minutes)
+                .chain(self.idle_timeout_minutes)
+                .min(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lockout_duration_backoff() {
+        let policy = LockoutPolicy { lockout_minutes: 10, max_lockout_minutes: 60, ..LockoutPolicy::default() };
+
+        assert_eq!(policy.lockout_duration_minutes(1), 10);
+        assert_eq!(policy.lockout_duration_minutes(2), 20);
+        assert_eq!(policy.lockout_duration_minutes(3), 40);
+        assert_eq!(policy.lockout_duration_minutes(4), 60);
+        assert_eq!(policy.lockout_duration_minutes(100), 60);
+    }
+
+    #[test]
+    fn test_lockout_window_and_end() {
+        let policy = LockoutPolicy { window_minutes: 10, lockout_minutes: 5, ..LockoutPolicy::default() };
+        let now = DateTime::from_millis(1_700_000_000_000);
+        let minutes = |m: i64| DateTime::from_millis(now.timestamp_millis() + m * 60 * 1000);
+
+        assert_eq!(policy.window_start(now), minutes(-10));
+        assert_eq!(policy.locked_until(1, now), minutes(5));
+        // The second lockout in a row lasts twice as long
+        assert_eq!(policy.locked_until(2, now), minutes(10));
+    }
+
+    #[test]
+    fn test_lockout_policy_validation() {
+        assert!(LockoutPolicy::default().validate().is_ok());
+        assert!(LockoutPolicy { max_failures: 0, ..LockoutPolicy::default() }.validate().is_err());
+        assert!(LockoutPolicy { max_lockout_minutes: 1, ..LockoutPolicy::default() }.validate().is_err());
+    }
+
+    fn role_policy(role: &str, max_sessions: Option<u32>, idle_timeout_minutes: Option<i64>) -> RoleSessionPolicy {
+        RoleSessionPolicy { role: role.to_string(), max_sessions, idle_timeout_minutes }
+    }
+
+    #[test]
+    fn test_session_limits_take_the_strictest_role() {
+        let policy = SessionPolicy {
+            idle_timeout_minutes: Some(120),
+            roles: vec![
+                role_policy("admin", Some(2), Some(15)),
+                role_policy("auditor", Some(1), None),
+                role_policy("support", None, Some(60)),
+            ],
+            ..SessionPolicy::default()
+        };
+        let roles = |names: &[&str]| names.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(policy.limits_for(&roles(&["user"])), SessionLimits { max_sessions: None, idle_timeout_minutes: Some(120) });
+        assert_eq!(policy.limits_for(&roles(&["admin", "auditor"])), SessionLimits { max_sessions: Some(1), idle_timeout_minutes: Some(15) });
+        assert_eq!(policy.limits_for(&roles(&["support"])), SessionLimits { max_sessions: None, idle_timeout_minutes: Some(60) });
+        assert_eq!(SessionPolicy::default().limits_for(&roles(&["admin"])), SessionLimits::default());
+    }
+
+    #[test]
+    fn test_session_policy_validation() {
+        assert!(SessionPolicy::default().validate().is_ok());
+        assert!(SessionPolicy { idle_timeout_minutes: Some(0), ..SessionPolicy::default() }.validate().is_err());
+
+        let roles = |roles| SessionPolicy { roles, ..SessionPolicy::default() };
+        assert!(roles(vec![role_policy("admin", Some(2), Some(15))]).validate().is_ok());
+        assert!(roles(vec![role_policy("admin", Some(0), None)]).validate().is_err());
+        assert!(roles(vec![role_policy(" ", Some(1), None)]).validate().is_err());
+        assert!(roles(vec![role_policy("admin", Some(1), None), role_policy("admin", Some(2), None)]).validate().is_err());
+    }
+}
@@ -0,0 +1,138 @@
+use serde::{Serialize, Deserialize};
+use mongodb::bson::DateTime;
+use crate::models::LoginLog;
+
+// A logged-in device; lives as long as its refresh token and ends when revoked
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Session {
+    // Same id as the session_id of the login's LoginLog entry
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub tenant_id: String,
+    pub user_id: String,
+    // Hash of the jti of the only refresh token currently valid for the session
+    pub refresh_token_hash: String,
+    pub login_method: String,
+    #[serde(default)]
+    pub amr: Vec<String>,
+    // Organization activated in the access tokens issued for the session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub org_id: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub device_type: Option<String>,
+    pub browser: Option<String>,
+    pub os: Option<String>,
+    pub created_at: DateTime,
+    pub last_seen_at: DateTime,
+    pub expires_at: DateTime,
+    // Set when the user's roles have an idle timeout; moved forward on activity
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_timeout_minutes: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_expires_at: Option<DateTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<DateTime>,
+    // e.g. "user", "admin", "password_changed", "refresh_token_reused", "session_limit", "idle_timeout"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revoked_reason: Option<String>,
+}
+
+impl Session {
+    // Next idle deadline for activity at `now`, if the session has an idle timeout
+    pub fn idle_deadline(&self, now: DateTime) -> Option<DateTime> {
+        self.idle_timeout_minutes
+            .map(|minutes| DateTime::from_millis(now.timestamp_millis() + minutes * 60 * 1000))
+    }
+
+    pub fn with_idle_timeout(mut self, minutes: Option<i64>) -> Self {
+        self.idle_timeout_minutes = minutes;
+        self.idle_expires_at = self.idle_deadline(self.last_seen_at);
+        self
+    }
+
+    pub fn from_login(login_log: &LoginLog, user_id: &str, refresh_token_hash: String, amr: Vec<String>, org_id: Option<String>, expires_at: DateTime) -> Self {
+        let now = DateTime::now();
+        Self {
+            id: login_log.session_id.clone().unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+            tenant_id: login_log.tenant_id.clone(),
+            user_id: user_id.to_string(),
+            refresh_token_hash,
+            login_method: login_log.login_method.clone(),
+            amr,
+            org_id,
+            ip_address: login_log.ip_address.clone(),
+            user_agent: login_log.user_agent.clone(),
+            device_type: login_log.device_type.clone(),
+            browser: login_log.browser.clone(),
+            os: login_log.os.clone(),
+            created_at: now,
+            last_seen_at: now,
+            expires_at,
+            idle_timeout_minutes: None,
+            idle_expires_at: None,
+            revoked_at: None,
+            revoked_reason: None,
+        }
+    }
+}
+
+// What users and admins see about a session
+#[derive(Debug, Serialize)]
+pub struct SessionSummary {
+    pub id: String,
+    pub login_method: String,
+    pub ip_address: Option<String>,
+    pub device_type: Option<String>,
+    pub browser: Option<String>,
+    pub os: Option<String>,
+    pub created_at: Option<String>,
+    pub last_seen_at: Option<String>,
+    pub expires_at: Option<String>,
+    // The session the request was made from
+    pub current: bool,
+}
+
+impl SessionSummary {
+    pub fn new(session: &Session, current_session_id: Option<&str>) -> Self {
+        Self {
+            id: session.id.clone(),
+            login_method: session.login_method.clone(),
+            ip_address: session.ip_address.clone(),
+            device_type: session.device_type.clone(),
+            browser: session.browser.clone(),
+            os: session.os.clone(),
+            created_at: session.created_at.try_to_rfc3339_string().ok(),
+            last_seen_at: session.last_seen_at.try_to_rfc3339_string().ok(),
+            expires_at: session.expires_at.try_to_rfc3339_string().ok(),
+            current: current_session_id == Some(session.id.as_str()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_from_login() {
+        let login_log = LoginLog::new(
+            "acme".to_string(),
+            "jane@example.com".to_string(),
+            false,
+            Some("203.0.113.7".to_string()),
+            Some("Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X) Mobile Safari/604.1".to_string()),
+        );
+        let session = Session::from_login(&login_log, "user-1", "hash".to_string(), vec!["pwd".to_string()], None, DateTime::now());
+
+        assert_eq!(Some(&session.id), login_log.session_id.as_ref());
+        assert_eq!(session.tenant_id, "acme");
+        assert_eq!(session.login_method, "password");
+        assert_eq!(session.device_type.as_deref(), Some("Mobile"));
+
+        assert!(SessionSummary::new(&session, Some(&session.id)).current);
+        assert!(!SessionSummary::new(&session, Some("other")).current);
+        let json = serde_json::to_string(&SessionSummary::new(&session, None)).unwrap();
+        assert!(!json.contains("hash"));
+    }
+}
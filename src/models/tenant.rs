@@ -0,0 +1,53 @@
+use mongodb::bson::{doc, Document};
+
+// Tenant assigned to documents created before multi-tenancy and to requests that don't name one
+pub const DEFAULT_TENANT_ID: &str = "default";
+
+pub fn default_tenant_id() -> String {
+    DEFAULT_TENANT_ID.to_string()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TenantScope {
+    Tenant(String),
+    // Cross-tenant access, reserved for super admins and system tasks
+    AllTenants,
+}
+
+impl TenantScope {
+    pub fn tenant_id(&self) -> Option<&str> {
+        match self {
+            TenantScope::Tenant(id) => Some(id),
+            TenantScope::AllTenants => None,
+        }
+    }
+
+    // Restricts a query filter to the scope's tenant
+    pub fn apply(&self, filter: Document) -> Document {
+        match self {
+            TenantScope::AllTenants => filter,
+            // Documents created before multi-tenancy have no tenant_id field at all
+            TenantScope::Tenant(id) if id == DEFAULT_TENANT_ID => doc! {
+                "$and": [filter, { "$or": [ { "tenant_id": id }, { "tenant_id": { "$exists": false } } ] }]
+            },
+            TenantScope::Tenant(id) => doc! { "$and": [filter, { "tenant_id": id }] },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_all_tenants_keeps_filter() {
+        let filter = doc! { "email": "user@example.com" };
+        assert_eq!(TenantScope::AllTenants.apply(filter.clone()), filter);
+    }
+
+    #[test]
+    fn test_tenant_scope_adds_tenant_condition() {
+        let scoped = TenantScope::Tenant("acme".to_string()).apply(doc! { "email": "user@example.com" });
+        assert_eq!(scoped, doc! { "$and": [ { "email": "user@example.com" }, { "tenant_id": "acme" } ] });
+    }
+}
@@ -1,14 +1,28 @@
 use serde::{Serialize, Deserialize};
 use mongodb::bson::{oid::ObjectId, DateTime};
 use std::time::SystemTime;
+use crate::models::tenant::default_tenant_id;
+use crate::models::webauthn_credential::WebauthnCredential;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct User {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub _id: Option<ObjectId>,
+    #[serde(default = "default_tenant_id")]
+    pub tenant_id: String,
     pub email: String,
     pub password: String,
 
+    // Profile
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avatar_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     pub roles: Option<Vec<String>>,
 
@@ -24,58 +38,382 @@ pub struct User {
     #[serde(default)]
     pub email_verified: bool,
 
+    // Access tokens issued before either instant are no longer accepted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_changed_at: Option<DateTime>,
+    // Set when an admin deactivates the account or changes its roles
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokens_revoked_at: Option<DateTime>,
+
+    // Set when the current password was found in the breached password list
+    #[serde(default)]
+    pub password_breached: bool,
+
+    // Account lockout state
+    #[serde(default)]
+    pub failed_login_count: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_failed_login_at: Option<DateTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locked_until: Option<DateTime>,
+    // Lockouts in a row, drives the exponential backoff
+    #[serde(default)]
+    pub lockout_count: u32,
+
+    // TOTP second factor; the pending secret waits for a confirmation code
+    #[serde(default)]
+    pub mfa_enabled: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totp_secret: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totp_pending_secret: Option<String>,
+    // Time step of the last accepted code, so a code cannot be replayed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totp_last_step: Option<i64>,
+    // Hashes of the unused MFA recovery codes
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recovery_codes: Vec<String>,
+    // Passkeys, usable as a second factor or for passwordless login
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub webauthn_credentials: Vec<WebauthnCredential>,
+
+    // Hash of the pending email verification token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email_verification_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email_verification_expiry: Option<DateTime>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     pub password_reset_token: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub password_reset_expiry: Option<DateTime>,
 
+    // Hash of the jti of the last magic link sent; cleared when the link is used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub magic_link_token: Option<String>,
+
+    // Pending login code sent by email, with the wrong guesses made against it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email_otp_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email_otp_expiry: Option<DateTime>,
+    #[serde(default)]
+    pub email_otp_attempts: u32,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     pub refresh_tokens: Option<Vec<String>>, 
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kong_consumer_id: Option<String>,
+}
+
+// Admin-facing view of a user; never carries the password hash, reset token or refresh tokens
+#[derive(Debug, Serialize)]
+pub struct UserSummary {
+    pub id: String,
+    pub tenant_id: String,
+    pub email: String,
+    pub name: Option<String>,
+    pub roles: Vec<String>,
+    pub is_active: bool,
+    pub email_verified: bool,
+    pub password_breached: bool,
+    pub mfa_enabled: bool,
+    pub locked_until: Option<String>,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+    pub last_login: Option<String>,
+    pub kong_consumer_id: Option<String>,
+}
+
+fn to_rfc3339(datetime: &Option<DateTime>) -> Option<String> {
+    datetime.and_then(|dt| dt.try_to_rfc3339_string().ok())
+}
+
+impl From<&User> for UserSummary {
+    fn from(user: &User) -> Self {
+        Self {
+            id: user._id.map(|id| id.to_hex()).unwrap_or_default(),
+            tenant_id: user.tenant_id.clone(),
+            email: user.email.clone(),
+            name: user.name.clone(),
+            roles: user.roles.clone().unwrap_or_default(),
+            is_active: user.is_active,
+            email_verified: user.email_verified,
+            password_breached: user.password_breached,
+            mfa_enabled: user.mfa_enabled,
+            locked_until: to_rfc3339(&user.locked_until.filter(|until| *until > DateTime::now())),
+            created_at: to_rfc3339(&user.created_at),
+            updated_at: to_rfc3339(&user.updated_at),
+            last_login: to_rfc3339(&user.last_login),
+            kong_consumer_id: user.kong_consumer_id.clone(),
+        }
+    }
 }
 
 impl User {
-    pub fn new(email: String, password_hash: String) -> Self {
+    pub fn new(tenant_id: String, email: String, password_hash: String) -> Self {
         Self {
             _id: Some(ObjectId::new()),
+            tenant_id,
             email,
             password: password_hash,
+            name: None,
+            avatar_url: None,
+            locale: None,
+            timezone: None,
             roles: Some(vec!["user".to_string()]),
             created_at: Some(DateTime::from_system_time(SystemTime::now())),
             updated_at: Some(DateTime::from_system_time(SystemTime::now())),
             last_login: None,
             is_active: true,
             email_verified: false,
+            password_changed_at: None,
+            tokens_revoked_at: None,
+            password_breached: false,
+            mfa_enabled: false,
+            totp_secret: None,
+            totp_pending_secret: None,
+            totp_last_step: None,
+            recovery_codes: Vec::new(),
+            webauthn_credentials: Vec::new(),
+            failed_login_count: 0,
+            first_failed_login_at: None,
+            locked_until: None,
+            lockout_count: 0,
+            email_verification_token: None,
+            email_verification_expiry: None,
             password_reset_token: None,
             password_reset_expiry: None,
+            magic_link_token: None,
+            email_otp_hash: None,
+            email_otp_expiry: None,
+            email_otp_attempts: 0,
             refresh_tokens: Some(vec![]),
+            kong_consumer_id: None,
+        }
+    }
+
+    // Name shown to other users; falls back to the email when no name is set
+    pub fn display_name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.email.clone())
+    }
+
+    // True for tokens issued before the last password change or admin revocation
+    pub fn token_revoked(&self, issued_at: usize) -> bool {
+        [self.password_changed_at, self.tokens_revoked_at]
+            .into_iter()
+            .flatten()
+            .any(|revoked| (issued_at as i64) < revoked.timestamp_millis() / 1000)
+    }
+
+    pub fn is_locked(&self, now: DateTime) -> bool {
+        self.locked_until.is_some_and(|until| until > now)
+    }
+
+    pub fn clear_login_failures(&mut self) {
+        self.failed_login_count = 0;
+        self.first_failed_login_at = None;
+        self.locked_until = None;
+        self.lockout_count = 0;
+    }
+
+    // Second factors a password login must be completed with; empty means none
+    pub fn second_factor_methods(&self) -> Vec<&'static str> {
+        let mut methods = Vec::new();
+        if self.mfa_enabled {
+            methods.push("totp");
+        }
+        if !self.webauthn_credentials.is_empty() {
+            methods.push("webauthn");
         }
+        methods
+    }
+
+    pub fn webauthn_credential(&self, credential_id: &str) -> Option<&WebauthnCredential> {
+        self.webauthn_credentials.iter().find(|c| c.credential_id == credential_id)
     }
 
     pub fn is_admin(&self) -> bool {
-        self.roles
-            .as_ref()
-            .map_or(false, |roles| roles.contains(&"admin".to_string()))
+        self.has_role("admin") || self.is_super_admin()
+    }
+
+    // Super admins manage every tenant, not just their own
+    pub fn is_super_admin(&self) -> bool {
+        self.has_role("super_admin")
     }
 
     pub fn has_role(&self, role: &str) -> bool {
         self.roles
             .as_ref()
-            .map_or(false, |roles| roles.contains(&role.to_string()))
+            .is_some_and(|roles| roles.iter().any(|r| r == role))
     }
+}
+
+const MAX_NAME_LENGTH: usize = 100;
+const MAX_AVATAR_URL_LENGTH: usize = 2048;
+const MAX_TIMEZONE_LENGTH: usize = 64;
 
-    pub fn add_role(&mut self, role: String) {
-        if let Some(roles) = &mut self.roles {
-            if !roles.contains(&role) {
-                roles.push(role);
+// Partial profile update; an empty string clears the field
+#[derive(Debug, Default, Deserialize)]
+pub struct ProfileUpdate {
+    pub name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub locale: Option<String>,
+    pub timezone: Option<String>,
+}
+
+impl ProfileUpdate {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(name) = &self.name {
+            validate_name(name)?;
+        }
+        if let Some(avatar_url) = self.avatar_url.as_deref().filter(|v| !v.is_empty()) {
+            if avatar_url.len() > MAX_AVATAR_URL_LENGTH || !avatar_url.starts_with("https://") {
+                return Err("avatar_url must be an https URL".to_string());
             }
-        } else {
-            self.roles = Some(vec![role]);
         }
+        if let Some(locale) = self.locale.as_deref().filter(|v| !v.is_empty()) {
+            if !is_valid_locale(locale) {
+                return Err("locale must be a language tag like en or pt-BR".to_string());
+            }
+        }
+        if let Some(timezone) = self.timezone.as_deref().filter(|v| !v.is_empty()) {
+            if !is_valid_timezone(timezone) {
+                return Err("timezone must be an IANA name like Europe/Paris".to_string());
+            }
+        }
+        Ok(())
     }
 
-    pub fn remove_role(&mut self, role: &str) {
-        if let Some(roles) = &mut self.roles {
-            roles.retain(|r| r != role);
-        }
+    // The fields to write, trimmed; None clears the field
+    pub fn changes(&self) -> Vec<(&'static str, Option<String>)> {
+        [("name", &self.name), ("avatar_url", &self.avatar_url), ("locale", &self.locale), ("timezone", &self.timezone)]
+            .into_iter()
+            .filter_map(|(field, value)| {
+                let value = value.as_deref()?.trim();
+                Some((field, (!value.is_empty()).then(|| value.to_string())))
+            })
+            .collect()
     }
-}
\ No newline at end of file
+}
+
+pub fn validate_name(name: &str) -> Result<(), String> {
+    if name.trim().chars().count() > MAX_NAME_LENGTH {
+        return Err(format!("name must be at most {} characters", MAX_NAME_LENGTH));
+    }
+    if name.chars().any(char::is_control) {
+        return Err("name cannot contain control characters".to_string());
+    }
+    Ok(())
+}
+
+// Loose BCP 47 check: a 2-3 letter language followed by alphanumeric subtags
+fn is_valid_locale(locale: &str) -> bool {
+    let mut parts = locale.split('-');
+    let language = parts.next().unwrap_or_default();
+
+    (2..=3).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_alphabetic())
+        && parts.all(|part| (2..=8).contains(&part.len()) && part.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+fn is_valid_timezone(timezone: &str) -> bool {
+    timezone.len() <= MAX_TIMEZONE_LENGTH
+        && timezone.chars().all(|c| c.is_ascii_alphanumeric() || "/_+-".contains(c))
+        && !timezone.starts_with('/')
+        && !timezone.contains("//")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::LockoutPolicy;
+
+    #[test]
+    fn test_user_summary_hides_secrets() {
+        let mut user = User::new("acme".to_string(), "user@example.com".to_string(), "$2b$12$hash".to_string());
+        user.password_reset_token = Some("reset-token".to_string());
+        user.refresh_tokens = Some(vec!["refresh-jti".to_string()]);
+        user.totp_secret = Some("JBSWY3DPEHPK3PXP".to_string());
+
+        let json = serde_json::to_string(&UserSummary::from(&user)).unwrap();
+
+        assert!(json.contains("user@example.com"));
+        assert!(!json.contains("\"password\""));
+        assert!(!json.contains("$2b$12$hash"));
+        assert!(!json.contains("reset-token"));
+        assert!(!json.contains("email_verification_token"));
+        assert!(!json.contains("refresh-jti"));
+        assert!(!json.contains("JBSWY3DPEHPK3PXP"));
+    }
+
+    #[test]
+    fn test_profile_update_validation() {
+        let valid = ProfileUpdate {
+            name: Some("Jane Doe".to_string()),
+            avatar_url: Some("https://cdn.example.com/jane.png".to_string()),
+            locale: Some("pt-BR".to_string()),
+            timezone: Some("America/Sao_Paulo".to_string()),
+        };
+        assert!(valid.validate().is_ok());
+
+        let clearing = ProfileUpdate { avatar_url: Some(String::new()), locale: Some(String::new()), ..ProfileUpdate::default() };
+        assert!(clearing.validate().is_ok());
+
+        assert!(ProfileUpdate { name: Some("x".repeat(101)), ..ProfileUpdate::default() }.validate().is_err());
+        assert!(ProfileUpdate { name: Some("Jane\nDoe".to_string()), ..ProfileUpdate::default() }.validate().is_err());
+        assert!(ProfileUpdate { avatar_url: Some("http://example.com/a.png".to_string()), ..ProfileUpdate::default() }.validate().is_err());
+        assert!(ProfileUpdate { locale: Some("english".to_string()), ..ProfileUpdate::default() }.validate().is_err());
+        assert!(ProfileUpdate { timezone: Some("Europe/Paris; drop".to_string()), ..ProfileUpdate::default() }.validate().is_err());
+    }
+
+    #[test]
+    fn test_profile_update_changes() {
+        let update = ProfileUpdate {
+            name: Some("  Jane Doe ".to_string()),
+            locale: Some(String::new()),
+            ..ProfileUpdate::default()
+        };
+
+        // Fields left out are not written at all
+        assert_eq!(update.changes(), vec![("name", Some("Jane Doe".to_string())), ("locale", None)]);
+        assert!(ProfileUpdate::default().changes().is_empty());
+
+        let mut user = User::new("acme".to_string(), "user@example.com".to_string(), "hash".to_string());
+        assert_eq!(user.display_name(), "user@example.com");
+        user.name = Some("Jane Doe".to_string());
+        assert_eq!(user.display_name(), "Jane Doe");
+    }
+
+    #[test]
+    fn test_token_revoked_after_password_change() {
+        let mut user = User::new("acme".to_string(), "user@example.com".to_string(), "hash".to_string());
+        assert!(!user.token_revoked(0));
+
+        user.password_changed_at = Some(DateTime::from_millis(1_700_000_000_500));
+        assert!(user.token_revoked(1_699_999_999));
+        assert!(!user.token_revoked(1_700_000_000));
+
+        user.tokens_revoked_at = Some(DateTime::from_millis(1_700_000_100_000));
+        assert!(user.token_revoked(1_700_000_000));
+        assert!(!user.token_revoked(1_700_000_100));
+    }
+
+    #[test]
+    fn test_lockout_state() {
+        let policy = LockoutPolicy { lockout_minutes: 5, ..LockoutPolicy::default() };
+        let mut user = User::new("acme".to_string(), "user@example.com".to_string(), "hash".to_string());
+        let start = DateTime::from_millis(1_700_000_000_000);
+        let minutes = |m: i64| DateTime::from_millis(start.timestamp_millis() + m * 60 * 1000);
+
+        user.lockout_count = 1;
+        user.locked_until = Some(policy.locked_until(1, start));
+        assert!(user.is_locked(minutes(4)));
+        assert!(!user.is_locked(minutes(5)));
+
+        user.clear_login_failures();
+        assert!(!user.is_locked(start));
+        assert_eq!((user.failed_login_count, user.lockout_count), (0, 0));
+    }
+}
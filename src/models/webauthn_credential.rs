@@ -0,0 +1,51 @@
+use serde::{Serialize, Deserialize};
+use mongodb::bson::DateTime;
+
+// A passkey registered by a user; stored inside the user document
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebauthnCredential {
+    // base64url credential ID chosen by the authenticator
+    pub credential_id: String,
+    // base64url SEC1 P-256 public key
+    pub public_key: String,
+    pub sign_count: u32,
+    #[serde(default)]
+    pub transports: Vec<String>,
+    pub name: Option<String>,
+    pub created_at: DateTime,
+    pub last_used_at: Option<DateTime>,
+}
+
+// What the owner sees about their passkeys
+#[derive(Debug, Serialize)]
+pub struct WebauthnCredentialSummary {
+    pub id: String,
+    pub name: Option<String>,
+    pub transports: Vec<String>,
+    pub created_at: Option<String>,
+    pub last_used_at: Option<String>,
+}
+
+impl From<&WebauthnCredential> for WebauthnCredentialSummary {
+    fn from(credential: &WebauthnCredential) -> Self {
+        Self {
+            id: credential.credential_id.clone(),
+            name: credential.name.clone(),
+            transports: credential.transports.clone(),
+            created_at: credential.created_at.try_to_rfc3339_string().ok(),
+            last_used_at: credential.last_used_at.and_then(|dt| dt.try_to_rfc3339_string().ok()),
+        }
+    }
+}
+
+// A pending ceremony; the challenge can be answered once, before it expires
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebauthnChallenge {
+    #[serde(rename = "_id")]
+    pub challenge: String,
+    pub tenant_id: String,
+    // "register", "login" or "mfa"
+    pub purpose: String,
+    pub user_id: Option<String>,
+    pub expires_at: DateTime,
+}
@@ -0,0 +1,50 @@
+use mongodb::{Database, Collection, bson::doc, options::FindOptions};
+use futures_util::stream::StreamExt;
+use std::error::Error;
+use crate::models::{AuditLog, TenantScope};
+
+pub struct AuditService {
+    db: Database,
+    scope: TenantScope,
+}
+
+impl AuditService {
+    pub fn new(db: Database, tenant_id: &str) -> Self {
+        Self::with_scope(db, TenantScope::Tenant(tenant_id.to_string()))
+    }
+
+    pub fn with_scope(db: Database, scope: TenantScope) -> Self {
+        Self { db, scope }
+    }
+
+    pub fn audit_collection(&self) -> Collection<AuditLog> {
+        self.db.collection("AuditLogs")
+    }
+
+    // Audit failures are reported but never fail the change being audited
+    pub async fn record(&self, entry: &AuditLog) {
+        if let Err(e) = self.audit_collection().insert_one(entry).await {
+            eprintln!("❌ Error saving audit log ({}): {}", entry.action, e);
+        }
+    }
+
+    pub async fn list(&self, target_type: Option<&str>, limit: Option<i64>) -> Result<Vec<AuditLog>, Box<dyn Error + Send + Sync>> {
+        let filter = match target_type {
+            Some(target_type) => self.scope.apply(doc! { "target_type": target_type }),
+            None => self.scope.apply(doc! {}),
+        };
+        let options = FindOptions::builder()
+            .sort(doc! { "timestamp": -1 })
+            .limit(limit.unwrap_or(100))
+            .build();
+
+        let mut cursor = self.audit_collection().find(filter).with_options(options).await?;
+        let mut entries = Vec::new();
+
+        while let Some(entry) = cursor.next().await {
+            entries.push(entry?);
+        }
+
+        Ok(entries)
+    }
+}
@@ -0,0 +1,125 @@
+use mongodb::{Database, Collection, bson::{doc, oid::ObjectId, DateTime}};
+use futures_util::stream::StreamExt;
+use std::error::Error;
+use crate::models::{inherited_roles, Group, TenantScope};
+
+pub struct GroupService {
+    db: Database,
+    scope: TenantScope,
+}
+
+impl GroupService {
+    pub fn new(db: Database, tenant_id: &str) -> Self {
+        Self::with_scope(db, TenantScope::Tenant(tenant_id.to_string()))
+    }
+
+    pub fn with_scope(db: Database, scope: TenantScope) -> Self {
+        Self { db, scope }
+    }
+
+    pub fn groups_collection(&self) -> Collection<Group> {
+        self.db.collection("Groups")
+    }
+
+    pub async fn create_group(&self, group: &Group) -> Result<ObjectId, Box<dyn Error + Send + Sync>> {
+        match self.groups_collection().insert_one(group).await {
+            Ok(result) => result.inserted_id.as_object_id().ok_or_else(|| "Failed to get inserted ID".into()),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub async fn find_by_id(&self, id: &ObjectId) -> Result<Option<Group>, Box<dyn Error + Send + Sync>> {
+        let filter = self.scope.apply(doc! { "_id": id });
+
+        match self.groups_collection().find_one(filter).await {
+            Ok(group) => Ok(group),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub async fn find_by_name(&self, name: &str) -> Result<Option<Group>, Box<dyn Error + Send + Sync>> {
+        let filter = self.scope.apply(doc! { "name": name });
+
+        match self.groups_collection().find_one(filter).await {
+            Ok(group) => Ok(group),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub async fn list_groups(&self) -> Result<Vec<Group>, Box<dyn Error + Send + Sync>> {
+        let filter = self.scope.apply(doc! {});
+        let mut cursor = self.groups_collection().find(filter).await?;
+        let mut groups = Vec::new();
+
+        while let Some(group) = cursor.next().await {
+            groups.push(group?);
+        }
+
+        Ok(groups)
+    }
+
+    pub async fn update_group(&self, id: &ObjectId, group: &Group) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let filter = self.scope.apply(doc! { "_id": id });
+        let update = doc! {
+            "$set": {
+                "name": &group.name,
+                "description": &group.description,
+                "roles": &group.roles,
+                "updated_at": DateTime::now(),
+            }
+        };
+
+        match self.groups_collection().update_one(filter, update).await {
+            Ok(result) => Ok(result.matched_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub async fn delete_group(&self, id: &ObjectId) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let filter = self.scope.apply(doc! { "_id": id });
+
+        match self.groups_collection().delete_one(filter).await {
+            Ok(result) => Ok(result.deleted_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub async fn add_member(&self, id: &ObjectId, user_id: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let filter = self.scope.apply(doc! { "_id": id });
+        let update = doc! {
+            "$addToSet": { "member_ids": user_id },
+            "$set": { "updated_at": DateTime::now() },
+        };
+
+        match self.groups_collection().update_one(filter, update).await {
+            Ok(result) => Ok(result.modified_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub async fn remove_member(&self, id: &ObjectId, user_id: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let filter = self.scope.apply(doc! { "_id": id });
+        let update = doc! {
+            "$pull": { "member_ids": user_id },
+            "$set": { "updated_at": DateTime::now() },
+        };
+
+        match self.groups_collection().update_one(filter, update).await {
+            Ok(result) => Ok(result.modified_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    // Union of the roles of every group the user belongs to
+    pub async fn roles_for_user(&self, user_id: &str) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        let filter = self.scope.apply(doc! { "member_ids": user_id });
+        let mut cursor = self.groups_collection().find(filter).await?;
+        let mut groups = Vec::new();
+
+        while let Some(group) = cursor.next().await {
+            groups.push(group?);
+        }
+
+        Ok(inherited_roles(&groups))
+    }
+}
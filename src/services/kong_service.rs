@@ -0,0 +1,135 @@
+use reqwest::{Client, RequestBuilder, StatusCode};
+use serde::Deserialize;
+use serde_json::json;
+use std::error::Error;
+use crate::auth::jwt::{current_signing_key, previous_signing_key, SigningKey};
+use crate::config::KongConfig;
+use crate::models::User;
+
+#[derive(Debug, Deserialize)]
+struct KongConsumer {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct KongJwtCredential {
+    id: String,
+    key: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct KongList<T> {
+    data: Vec<T>,
+}
+
+// Key of the consumer's jwt credential for a given signing key; tokens carry it in the `key` claim
+pub fn kong_credential_key(user_id: &str, kid: &str) -> String {
+    format!("{}.{}", user_id, kid)
+}
+
+#[derive(Clone)]
+pub struct KongService {
+    client: Client,
+    config: KongConfig,
+}
+
+impl KongService {
+    pub fn new(config: KongConfig) -> Self {
+        Self { client: Client::new(), config }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.admin_url.is_some()
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> Result<RequestBuilder, Box<dyn Error + Send + Sync>> {
+        let base_url = self.config.admin_url.as_ref().ok_or("Kong admin URL is not configured")?;
+        let mut builder = self.client.request(method, format!("{}{}", base_url.trim_end_matches('/'), path));
+
+        if let Some(token) = &self.config.admin_token {
+            builder = builder.header("Kong-Admin-Token", token);
+        }
+
+        Ok(builder)
+    }
+
+    // Creates or updates the consumer for the user, returning its Kong id
+    async fn upsert_consumer(&self, user_id: &str, user: &User) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let response = self.request(reqwest::Method::PUT, &format!("/consumers/{}", user_id))?
+            .json(&json!({
+                "username": user_id,
+                "custom_id": format!("{}:{}", user.tenant_id, user.email),
+                "tags": ["kong-security-api", format!("tenant:{}", user.tenant_id)],
+            }))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let consumer: KongConsumer = response.json().await?;
+        Ok(consumer.id)
+    }
+
+    async fn upsert_jwt_credential(&self, consumer_id: &str, user_id: &str, key: &SigningKey) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let credential_key = kong_credential_key(user_id, &key.kid);
+
+        self.request(reqwest::Method::PUT, &format!("/consumers/{}/jwt/{}", consumer_id, credential_key))?
+            .json(&json!({
+                "key": credential_key,
+                "algorithm": "RS256",
+                "rsa_public_key": key.public_pem_string(),
+                "tags": ["kong-security-api"],
+            }))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    async fn list_jwt_credentials(&self, consumer_id: &str) -> Result<Vec<KongJwtCredential>, Box<dyn Error + Send + Sync>> {
+        let response = self.request(reqwest::Method::GET, &format!("/consumers/{}/jwt", consumer_id))?
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let list: KongList<KongJwtCredential> = response.json().await?;
+        Ok(list.data)
+    }
+
+    async fn delete_jwt_credential(&self, consumer_id: &str, credential_id: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let response = self.request(reqwest::Method::DELETE, &format!("/consumers/{}/jwt/{}", consumer_id, credential_id))?
+            .send()
+            .await?;
+
+        // Already gone is fine
+        if response.status() != StatusCode::NOT_FOUND {
+            response.error_for_status()?;
+        }
+
+        Ok(())
+    }
+
+    // Registers the user as a Kong consumer with a jwt credential for the current signing key.
+    // The credential for the previous key is kept so tokens issued before a rotation still verify;
+    // credentials for any older keys are removed.
+    pub async fn sync_user(&self, user: &User) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let user_id = user._id.as_ref().ok_or("User ID is required")?.to_hex();
+        let consumer_id = self.upsert_consumer(&user_id, user).await?;
+
+        let current = current_signing_key();
+        self.upsert_jwt_credential(&consumer_id, &user_id, &current).await?;
+
+        let mut valid_keys = vec![kong_credential_key(&user_id, &current.kid)];
+        if let Some(previous) = previous_signing_key() {
+            valid_keys.push(kong_credential_key(&user_id, &previous.kid));
+        }
+
+        for credential in self.list_jwt_credentials(&consumer_id).await? {
+            if !valid_keys.contains(&credential.key) {
+                self.delete_jwt_credential(&consumer_id, &credential.id).await?;
+            }
+        }
+
+        Ok(consumer_id)
+    }
+}
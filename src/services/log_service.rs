@@ -1,15 +1,20 @@
 use mongodb::{Database, Collection, bson::doc};
 use futures_util::stream::StreamExt;
 use std::error::Error;
-use crate::models::{LoginLog, LoginStats};
+use crate::models::{LoginLog, LoginStats, TenantScope};
 
 pub struct LogService {
     db: Database,
+    scope: TenantScope,
 }
 
 impl LogService {
-    pub fn new(db: Database) -> Self {
-        Self { db }
+    pub fn new(db: Database, tenant_id: &str) -> Self {
+        Self::with_scope(db, TenantScope::Tenant(tenant_id.to_string()))
+    }
+
+    pub fn with_scope(db: Database, scope: TenantScope) -> Self {
+        Self { db, scope }
     }
 
     pub fn logs_collection(&self) -> Collection<LoginLog> {
@@ -37,7 +42,7 @@ impl LogService {
 
     pub async fn get_user_logs(&self, user_id: &str, limit: Option<i64>) -> Result<Vec<LoginLog>, Box<dyn Error + Send + Sync>> {
         let collection = self.logs_collection();
-        let filter = doc! { "user_id": user_id };
+        let filter = self.scope.apply(doc! { "user_id": user_id });
 
         let mut cursor = collection.find(filter).await?;
         let mut logs = Vec::new();
@@ -59,13 +64,13 @@ impl LogService {
         }
 
         // Sort by timestamp descending (most recent first)
-        logs.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        logs.sort_by_key(|log| std::cmp::Reverse(log.timestamp));
         Ok(logs)
     }
 
     pub async fn get_all_logs(&self, limit: Option<i64>) -> Result<Vec<LoginLog>, Box<dyn Error + Send + Sync>> {
         let collection = self.logs_collection();
-        let filter = doc! {};
+        let filter = self.scope.apply(doc! {});
 
         let mut cursor = collection.find(filter).await?;
         let mut logs = Vec::new();
@@ -87,7 +92,7 @@ impl LogService {
         }
 
         // Sort by timestamp descending (most recent first)
-        logs.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        logs.sort_by_key(|log| std::cmp::Reverse(log.timestamp));
         Ok(logs)
     }
 
@@ -99,15 +104,18 @@ impl LogService {
         let system_time = date_threshold.timestamp_millis();
         let date_filter = mongodb::bson::DateTime::from_millis(system_time);
         
-        // Count total attempts
-        let total_filter = doc! { "timestamp": { "$gte": date_filter } };
+        // Count total attempts; the password step of an MFA login is not an attempt of its own
+        let total_filter = self.scope.apply(doc! {
+            "timestamp": { "$gte": date_filter },
+            "mfa_pending": { "$ne": true },
+        });
         let total_attempts = collection.count_documents(total_filter.clone()).await?;
         
         // Count successful logins
-        let success_filter = doc! { 
+        let success_filter = self.scope.apply(doc! { 
             "timestamp": { "$gte": date_filter },
             "success": true 
-        };
+        });
         let successful_logins = collection.count_documents(success_filter).await?;
         
         // Count failed logins
@@ -128,32 +136,4 @@ impl LogService {
             period_days: days,
         })
     }
-
-    pub async fn get_logs_by_email(&self, email: &str, limit: Option<i64>) -> Result<Vec<LoginLog>, Box<dyn Error + Send + Sync>> {
-        let collection = self.logs_collection();
-        let filter = doc! { "email": email };
-
-        let mut cursor = collection.find(filter).await?;
-        let mut logs = Vec::new();
-        let mut count = 0;
-
-        while let Some(log) = cursor.next().await {
-            match log {
-                Ok(l) => {
-                    logs.push(l);
-                    count += 1;
-                    if let Some(limit_val) = limit {
-                        if count >= limit_val {
-                            break;
-                        }
-                    }
-                }
-                Err(e) => return Err(Box::new(e)),
-            }
-        }
-
-        // Sort by timestamp descending (most recent first)
-        logs.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
-        Ok(logs)
-    }
 }
\ No newline at end of file
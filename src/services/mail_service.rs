@@ -0,0 +1,295 @@
+use lettre::message::{header::ContentType, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use serde::Serialize;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use crate::config::{MailConfig, MailTransport, SmtpConfig};
+use crate::services::mail_templates::MailTemplate;
+
+// Delay before the first retry; doubles with every further attempt
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub html: Option<String>,
+}
+
+impl Email {
+    pub fn from_template(to: &str, locale: Option<&str>, template: &MailTemplate) -> Self {
+        let rendered = template.render(locale);
+        Self {
+            to: to.to_string(),
+            subject: rendered.subject,
+            text: rendered.text,
+            html: Some(rendered.html),
+        }
+    }
+}
+
+// Outbound mail transport; called from a blocking thread, never from a handler
+pub trait Mailer: Send + Sync {
+    fn send(&self, from: &str, email: &Email) -> Result<(), Box<dyn Error + Send + Sync>>;
+}
+
+// Writes emails to stdout
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+    fn send(&self, from: &str, email: &Email) -> Result<(), Box<dyn Error + Send + Sync>> {
+        println!("📧 Email from {} to {}: {}\n{}", from, email.to, email.subject, email.text);
+        Ok(())
+    }
+}
+
+// Writes each email as a JSON file so tests and local development can inspect what was sent
+pub struct OutboxMailer {
+    dir: PathBuf,
+}
+
+#[derive(Serialize)]
+struct OutboxEntry<'a> {
+    from: &'a str,
+    #[serde(flatten)]
+    email: &'a Email,
+    queued_at: String,
+}
+
+impl OutboxMailer {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+}
+
+impl Mailer for OutboxMailer {
+    fn send(&self, from: &str, email: &Email) -> Result<(), Box<dyn Error + Send + Sync>> {
+        fs::create_dir_all(&self.dir)?;
+
+        let now = chrono::Utc::now();
+        let entry = OutboxEntry { from, email, queued_at: now.to_rfc3339() };
+        let path = self.dir.join(format!("{}-{}.json", now.timestamp_millis(), uuid::Uuid::new_v4()));
+
+        fs::write(path, serde_json::to_vec_pretty(&entry)?)?;
+        Ok(())
+    }
+}
+
+pub struct SmtpMailer {
+    transport: SmtpTransport,
+}
+
+impl SmtpMailer {
+    pub fn new(config: &SmtpConfig) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut builder = match config.tls.as_str() {
+            "tls" => SmtpTransport::relay(&config.host)?,
+            "none" => SmtpTransport::builder_dangerous(&config.host),
+            _ => SmtpTransport::starttls_relay(&config.host)?,
+        };
+
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        Ok(Self { transport: builder.build() })
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, from: &str, email: &Email) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let builder = Message::builder()
+            .from(from.parse()?)
+            .to(email.to.parse()?)
+            .subject(&email.subject);
+
+        let message = match &email.html {
+            Some(html) => builder.multipart(MultiPart::alternative_plain_html(email.text.clone(), html.clone()))?,
+            None => builder.header(ContentType::TEXT_PLAIN).body(email.text.clone())?,
+        };
+
+        self.transport.send(&message)?;
+        Ok(())
+    }
+}
+
+// Queues emails and delivers them from a background task, retrying with exponential backoff
+#[derive(Clone)]
+pub struct MailService {
+    queue: mpsc::UnboundedSender<Email>,
+    config: MailConfig,
+}
+
+impl MailService {
+    pub fn new(config: MailConfig) -> Self {
+        let mailer: Arc<dyn Mailer> = match &config.transport {
+            MailTransport::Log => Arc::new(LogMailer),
+            MailTransport::Outbox(dir) => Arc::new(OutboxMailer::new(dir.clone())),
+            MailTransport::Smtp(smtp) => Arc::new(SmtpMailer::new(smtp).expect("Invalid SMTP configuration")),
+        };
+
+        Self::with_mailer(config, mailer, RETRY_BASE_DELAY)
+    }
+
+    // Must be called inside a Tokio runtime, which runs the delivery worker
+    pub fn with_mailer(config: MailConfig, mailer: Arc<dyn Mailer>, retry_base: Duration) -> Self {
+        let (queue, mut receiver) = mpsc::unbounded_channel::<Email>();
+        let from = config.from.clone();
+        let max_attempts = config.max_attempts.max(1);
+
+        tokio::spawn(async move {
+            while let Some(email) = receiver.recv().await {
+                // One task per email so a failing recipient doesn't hold up the rest of the queue
+                tokio::spawn(deliver(mailer.clone(), from.clone(), email, max_attempts, retry_base));
+            }
+        });
+
+        Self { queue, config }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.config.app_base_url.trim_end_matches('/'), path)
+    }
+
+    // Link into the frontend, e.g. /reset-password?token=...
+    pub fn link(&self, path: &str, token: &str) -> String {
+        format!("{}?token={}", self.url(path), token)
+    }
+
+    // Queues the email and returns immediately
+    pub fn send(&self, email: Email) {
+        if let Err(e) = self.queue.send(email) {
+            eprintln!("❌ Mail queue closed, dropping email to {}", e.0.to);
+        }
+    }
+
+    pub fn send_template(&self, to: &str, locale: Option<&str>, template: MailTemplate) {
+        self.send(Email::from_template(to, locale, &template));
+    }
+}
+
+async fn deliver(mailer: Arc<dyn Mailer>, from: String, email: Email, max_attempts: u32, retry_base: Duration) {
+    let email = Arc::new(email);
+
+    for attempt in 1..=max_attempts {
+        let (mailer, from, message) = (mailer.clone(), from.clone(), email.clone());
+        let result = tokio::task::spawn_blocking(move || mailer.send(&from, &message)).await;
+
+        let error = match result {
+            Ok(Ok(())) => return,
+            Ok(Err(e)) => e.to_string(),
+            Err(e) => e.to_string(),
+        };
+
+        if attempt == max_attempts {
+            eprintln!("❌ Giving up on email to {} after {} attempts: {}", email.to, attempt, error);
+            return;
+        }
+
+        let delay = retry_base * 2u32.pow(attempt - 1);
+        eprintln!("⚠️ Email to {} failed (attempt {}), retrying in {:?}: {}", email.to, attempt, delay, error);
+        tokio::time::sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    // Fails the first `failures` sends
+    struct FlakyMailer {
+        failures: u32,
+        attempts: AtomicU32,
+        delivered: AtomicU32,
+    }
+
+    impl Mailer for FlakyMailer {
+        fn send(&self, _from: &str, _email: &Email) -> Result<(), Box<dyn Error + Send + Sync>> {
+            if self.attempts.fetch_add(1, Ordering::SeqCst) < self.failures {
+                return Err("connection refused".into());
+            }
+            self.delivered.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    fn test_config(max_attempts: u32) -> MailConfig {
+        MailConfig {
+            from: "no-reply@example.com".to_string(),
+            app_base_url: "https://app.example.com/".to_string(),
+            transport: MailTransport::Log,
+            max_attempts,
+        }
+    }
+
+    fn test_email() -> Email {
+        Email {
+            to: "user@example.com".to_string(),
+            subject: "Hello".to_string(),
+            text: "Hello there".to_string(),
+            html: None,
+        }
+    }
+
+    async fn wait_for(condition: impl Fn() -> bool) {
+        for _ in 0..200 {
+            if condition() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_queue_retries_until_delivered() {
+        let mailer = Arc::new(FlakyMailer { failures: 2, attempts: AtomicU32::new(0), delivered: AtomicU32::new(0) });
+        let service = MailService::with_mailer(test_config(5), mailer.clone(), Duration::from_millis(1));
+
+        service.send(test_email());
+        wait_for(|| mailer.delivered.load(Ordering::SeqCst) == 1).await;
+
+        assert_eq!(mailer.delivered.load(Ordering::SeqCst), 1);
+        assert_eq!(mailer.attempts.load(Ordering::SeqCst), 3);
+        assert_eq!(service.link("/verify-email", "abc"), "https://app.example.com/verify-email?token=abc");
+    }
+
+    #[tokio::test]
+    async fn test_queue_gives_up_after_max_attempts() {
+        let mailer = Arc::new(FlakyMailer { failures: u32::MAX, attempts: AtomicU32::new(0), delivered: AtomicU32::new(0) });
+        let service = MailService::with_mailer(test_config(3), mailer.clone(), Duration::from_millis(1));
+
+        service.send(test_email());
+        wait_for(|| mailer.attempts.load(Ordering::SeqCst) >= 3).await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        assert_eq!(mailer.attempts.load(Ordering::SeqCst), 3);
+        assert_eq!(mailer.delivered.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_outbox_mailer_writes_json() {
+        let dir = std::env::temp_dir().join(format!("outbox-{}", uuid::Uuid::new_v4()));
+        let mailer = OutboxMailer::new(dir.clone());
+
+        mailer.send("no-reply@example.com", &test_email()).unwrap();
+
+        let files: Vec<_> = fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(files.len(), 1);
+        let entry: serde_json::Value = serde_json::from_slice(&fs::read(files[0].as_ref().unwrap().path()).unwrap()).unwrap();
+        assert_eq!(entry["to"], "user@example.com");
+        assert_eq!(entry["from"], "no-reply@example.com");
+        assert_eq!(entry["subject"], "Hello");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
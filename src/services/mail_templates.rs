@@ -0,0 +1,261 @@
+// Localized email templates; each renders to both plain text and HTML
+
+pub const SUPPORTED_LANGUAGES: [&str; 3] = ["en", "es", "pt"];
+
+pub enum MailTemplate<'a> {
+    PasswordReset { link: &'a str, ttl_minutes: i64 },
+    EmailVerification { link: &'a str, ttl_hours: i64 },
+    OrganizationInvitation { link: &'a str, org_name: &'a str, inviter: &'a str },
+    MagicLink { link: &'a str, ttl_minutes: i64 },
+    LoginCode { code: &'a str, ttl_minutes: i64 },
+    // A security-relevant change on the account, e.g. "password_changed"
+    SecurityAlert { event: &'a str },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderedEmail {
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+// Content shared by both renderings
+struct Content {
+    subject: String,
+    paragraphs: Vec<String>,
+    action: Option<(String, String)>, // (label, url)
+}
+
+// "pt-BR" -> "pt"; unsupported or missing locales fall back to English
+pub fn language_for(locale: Option<&str>) -> &'static str {
+    let language = locale
+        .and_then(|l| l.split(['-', '_']).next())
+        .unwrap_or_default()
+        .to_lowercase();
+
+    SUPPORTED_LANGUAGES
+        .iter()
+        .find(|supported| **supported == language)
+        .copied()
+        .unwrap_or("en")
+}
+
+impl MailTemplate<'_> {
+    pub fn render(&self, locale: Option<&str>) -> RenderedEmail {
+        let content = self.content(language_for(locale));
+
+        let mut text = content.paragraphs.join("\n\n");
+        let mut html = content.paragraphs.iter()
+            .map(|p| format!("<p>{}</p>", escape_html(p)))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        if let Some((label, url)) = &content.action {
+            text.push_str(&format!("\n\n{}: {}", label, url));
+            html.push_str(&format!("\n<p><a href=\"{}\">{}</a></p>", escape_html(url), escape_html(label)));
+        }
+
+        RenderedEmail {
+            subject: content.subject,
+            text,
+            html: format!("<!DOCTYPE html>\n<html><body>\n{}\n</body></html>", html),
+        }
+    }
+
+    fn content(&self, language: &str) -> Content {
+        match self {
+            MailTemplate::PasswordReset { link, ttl_minutes } => match language {
+                "es" => Content {
+                    subject: "Restablece tu contraseña".to_string(),
+                    paragraphs: vec![
+                        "Recibimos una solicitud para restablecer tu contraseña.".to_string(),
+                        format!("El enlace caduca en {} minutos. Si no lo solicitaste, ignora este correo.", ttl_minutes),
+                    ],
+                    action: Some(("Elegir una nueva contraseña".to_string(), link.to_string())),
+                },
+                "pt" => Content {
+                    subject: "Redefina sua senha".to_string(),
+                    paragraphs: vec![
+                        "Recebemos um pedido para redefinir sua senha.".to_string(),
+                        format!("O link expira em {} minutos. Se você não fez o pedido, ignore este e-mail.", ttl_minutes),
+                    ],
+                    action: Some(("Escolher uma nova senha".to_string(), link.to_string())),
+                },
+                _ => Content {
+                    subject: "Reset your password".to_string(),
+                    paragraphs: vec![
+                        "We received a request to reset your password.".to_string(),
+                        format!("The link expires in {} minutes. If you did not ask for it, ignore this email.", ttl_minutes),
+                    ],
+                    action: Some(("Choose a new password".to_string(), link.to_string())),
+                },
+            },
+            MailTemplate::EmailVerification { link, ttl_hours } => match language {
+                "es" => Content {
+                    subject: "Verifica tu correo electrónico".to_string(),
+                    paragraphs: vec![format!("Confirma tu dirección de correo. El enlace caduca en {} horas.", ttl_hours)],
+                    action: Some(("Verificar correo".to_string(), link.to_string())),
+                },
+                "pt" => Content {
+                    subject: "Confirme seu e-mail".to_string(),
+                    paragraphs: vec![format!("Confirme seu endereço de e-mail. O link expira em {} horas.", ttl_hours)],
+                    action: Some(("Confirmar e-mail".to_string(), link.to_string())),
+                },
+                _ => Content {
+                    subject: "Verify your email address".to_string(),
+                    paragraphs: vec![format!("Confirm your email address. The link expires in {} hours.", ttl_hours)],
+                    action: Some(("Verify email".to_string(), link.to_string())),
+                },
+            },
+            MailTemplate::OrganizationInvitation { link, org_name, inviter } => match language {
+                "es" => Content {
+                    subject: format!("Invitación a {}", org_name),
+                    paragraphs: vec![format!("{} te invitó a unirte a {}.", inviter, org_name)],
+                    action: Some(("Ver invitación".to_string(), link.to_string())),
+                },
+                "pt" => Content {
+                    subject: format!("Convite para {}", org_name),
+                    paragraphs: vec![format!("{} convidou você para participar de {}.", inviter, org_name)],
+                    action: Some(("Ver convite".to_string(), link.to_string())),
+                },
+                _ => Content {
+                    subject: format!("Invitation to {}", org_name),
+                    paragraphs: vec![format!("{} invited you to join {}.", inviter, org_name)],
+                    action: Some(("View invitation".to_string(), link.to_string())),
+                },
+            },
+            MailTemplate::MagicLink { link, ttl_minutes } => match language {
+                "es" => Content {
+                    subject: "Tu enlace para iniciar sesión".to_string(),
+                    paragraphs: vec![
+                        "Usa este enlace para iniciar sesión. Solo funciona una vez.".to_string(),
+                        format!("El enlace caduca en {} minutos. Si no lo solicitaste, ignora este correo.", ttl_minutes),
+                    ],
+                    action: Some(("Iniciar sesión".to_string(), link.to_string())),
+                },
+                "pt" => Content {
+                    subject: "Seu link para entrar".to_string(),
+                    paragraphs: vec![
+                        "Use este link para entrar. Ele só funciona uma vez.".to_string(),
+                        format!("O link expira em {} minutos. Se você não fez o pedido, ignore este e-mail.", ttl_minutes),
+                    ],
+                    action: Some(("Entrar".to_string(), link.to_string())),
+                },
+                _ => Content {
+                    subject: "Your sign-in link".to_string(),
+                    paragraphs: vec![
+                        "Use this link to sign in. It only works once.".to_string(),
+                        format!("The link expires in {} minutes. If you did not ask for it, ignore this email.", ttl_minutes),
+                    ],
+                    action: Some(("Sign in".to_string(), link.to_string())),
+                },
+            },
+            MailTemplate::LoginCode { code, ttl_minutes } => match language {
+                "es" => Content {
+                    subject: format!("Tu código para iniciar sesión: {}", code),
+                    paragraphs: vec![
+                        format!("Tu código para iniciar sesión es {}.", code),
+                        format!("Caduca en {} minutos. Si no lo solicitaste, ignora este correo.", ttl_minutes),
+                    ],
+                    action: None,
+                },
+                "pt" => Content {
+                    subject: format!("Seu código para entrar: {}", code),
+                    paragraphs: vec![
+                        format!("Seu código para entrar é {}.", code),
+                        format!("Ele expira em {} minutos. Se você não fez o pedido, ignore este e-mail.", ttl_minutes),
+                    ],
+                    action: None,
+                },
+                _ => Content {
+                    subject: format!("Your sign-in code: {}", code),
+                    paragraphs: vec![
+                        format!("Your sign-in code is {}.", code),
+                        format!("It expires in {} minutes. If you did not ask for it, ignore this email.", ttl_minutes),
+                    ],
+                    action: None,
+                },
+            },
+            MailTemplate::SecurityAlert { event } => {
+                let (subject, what, advice) = match (language, *event) {
+                    ("es", "password_changed") => ("Tu contraseña cambió", "La contraseña de tu cuenta fue cambiada.", "Si no fuiste tú, restablece tu contraseña de inmediato."),
+                    ("es", "account_locked") => ("Tu cuenta fue bloqueada temporalmente", "Tu cuenta se bloqueó temporalmente tras varios intentos fallidos de inicio de sesión.", "Si no fuiste tú, restablece tu contraseña cuando se desbloquee."),
+                    ("es", "mfa_enabled") => ("Verificación en dos pasos activada", "Se activó la verificación en dos pasos en tu cuenta.", "Si no fuiste tú, restablece tu contraseña y contacta con soporte."),
+                    ("es", "mfa_disabled") => ("Verificación en dos pasos desactivada", "Se desactivó la verificación en dos pasos en tu cuenta.", "Si no fuiste tú, restablece tu contraseña de inmediato."),
+                    ("es", "recovery_code_used") => ("Se usó un código de recuperación", "Se inició sesión en tu cuenta con un código de recuperación.", "Si no fuiste tú, restablece tu contraseña y genera nuevos códigos de inmediato."),
+                    ("es", "passkey_added") => ("Se añadió una llave de acceso", "Se registró una nueva llave de acceso en tu cuenta.", "Si no fuiste tú, elimínala y restablece tu contraseña de inmediato."),
+                    ("es", "passkey_removed") => ("Se eliminó una llave de acceso", "Se eliminó una llave de acceso de tu cuenta.", "Si no fuiste tú, restablece tu contraseña de inmediato."),
+                    ("es", _) => ("Alerta de seguridad", "Hubo un cambio de seguridad en tu cuenta.", "Si no fuiste tú, contacta con soporte."),
+                    ("pt", "password_changed") => ("Sua senha foi alterada", "A senha da sua conta foi alterada.", "Se não foi você, redefina sua senha imediatamente."),
+                    ("pt", "account_locked") => ("Sua conta foi bloqueada temporariamente", "Sua conta foi bloqueada temporariamente após várias tentativas de login sem sucesso.", "Se não foi você, redefina sua senha quando ela for desbloqueada."),
+                    ("pt", "mfa_enabled") => ("Verificação em duas etapas ativada", "A verificação em duas etapas foi ativada na sua conta.", "Se não foi você, redefina sua senha e entre em contato com o suporte."),
+                    ("pt", "mfa_disabled") => ("Verificação em duas etapas desativada", "A verificação em duas etapas foi desativada na sua conta.", "Se não foi você, redefina sua senha imediatamente."),
+                    ("pt", "recovery_code_used") => ("Um código de recuperação foi usado", "Alguém entrou na sua conta com um código de recuperação.", "Se não foi você, redefina sua senha e gere novos códigos imediatamente."),
+                    ("pt", "passkey_added") => ("Uma chave de acesso foi adicionada", "Uma nova chave de acesso foi registrada na sua conta.", "Se não foi você, remova-a e redefina sua senha imediatamente."),
+                    ("pt", "passkey_removed") => ("Uma chave de acesso foi removida", "Uma chave de acesso foi removida da sua conta.", "Se não foi você, redefina sua senha imediatamente."),
+                    ("pt", _) => ("Alerta de segurança", "Houve uma alteração de segurança na sua conta.", "Se não foi você, entre em contato com o suporte."),
+                    (_, "password_changed") => ("Your password was changed", "The password of your account was changed.", "If this wasn't you, reset your password right away."),
+                    (_, "account_locked") => ("Your account was temporarily locked", "Your account was temporarily locked after several failed login attempts.", "If this wasn't you, reset your password once it is unlocked."),
+                    (_, "mfa_enabled") => ("Two-step verification turned on", "Two-step verification was turned on for your account.", "If this wasn't you, reset your password and contact support."),
+                    (_, "mfa_disabled") => ("Two-step verification turned off", "Two-step verification was turned off for your account.", "If this wasn't you, reset your password right away."),
+                    (_, "recovery_code_used") => ("A recovery code was used", "A recovery code was used to sign in to your account.", "If this wasn't you, reset your password and generate new codes right away."),
+                    (_, "passkey_added") => ("A passkey was added", "A new passkey was registered on your account.", "If this wasn't you, remove it and reset your password right away."),
+                    (_, "passkey_removed") => ("A passkey was removed", "A passkey was removed from your account.", "If this wasn't you, reset your password right away."),
+                    _ => ("Security alert", "There was a security change on your account.", "If this wasn't you, contact support."),
+                };
+
+                Content {
+                    subject: subject.to_string(),
+                    paragraphs: vec![what.to_string(), advice.to_string()],
+                    action: None,
+                }
+            }
+        }
+    }
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_language_fallback() {
+        assert_eq!(language_for(Some("pt-BR")), "pt");
+        assert_eq!(language_for(Some("es_MX")), "es");
+        assert_eq!(language_for(Some("de")), "en");
+        assert_eq!(language_for(None), "en");
+    }
+
+    #[test]
+    fn test_render_text_and_html() {
+        let link = "https://app.example.com/reset-password?token=abc&x=1";
+        let email = MailTemplate::PasswordReset { link, ttl_minutes: 30 }.render(Some("es"));
+
+        assert_eq!(email.subject, "Restablece tu contraseña");
+        assert!(email.text.contains("30 minutos"));
+        assert!(email.text.contains(link));
+        assert!(email.html.contains("href=\"https://app.example.com/reset-password?token=abc&amp;x=1\""));
+    }
+
+    #[test]
+    fn test_render_escapes_user_content() {
+        let email = MailTemplate::OrganizationInvitation {
+            link: "https://app.example.com/orgs/1",
+            org_name: "<script>Acme</script>",
+            inviter: "jane@example.com",
+        }.render(None);
+
+        assert!(email.text.contains("<script>Acme</script>"));
+        assert!(!email.html.contains("<script>"));
+        assert!(email.html.contains("&lt;script&gt;Acme&lt;/script&gt;"));
+    }
+}
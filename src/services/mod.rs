@@ -1,5 +1,27 @@
 pub mod user_service;
 pub mod log_service;
+pub mod kong_service;
+pub mod organization_service;
+pub mod group_service;
+pub mod audit_service;
+pub mod mail_service;
+pub mod mail_templates;
+pub mod rate_limit_service;
+pub mod settings_service;
+pub mod webauthn_service;
+pub mod session_service;
+pub mod revoked_token_service;
 
 pub use user_service::UserService;
-pub use log_service::LogService;
\ No newline at end of file
+pub use log_service::LogService;
+pub use kong_service::KongService;
+pub use organization_service::OrganizationService;
+pub use group_service::GroupService;
+pub use audit_service::AuditService;
+pub use mail_service::MailService;
+pub use mail_templates::MailTemplate;
+pub use rate_limit_service::RateLimitService;
+pub use settings_service::SettingsService;
+pub use webauthn_service::WebauthnService;
+pub use session_service::SessionService;
+pub use revoked_token_service::RevokedTokenService;
@@ -0,0 +1,140 @@
+use mongodb::{Database, Collection, bson::{doc, oid::ObjectId, DateTime}};
+use futures_util::stream::StreamExt;
+use std::error::Error;
+use crate::models::{Membership, MembershipStatus, Organization, TenantScope};
+
+pub struct OrganizationService {
+    db: Database,
+    scope: TenantScope,
+}
+
+impl OrganizationService {
+    pub fn new(db: Database, tenant_id: &str) -> Self {
+        Self { db, scope: TenantScope::Tenant(tenant_id.to_string()) }
+    }
+
+    pub fn organizations_collection(&self) -> Collection<Organization> {
+        self.db.collection("Organizations")
+    }
+
+    pub fn memberships_collection(&self) -> Collection<Membership> {
+        self.db.collection("Memberships")
+    }
+
+    // Creates the organization with its creator as the first owner
+    pub async fn create_organization(&self, org: &Organization) -> Result<ObjectId, Box<dyn Error + Send + Sync>> {
+        let org_id = org._id.ok_or("Organization ID is required")?;
+        self.organizations_collection().insert_one(org).await?;
+
+        let owner = Membership::new(
+            org.tenant_id.clone(),
+            org_id,
+            org.created_by.clone(),
+            vec!["owner".to_string()],
+            MembershipStatus::Active,
+            None,
+        );
+        self.memberships_collection().insert_one(&owner).await?;
+
+        Ok(org_id)
+    }
+
+    pub async fn find_by_id(&self, org_id: &ObjectId) -> Result<Option<Organization>, Box<dyn Error + Send + Sync>> {
+        let filter = self.scope.apply(doc! { "_id": org_id });
+
+        match self.organizations_collection().find_one(filter).await {
+            Ok(org) => Ok(org),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub async fn find_membership(&self, org_id: &ObjectId, user_id: &str) -> Result<Option<Membership>, Box<dyn Error + Send + Sync>> {
+        let filter = self.scope.apply(doc! { "org_id": org_id, "user_id": user_id });
+
+        match self.memberships_collection().find_one(filter).await {
+            Ok(membership) => Ok(membership),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub async fn find_active_membership(&self, org_id: &ObjectId, user_id: &str) -> Result<Option<Membership>, Box<dyn Error + Send + Sync>> {
+        Ok(self.find_membership(org_id, user_id).await?.filter(|m| m.is_active()))
+    }
+
+    // Active and pending memberships of a user, paired with their organization
+    pub async fn list_for_user(&self, user_id: &str) -> Result<Vec<(Organization, Membership)>, Box<dyn Error + Send + Sync>> {
+        let filter = self.scope.apply(doc! { "user_id": user_id });
+        let mut cursor = self.memberships_collection().find(filter).await?;
+        let mut result = Vec::new();
+
+        while let Some(membership) = cursor.next().await {
+            let membership = membership?;
+            if let Some(org) = self.find_by_id(&membership.org_id).await? {
+                result.push((org, membership));
+            }
+        }
+
+        Ok(result)
+    }
+
+    pub async fn list_members(&self, org_id: &ObjectId) -> Result<Vec<Membership>, Box<dyn Error + Send + Sync>> {
+        let filter = self.scope.apply(doc! { "org_id": org_id });
+        let mut cursor = self.memberships_collection().find(filter).await?;
+        let mut members = Vec::new();
+
+        while let Some(membership) = cursor.next().await {
+            members.push(membership?);
+        }
+
+        Ok(members)
+    }
+
+    pub async fn add_membership(&self, membership: &Membership) -> Result<ObjectId, Box<dyn Error + Send + Sync>> {
+        if self.find_membership(&membership.org_id, &membership.user_id).await?.is_some() {
+            return Err("User is already a member of this organization".into());
+        }
+
+        match self.memberships_collection().insert_one(membership).await {
+            Ok(result) => result.inserted_id.as_object_id().ok_or_else(|| "Failed to get inserted ID".into()),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub async fn accept_invitation(&self, org_id: &ObjectId, user_id: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let filter = self.scope.apply(doc! { "org_id": org_id, "user_id": user_id, "status": "invited" });
+        let update = doc! { "$set": { "status": "active", "updated_at": DateTime::now() } };
+
+        match self.memberships_collection().update_one(filter, update).await {
+            Ok(result) => Ok(result.modified_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub async fn set_member_roles(&self, org_id: &ObjectId, user_id: &str, roles: &[String]) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let filter = self.scope.apply(doc! { "org_id": org_id, "user_id": user_id });
+        let update = doc! { "$set": { "roles": roles, "updated_at": DateTime::now() } };
+
+        match self.memberships_collection().update_one(filter, update).await {
+            Ok(result) => Ok(result.matched_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub async fn remove_membership(&self, org_id: &ObjectId, user_id: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let filter = self.scope.apply(doc! { "org_id": org_id, "user_id": user_id });
+
+        match self.memberships_collection().delete_one(filter).await {
+            Ok(result) => Ok(result.deleted_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub async fn count_owners(&self, org_id: &ObjectId) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        let filter = self.scope.apply(doc! { "org_id": org_id, "roles": "owner", "status": "active" });
+
+        match self.memberships_collection().count_documents(filter).await {
+            Ok(count) => Ok(count),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+}
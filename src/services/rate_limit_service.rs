@@ -0,0 +1,71 @@
+use mongodb::{Database, Collection, bson::{doc, DateTime, Document}, options::ReturnDocument};
+use std::error::Error;
+
+// Fixed-window counters shared by every instance through MongoDB
+pub struct RateLimitService {
+    db: Database,
+}
+
+impl RateLimitService {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    pub fn rate_limits_collection(&self) -> Collection<Document> {
+        self.db.collection("RateLimits")
+    }
+
+    // Counts a hit for `key` and returns whether it is still within `limit` for the current window
+    pub async fn check(&self, key: &str, limit: i64, window: chrono::Duration) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let window_ms = window.num_milliseconds().max(1);
+        let window_start = window_start(DateTime::now().timestamp_millis(), window_ms);
+
+        let filter = doc! { "_id": format!("{}:{}", key, window_start) };
+        let update = doc! {
+            "$inc": { "count": 1_i64 },
+            // Lets a TTL index on expires_at clean up old windows
+            "$setOnInsert": { "expires_at": DateTime::from_millis(window_start + window_ms) },
+        };
+
+        let counter = self.rate_limits_collection()
+            .find_one_and_update(filter, update)
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .await?;
+
+        let count = counter.and_then(|c| c.get_i64("count").ok()).unwrap_or(1);
+        Ok(within_limit(count, limit))
+    }
+}
+
+// Start of the fixed window that `now_ms` falls in
+fn window_start(now_ms: i64, window_ms: i64) -> i64 {
+    now_ms - now_ms % window_ms
+}
+
+// `count` includes the hit being checked
+fn within_limit(count: i64, limit: i64) -> bool {
+    count <= limit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR_MS: i64 = 60 * 60 * 1000;
+
+    #[test]
+    fn test_hits_share_a_window_until_it_ends() {
+        let start = 1_700_000_000_000 - 1_700_000_000_000 % HOUR_MS;
+
+        assert_eq!(window_start(start, HOUR_MS), start);
+        assert_eq!(window_start(start + HOUR_MS - 1, HOUR_MS), start);
+        assert_eq!(window_start(start + HOUR_MS, HOUR_MS), start + HOUR_MS);
+    }
+
+    #[test]
+    fn test_hits_beyond_the_limit_are_refused() {
+        let allowed: Vec<bool> = (1..=4).map(|count| within_limit(count, 3)).collect();
+        assert_eq!(allowed, vec![true, true, true, false]);
+    }
+}
@@ -0,0 +1,33 @@
+use mongodb::{Database, Collection, bson::{doc, DateTime, Document}};
+use std::error::Error;
+
+// Access tokens ended before they expire, by jti. Only tokens without a session
+// (impersonation) need this; the others end with their session
+pub struct RevokedTokenService {
+    db: Database,
+}
+
+impl RevokedTokenService {
+    pub fn new(db: Database) -> Self {
+        Self { db }
+    }
+
+    pub fn revoked_tokens_collection(&self) -> Collection<Document> {
+        self.db.collection("RevokedTokens")
+    }
+
+    // Kept until the token would have expired anyway
+    pub async fn revoke(&self, jti: &str, expires_at: DateTime) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let filter = doc! { "_id": jti };
+        // Lets a TTL index on expires_at clean up old entries
+        let update = doc! { "$setOnInsert": { "expires_at": expires_at } };
+
+        self.revoked_tokens_collection().update_one(filter, update).upsert(true).await?;
+        Ok(())
+    }
+
+    pub async fn is_revoked(&self, jti: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let entry = self.revoked_tokens_collection().find_one(doc! { "_id": jti }).await?;
+        Ok(entry.is_some())
+    }
+}
@@ -0,0 +1,210 @@
+use mongodb::{Database, Collection, bson::{doc, DateTime, Document}};
+use mongodb::options::ReturnDocument;
+use futures_util::stream::StreamExt;
+use std::error::Error;
+use crate::models::{AuditLog, Session, TenantScope, User};
+use crate::services::AuditService;
+
+pub struct SessionService {
+    db: Database,
+    scope: TenantScope,
+}
+
+impl SessionService {
+    pub fn new(db: Database, tenant_id: &str) -> Self {
+        Self::with_scope(db, TenantScope::Tenant(tenant_id.to_string()))
+    }
+
+    pub fn with_scope(db: Database, scope: TenantScope) -> Self {
+        Self { db, scope }
+    }
+
+    pub fn sessions_collection(&self) -> Collection<Session> {
+        self.db.collection("Sessions")
+    }
+
+    // Not revoked, not past the refresh token's lifetime and not idle for too long
+    fn active(filter: Document) -> Document {
+        let now = DateTime::now();
+        let mut filter = filter;
+        filter.insert("revoked_at", doc! { "$exists": false });
+        filter.insert("expires_at", doc! { "$gt": now });
+        filter.insert("$or", vec![
+            doc! { "idle_expires_at": { "$exists": false } },
+            doc! { "idle_expires_at": { "$gt": now } },
+        ]);
+        filter
+    }
+
+    async fn collect(&self, filter: Document, sort: Document) -> Result<Vec<Session>, Box<dyn Error + Send + Sync>> {
+        let mut cursor = self.sessions_collection().find(filter).sort(sort).await?;
+
+        let mut sessions = Vec::new();
+        while let Some(session) = cursor.next().await {
+            sessions.push(session?);
+        }
+        Ok(sessions)
+    }
+
+    // Revokes exactly the given sessions if they are still unrevoked
+    async fn revoke_ids(&self, ids: Vec<String>, reason: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let filter = self.scope.apply(doc! { "_id": { "$in": ids }, "revoked_at": { "$exists": false } });
+        let update = doc! { "$set": { "revoked_at": DateTime::now(), "revoked_reason": reason } };
+
+        match self.sessions_collection().update_many(filter, update).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub async fn create(&self, session: &Session) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match self.sessions_collection().insert_one(session).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub async fn find_active(&self, id: &str) -> Result<Option<Session>, Box<dyn Error + Send + Sync>> {
+        let filter = self.scope.apply(Self::active(doc! { "_id": id }));
+
+        match self.sessions_collection().find_one(filter).await {
+            Ok(session) => Ok(session),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    // Records activity on the session and pushes back its idle deadline
+    pub async fn touch(&self, session: &Session) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let now = DateTime::now();
+        let mut set = doc! { "last_seen_at": now };
+        if let Some(idle_expires_at) = session.idle_deadline(now) {
+            set.insert("idle_expires_at", idle_expires_at);
+        }
+        let filter = self.scope.apply(Self::active(doc! { "_id": &session.id }));
+
+        match self.sessions_collection().update_one(filter, doc! { "$set": set }).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    // Swaps the refresh token for a new one; fails if the old one was already swapped
+    pub async fn rotate_refresh_token(
+        &self,
+        id: &str,
+        old_hash: &str,
+        new_hash: &str,
+        ip_address: Option<String>,
+        idle_expires_at: Option<DateTime>,
+    ) -> Result<Option<Session>, Box<dyn Error + Send + Sync>> {
+        let now = DateTime::now();
+        let filter = self.scope.apply(Self::active(doc! { "_id": id, "refresh_token_hash": old_hash }));
+        let mut set = doc! { "refresh_token_hash": new_hash, "last_seen_at": now, "ip_address": ip_address };
+        if let Some(idle_expires_at) = idle_expires_at {
+            set.insert("idle_expires_at", idle_expires_at);
+        }
+        let update = doc! { "$set": set };
+
+        match self.sessions_collection().find_one_and_update(filter, update).return_document(ReturnDocument::After).await {
+            Ok(session) => Ok(session),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub async fn set_org(&self, id: &str, org_id: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let filter = self.scope.apply(Self::active(doc! { "_id": id }));
+        let update = doc! { "$set": { "org_id": org_id } };
+
+        match self.sessions_collection().update_one(filter, update).await {
+            Ok(result) => Ok(result.matched_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    // Most recently used first
+    pub async fn list_active(&self, user_id: &str) -> Result<Vec<Session>, Box<dyn Error + Send + Sync>> {
+        let filter = self.scope.apply(Self::active(doc! { "user_id": user_id }));
+        self.collect(filter, doc! { "last_seen_at": -1 }).await
+    }
+
+    // Marks the user's sessions that went idle past their timeout as revoked and
+    // returns them, so every expiry gets recorded once
+    pub async fn expire_idle(&self, user_id: &str) -> Result<Vec<Session>, Box<dyn Error + Send + Sync>> {
+        let filter = self.scope.apply(doc! {
+            "user_id": user_id,
+            "revoked_at": { "$exists": false },
+            "idle_expires_at": { "$lte": DateTime::now() },
+        });
+        let sessions = self.collect(filter, doc! { "idle_expires_at": 1 }).await?;
+
+        self.revoke_ids(sessions.iter().map(|session| session.id.clone()).collect(), "idle_timeout").await?;
+        Ok(sessions)
+    }
+
+    // Ends the user's sessions that went idle past their timeout and records each one
+    pub async fn expire_idle_sessions(&self, user: &User) {
+        match self.expire_idle(&user._id.unwrap().to_hex()).await {
+            Ok(sessions) => self.record_evictions(user, &sessions, "idle_timeout").await,
+            Err(e) => eprintln!("Failed to expire idle sessions: {}", e),
+        }
+    }
+
+    // Audits every session ended by policy rather than by someone's request
+    pub async fn record_evictions(&self, user: &User, sessions: &[Session], reason: &str) {
+        let audit_service = AuditService::new(self.db.clone(), &user.tenant_id);
+        for session in sessions {
+            audit_service.record(&AuditLog::new(
+                &user.tenant_id,
+                user,
+                "user.session_evicted",
+                "user",
+                &user._id.unwrap().to_hex(),
+                Some(doc! {
+                    "session_id": &session.id,
+                    "reason": reason,
+                    "login_method": &session.login_method,
+                    "ip_address": &session.ip_address,
+                    "last_seen_at": session.last_seen_at,
+                }),
+            )).await;
+        }
+    }
+
+    // Revokes the user's `count` oldest active sessions to make room for a new one
+    pub async fn evict_oldest(&self, user_id: &str, count: usize) -> Result<Vec<Session>, Box<dyn Error + Send + Sync>> {
+        let filter = self.scope.apply(Self::active(doc! { "user_id": user_id }));
+        let mut sessions = self.collect(filter, doc! { "created_at": 1 }).await?;
+        sessions.truncate(count);
+
+        self.revoke_ids(sessions.iter().map(|session| session.id.clone()).collect(), "session_limit").await?;
+        Ok(sessions)
+    }
+
+    pub async fn revoke(&self, user_id: &str, id: &str, reason: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let filter = self.scope.apply(Self::active(doc! { "_id": id, "user_id": user_id }));
+        let update = doc! { "$set": { "revoked_at": DateTime::now(), "revoked_reason": reason } };
+
+        match self.sessions_collection().update_one(filter, update).await {
+            Ok(result) => Ok(result.modified_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    // Revokes every active session of the user except `keep`; returns how many were revoked
+    pub async fn revoke_all(&self, user_id: &str, keep: Option<&str>, reason: &str) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        let mut filter = doc! { "user_id": user_id };
+        if let Some(keep) = keep {
+            filter.insert("_id", doc! { "$ne": keep });
+        }
+        let filter = self.scope.apply(Self::active(filter));
+        let update = doc! { "$set": { "revoked_at": DateTime::now(), "revoked_reason": reason } };
+
+        match self.sessions_collection().update_many(filter, update).await {
+            Ok(result) => Ok(result.modified_count),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use mongodb::{Database, Collection, bson::doc};
+use std::error::Error;
+use crate::models::SecuritySettings;
+
+pub struct SettingsService {
+    db: Database,
+    tenant_id: String,
+}
+
+impl SettingsService {
+    pub fn new(db: Database, tenant_id: &str) -> Self {
+        Self { db, tenant_id: tenant_id.to_string() }
+    }
+
+    pub fn settings_collection(&self) -> Collection<SecuritySettings> {
+        self.db.collection("SecuritySettings")
+    }
+
+    // Stored settings for the tenant, or the defaults if an admin never changed them
+    pub async fn get(&self) -> Result<SecuritySettings, Box<dyn Error + Send + Sync>> {
+        let settings = self.settings_collection()
+            .find_one(doc! { "tenant_id": &self.tenant_id })
+            .await?;
+
+        Ok(settings.unwrap_or_else(|| SecuritySettings::new(self.tenant_id.clone())))
+    }
+
+    pub async fn save(&self, settings: &SecuritySettings) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.settings_collection()
+            .replace_one(doc! { "tenant_id": &self.tenant_id }, settings)
+            .upsert(true)
+            .await?;
+
+        Ok(())
+    }
+}
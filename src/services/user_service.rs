@@ -1,15 +1,121 @@
-use mongodb::{Database, Collection, bson::{doc, oid::ObjectId}};
+use mongodb::{Database, Collection, bson::{doc, oid::ObjectId, DateTime, Document, Regex}, options::{FindOptions, ReturnDocument}};
 use futures_util::stream::StreamExt;
 use std::error::Error;
-use crate::models::User;
+use crate::models::{LockoutPolicy, ProfileUpdate, TenantScope, User, WebauthnCredential};
+
+pub const MAX_USERS_PER_PAGE: i64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UserSortField {
+    CreatedAt,
+    LastLogin,
+    Email,
+}
+
+impl UserSortField {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "created_at" => Some(Self::CreatedAt),
+            "last_login" => Some(Self::LastLogin),
+            "email" => Some(Self::Email),
+            _ => None,
+        }
+    }
+
+    fn field_name(&self) -> &'static str {
+        match self {
+            Self::CreatedAt => "created_at",
+            Self::LastLogin => "last_login",
+            Self::Email => "email",
+        }
+    }
+}
+
+// Filters, sorting and offset pagination for listing users
+#[derive(Debug, Clone)]
+pub struct UserListQuery {
+    pub page: u64,
+    pub per_page: i64,
+    pub sort_by: UserSortField,
+    pub descending: bool,
+    pub role: Option<String>,
+    pub is_active: Option<bool>,
+    pub email_verified: Option<bool>,
+    pub email_prefix: Option<String>,
+}
+
+impl Default for UserListQuery {
+    fn default() -> Self {
+        Self {
+            page: 1,
+            per_page: 20,
+            sort_by: UserSortField::CreatedAt,
+            descending: true,
+            role: None,
+            is_active: None,
+            email_verified: None,
+            email_prefix: None,
+        }
+    }
+}
+
+impl UserListQuery {
+    pub fn filter(&self) -> Document {
+        let mut filter = doc! {};
+
+        if let Some(role) = &self.role {
+            filter.insert("roles", role);
+        }
+        if let Some(is_active) = self.is_active {
+            filter.insert("is_active", is_active);
+        }
+        if let Some(email_verified) = self.email_verified {
+            filter.insert("email_verified", email_verified);
+        }
+        if let Some(prefix) = self.email_prefix.as_ref().filter(|p| !p.is_empty()) {
+            filter.insert("email", Regex {
+                pattern: format!("^{}", escape_regex(prefix)),
+                options: "i".to_string(),
+            });
+        }
+
+        filter
+    }
+
+    pub fn sort(&self) -> Document {
+        let direction = if self.descending { -1 } else { 1 };
+        // _id breaks ties so pages never overlap or skip users
+        doc! { self.sort_by.field_name(): direction, "_id": direction }
+    }
+
+    pub fn skip(&self) -> u64 {
+        (self.page.max(1) - 1) * self.per_page as u64
+    }
+}
+
+fn escape_regex(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if "\\.+*?()|[]{}^$#&-~".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
 
 pub struct UserService {
     db: Database,
+    scope: TenantScope,
 }
 
 impl UserService {
-    pub fn new(db: Database) -> Self {
-        Self { db }
+    pub fn new(db: Database, tenant_id: &str) -> Self {
+        Self::with_scope(db, TenantScope::Tenant(tenant_id.to_string()))
+    }
+
+    pub fn with_scope(db: Database, scope: TenantScope) -> Self {
+        Self { db, scope }
     }
 
     pub fn users_collection(&self) -> Collection<User> {
@@ -18,7 +124,7 @@ impl UserService {
 
     pub async fn find_by_email(&self, email: &str) -> Result<Option<User>, Box<dyn Error + Send + Sync>> {
         let collection = self.users_collection();
-        let filter = doc! { "email": email };
+        let filter = self.scope.apply(doc! { "email": email });
         
         match collection.find_one(filter).await {
             Ok(user) => Ok(user),
@@ -26,8 +132,22 @@ impl UserService {
         }
     }
 
+    pub async fn find_by_id(&self, id: &ObjectId) -> Result<Option<User>, Box<dyn Error + Send + Sync>> {
+        let collection = self.users_collection();
+        let filter = self.scope.apply(doc! { "_id": id });
+
+        match collection.find_one(filter).await {
+            Ok(user) => Ok(user),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
     pub async fn create_user(&self, user: &User) -> Result<ObjectId, Box<dyn Error + Send + Sync>> {
         let collection = self.users_collection();
+
+        if self.scope.tenant_id().is_some_and(|tenant_id| tenant_id != user.tenant_id) {
+            return Err("User belongs to another tenant".into());
+        }
         
         match collection.insert_one(user).await {
             Ok(result) => {
@@ -41,19 +161,417 @@ impl UserService {
         }
     }
 
-    pub async fn update_user(&self, id: &ObjectId, user: &User) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    // Writes only the profile fields present in the update, so a password, role or status
+    // change made meanwhile is kept; returns the updated user
+    pub async fn update_profile(&self, id: &ObjectId, update: &ProfileUpdate) -> Result<Option<User>, Box<dyn Error + Send + Sync>> {
+        let mut set = doc! { "updated_at": DateTime::now() };
+        let mut unset = Document::new();
+        for (field, value) in update.changes() {
+            match value {
+                Some(value) => set.insert(field, value),
+                None => unset.insert(field, ""),
+            };
+        }
+
+        let mut update = doc! { "$set": set };
+        if !unset.is_empty() {
+            update.insert("$unset", unset);
+        }
+        self.update_and_return(id, update).await
+    }
+
+    // Admin changes to the account; fields left as None are not written
+    pub async fn update_account(
+        &self,
+        id: &ObjectId,
+        roles: Option<&[String]>,
+        is_active: Option<bool>,
+        email_verified: Option<bool>,
+    ) -> Result<Option<User>, Box<dyn Error + Send + Sync>> {
+        let mut set = doc! { "updated_at": DateTime::now() };
+        if let Some(roles) = roles {
+            set.insert("roles", roles);
+        }
+        if let Some(is_active) = is_active {
+            set.insert("is_active", is_active);
+        }
+        if let Some(email_verified) = email_verified {
+            set.insert("email_verified", email_verified);
+        }
+
+        self.update_and_return(id, doc! { "$set": set }).await
+    }
+
+    async fn update_and_return(&self, id: &ObjectId, update: Document) -> Result<Option<User>, Box<dyn Error + Send + Sync>> {
+        let filter = self.scope.apply(doc! { "_id": id });
+
+        match self.users_collection().find_one_and_update(filter, update).return_document(ReturnDocument::After).await {
+            Ok(user) => Ok(user),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    // Stores a new password hash and drops every refresh token issued so far
+    pub async fn change_password(&self, id: &ObjectId, password_hash: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let collection = self.users_collection();
+        let filter = self.scope.apply(doc! { "_id": id });
+        let now = DateTime::now();
+        let update = doc! {
+            "$set": {
+                "password": password_hash,
+                "password_changed_at": now,
+                "password_breached": false,
+                "updated_at": now,
+                "refresh_tokens": [],
+            }
+        };
+
+        match collection.update_one(filter, update).await {
+            Ok(result) => Ok(result.matched_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    // Rejects every access token issued so far without touching the password
+    pub async fn revoke_tokens(&self, id: &ObjectId) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let collection = self.users_collection();
+        let filter = self.scope.apply(doc! { "_id": id });
+        let update = doc! { "$set": { "tokens_revoked_at": DateTime::now(), "refresh_tokens": [] } };
+
+        match collection.update_one(filter, update).await {
+            Ok(result) => Ok(result.matched_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    // Swaps in a rehash of the same password; unlike a change it keeps sessions and tokens.
+    // Matching on the old hash avoids overwriting a password changed in the meantime
+    pub async fn upgrade_password_hash(&self, id: &ObjectId, old_hash: &str, new_hash: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let collection = self.users_collection();
+        let filter = self.scope.apply(doc! { "_id": id, "password": old_hash });
+        let update = doc! { "$set": { "password": new_hash } };
+
+        match collection.update_one(filter, update).await {
+            Ok(result) => Ok(result.modified_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    // Keeps the new secret aside until the user proves their app produces matching codes
+    pub async fn set_totp_pending(&self, id: &ObjectId, secret: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let collection = self.users_collection();
+        let filter = self.scope.apply(doc! { "_id": id });
+        let update = doc! { "$set": { "totp_pending_secret": secret } };
+
+        match collection.update_one(filter, update).await {
+            Ok(result) => Ok(result.matched_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    // Promotes the pending secret, replacing any previous one, along with a fresh set of recovery codes
+    pub async fn confirm_totp(&self, id: &ObjectId, secret: &str, step: i64, recovery_code_hashes: &[String]) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let collection = self.users_collection();
+        let filter = self.scope.apply(doc! { "_id": id, "totp_pending_secret": secret });
+        let update = doc! {
+            "$set": {
+                "mfa_enabled": true,
+                "totp_secret": secret,
+                "totp_last_step": step,
+                "recovery_codes": recovery_code_hashes,
+                "updated_at": DateTime::now(),
+            },
+            "$unset": { "totp_pending_secret": "" },
+        };
+
+        match collection.update_one(filter, update).await {
+            Ok(result) => Ok(result.modified_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub async fn disable_totp(&self, id: &ObjectId) -> Result<bool, Box<dyn Error + Send + Sync>> {
         let collection = self.users_collection();
-        let filter = doc! { "_id": id };
+        let filter = self.scope.apply(doc! { "_id": id });
+        let update = doc! {
+            "$set": { "mfa_enabled": false, "updated_at": DateTime::now() },
+            "$unset": { "totp_secret": "", "totp_pending_secret": "", "totp_last_step": "", "recovery_codes": "" },
+        };
+
+        match collection.update_one(filter, update).await {
+            Ok(result) => Ok(result.matched_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    // Records a code's time step; false if that step (or a later one) was already used,
+    // which stops two concurrent requests from both accepting the same code
+    pub async fn use_totp_step(&self, id: &ObjectId, step: i64) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let collection = self.users_collection();
+        let filter = self.scope.apply(doc! {
+            "_id": id,
+            "$or": [
+                { "totp_last_step": { "$exists": false } },
+                { "totp_last_step": { "$lt": step } },
+            ],
+        });
+        let update = doc! { "$set": { "totp_last_step": step } };
+
+        match collection.update_one(filter, update).await {
+            Ok(result) => Ok(result.modified_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub async fn replace_recovery_codes(&self, id: &ObjectId, recovery_code_hashes: &[String]) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let collection = self.users_collection();
+        let filter = self.scope.apply(doc! { "_id": id, "mfa_enabled": true });
+        let update = doc! { "$set": { "recovery_codes": recovery_code_hashes, "updated_at": DateTime::now() } };
+
+        match collection.update_one(filter, update).await {
+            Ok(result) => Ok(result.matched_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    // Removes the code if it is still unused; false means it was never valid or already spent
+    pub async fn use_recovery_code(&self, id: &ObjectId, code_hash: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let collection = self.users_collection();
+        let filter = self.scope.apply(doc! { "_id": id, "recovery_codes": code_hash });
+        let update = doc! { "$pull": { "recovery_codes": code_hash } };
+
+        match collection.update_one(filter, update).await {
+            Ok(result) => Ok(result.modified_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub async fn find_by_webauthn_credential(&self, credential_id: &str) -> Result<Option<User>, Box<dyn Error + Send + Sync>> {
+        let collection = self.users_collection();
+        let filter = self.scope.apply(doc! { "webauthn_credentials.credential_id": credential_id });
+
+        match collection.find_one(filter).await {
+            Ok(user) => Ok(user),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub async fn add_webauthn_credential(&self, id: &ObjectId, credential: &WebauthnCredential) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let collection = self.users_collection();
+        // The same authenticator cannot be registered twice
+        let filter = self.scope.apply(doc! { "_id": id, "webauthn_credentials.credential_id": { "$ne": &credential.credential_id } });
+        let update = doc! {
+            "$push": { "webauthn_credentials": mongodb::bson::to_bson(credential)? },
+            "$set": { "updated_at": DateTime::now() },
+        };
+
+        match collection.update_one(filter, update).await {
+            Ok(result) => Ok(result.modified_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    // Matching on the previous counter makes two concurrent uses of a cloned key fail
+    pub async fn record_webauthn_use(&self, id: &ObjectId, credential_id: &str, previous_count: u32, sign_count: u32) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let collection = self.users_collection();
+        let filter = self.scope.apply(doc! {
+            "_id": id,
+            "webauthn_credentials": { "$elemMatch": { "credential_id": credential_id, "sign_count": previous_count } },
+        });
         let update = doc! {
             "$set": {
-                "email": &user.email,
-                "password": &user.password,
-                "roles": &user.roles,
-                "created_at": &user.created_at,
-                "updated_at": &user.updated_at,
-                "is_active": &user.is_active,
-                "last_login": &user.last_login,
+                "webauthn_credentials.$.sign_count": sign_count,
+                "webauthn_credentials.$.last_used_at": DateTime::now(),
+            }
+        };
+
+        match collection.update_one(filter, update).await {
+            Ok(result) => Ok(result.matched_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub async fn remove_webauthn_credential(&self, id: &ObjectId, credential_id: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let collection = self.users_collection();
+        let filter = self.scope.apply(doc! { "_id": id });
+        let update = doc! {
+            "$pull": { "webauthn_credentials": { "credential_id": credential_id } },
+            "$set": { "updated_at": DateTime::now() },
+        };
+
+        match collection.update_one(filter, update).await {
+            Ok(result) => Ok(result.modified_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub async fn set_password_breached(&self, id: &ObjectId, breached: bool) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let collection = self.users_collection();
+        let filter = self.scope.apply(doc! { "_id": id });
+        let update = doc! { "$set": { "password_breached": breached } };
+
+        match collection.update_one(filter, update).await {
+            Ok(result) => Ok(result.matched_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    // Called once every factor of a login has been checked; also clears the failed login counters
+    pub async fn record_login(&self, id: &ObjectId) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let collection = self.users_collection();
+        let filter = self.scope.apply(doc! { "_id": id });
+        let update = doc! {
+            "$set": { "last_login": DateTime::now(), "failed_login_count": 0_i64, "lockout_count": 0_i64 },
+            "$unset": { "first_failed_login_at": "", "locked_until": "" },
+        };
+
+        match collection.update_one(filter, update).await {
+            Ok(result) => Ok(result.matched_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    // Counts a failed login; returns true when this failure locks the account. Concurrent
+    // failures each increment the stored counter, so none of them can be lost
+    pub async fn register_login_failure(&self, id: &ObjectId, policy: &LockoutPolicy, now: DateTime) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        if !policy.enabled {
+            return Ok(false);
+        }
+
+        let collection = self.users_collection();
+        let window_start = policy.window_start(now);
+        let mut counted = None;
+
+        // Another failure can open the window between the two updates; retrying settles it
+        for _ in 0..3 {
+            let filter = self.scope.apply(doc! { "_id": id, "first_failed_login_at": { "$gte": window_start } });
+            counted = collection.find_one_and_update(filter, doc! { "$inc": { "failed_login_count": 1_i64 } })
+                .return_document(ReturnDocument::After)
+                .await?;
+            if counted.is_some() {
+                break;
             }
+
+            // First failure of a new window
+            let filter = self.scope.apply(doc! {
+                "_id": id,
+                "$or": [ { "first_failed_login_at": null }, { "first_failed_login_at": { "$lt": window_start } } ],
+            });
+            let update = doc! { "$set": { "failed_login_count": 1_i64, "first_failed_login_at": now } };
+            counted = collection.find_one_and_update(filter, update)
+                .return_document(ReturnDocument::After)
+                .await?;
+            if counted.is_some() {
+                break;
+            }
+        }
+
+        let user = match counted {
+            Some(user) if user.failed_login_count >= policy.max_failures => user,
+            _ => return Ok(false),
+        };
+
+        // Locking resets the counter, so only one of several concurrent failures gets here
+        let filter = self.scope.apply(doc! { "_id": id, "failed_login_count": { "$gte": policy.max_failures as i64 } });
+        let update = doc! {
+            "$set": {
+                "locked_until": policy.locked_until(user.lockout_count + 1, now),
+                "failed_login_count": 0_i64,
+                "first_failed_login_at": null,
+            },
+            "$inc": { "lockout_count": 1_i64 },
+        };
+        let locked = collection.find_one_and_update(filter, update).await?;
+
+        Ok(locked.is_some())
+    }
+
+    pub async fn clear_login_failures(&self, id: &ObjectId) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let collection = self.users_collection();
+        let filter = self.scope.apply(doc! { "_id": id });
+        let update = doc! {
+            "$set": { "failed_login_count": 0_i64, "lockout_count": 0_i64 },
+            "$unset": { "first_failed_login_at": "", "locked_until": "" },
+        };
+
+        match collection.update_one(filter, update).await {
+            Ok(result) => Ok(result.matched_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub async fn set_email_verification(&self, id: &ObjectId, token_hash: &str, expiry: DateTime) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let collection = self.users_collection();
+        let filter = self.scope.apply(doc! { "_id": id });
+        let update = doc! {
+            "$set": { "email_verification_token": token_hash, "email_verification_expiry": expiry }
+        };
+
+        match collection.update_one(filter, update).await {
+            Ok(result) => Ok(result.matched_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    // Consumes a pending verification token and marks the email as verified
+    pub async fn verify_email(&self, token_hash: &str) -> Result<Option<User>, Box<dyn Error + Send + Sync>> {
+        let collection = self.users_collection();
+        let filter = self.scope.apply(doc! {
+            "email_verification_token": token_hash,
+            "email_verification_expiry": { "$gt": DateTime::now() },
+        });
+        let update = doc! {
+            "$set": { "email_verified": true, "updated_at": DateTime::now() },
+            "$unset": { "email_verification_token": "", "email_verification_expiry": "" },
+        };
+
+        match collection.find_one_and_update(filter, update).return_document(ReturnDocument::After).await {
+            Ok(user) => Ok(user),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub async fn set_password_reset(&self, id: &ObjectId, token_hash: &str, expiry: DateTime) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let collection = self.users_collection();
+        let filter = self.scope.apply(doc! { "_id": id });
+        let update = doc! {
+            "$set": { "password_reset_token": token_hash, "password_reset_expiry": expiry }
+        };
+
+        match collection.update_one(filter, update).await {
+            Ok(result) => Ok(result.matched_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub async fn find_by_reset_token(&self, token_hash: &str) -> Result<Option<User>, Box<dyn Error + Send + Sync>> {
+        let collection = self.users_collection();
+        let filter = self.scope.apply(doc! {
+            "password_reset_token": token_hash,
+            "password_reset_expiry": { "$gt": DateTime::now() },
+        });
+
+        match collection.find_one(filter).await {
+            Ok(user) => Ok(user),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    // Consumes the reset token; returns false if it was already used
+    pub async fn reset_password(&self, id: &ObjectId, token_hash: &str, password_hash: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let collection = self.users_collection();
+        // An account deactivated since the token was sent can't be reset
+        let filter = self.scope.apply(doc! { "_id": id, "password_reset_token": token_hash, "is_active": true });
+        let now = DateTime::now();
+        let update = doc! {
+            "$set": {
+                "password": password_hash,
+                "password_changed_at": now,
+                "password_breached": false,
+                "updated_at": now,
+                "refresh_tokens": [],
+            },
+            "$unset": { "password_reset_token": "", "password_reset_expiry": "" },
         };
 
         match collection.update_one(filter, update).await {
@@ -62,9 +580,104 @@ impl UserService {
         }
     }
 
+    // A new link replaces any previous one
+    pub async fn set_magic_link(&self, id: &ObjectId, token_hash: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let collection = self.users_collection();
+        let filter = self.scope.apply(doc! { "_id": id });
+        let update = doc! { "$set": { "magic_link_token": token_hash } };
+
+        match collection.update_one(filter, update).await {
+            Ok(result) => Ok(result.matched_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    // Consumes the magic link; returns false if it was already used or replaced
+    pub async fn use_magic_link(&self, id: &ObjectId, token_hash: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let collection = self.users_collection();
+        let filter = self.scope.apply(doc! { "_id": id, "magic_link_token": token_hash });
+        let update = doc! { "$unset": { "magic_link_token": "" } };
+
+        match collection.update_one(filter, update).await {
+            Ok(result) => Ok(result.modified_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    // A new code replaces any previous one and resets the attempt count
+    pub async fn set_email_otp(&self, id: &ObjectId, code_hash: &str, expiry: DateTime) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let collection = self.users_collection();
+        let filter = self.scope.apply(doc! { "_id": id });
+        let update = doc! {
+            "$set": { "email_otp_hash": code_hash, "email_otp_expiry": expiry, "email_otp_attempts": 0 }
+        };
+
+        match collection.update_one(filter, update).await {
+            Ok(result) => Ok(result.matched_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    // Consumes a matching, unexpired code that still has attempts left. Receiving
+    // the code proves the address, so the email is marked as verified too
+    pub async fn use_email_otp(&self, id: &ObjectId, code_hash: &str, max_attempts: u32) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let collection = self.users_collection();
+        let filter = self.scope.apply(doc! {
+            "_id": id,
+            "email_otp_hash": code_hash,
+            "email_otp_expiry": { "$gt": DateTime::now() },
+            "email_otp_attempts": { "$lt": max_attempts as i64 },
+        });
+        let update = doc! {
+            "$set": { "email_verified": true, "email_otp_attempts": 0, "updated_at": DateTime::now() },
+            "$unset": { "email_otp_hash": "", "email_otp_expiry": "" },
+        };
+
+        match collection.update_one(filter, update).await {
+            Ok(result) => Ok(result.modified_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub async fn register_email_otp_failure(&self, id: &ObjectId) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let collection = self.users_collection();
+        let filter = self.scope.apply(doc! { "_id": id, "email_otp_hash": { "$exists": true } });
+        let update = doc! { "$inc": { "email_otp_attempts": 1 } };
+
+        match collection.update_one(filter, update).await {
+            Ok(result) => Ok(result.modified_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub async fn set_kong_consumer_id(&self, id: &ObjectId, consumer_id: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let collection = self.users_collection();
+        let filter = self.scope.apply(doc! { "_id": id });
+        let update = doc! { "$set": { "kong_consumer_id": consumer_id } };
+
+        match collection.update_one(filter, update).await {
+            Ok(result) => Ok(result.matched_count > 0),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub async fn list_kong_consumers(&self) -> Result<Vec<User>, Box<dyn Error + Send + Sync>> {
+        let collection = self.users_collection();
+        let filter = self.scope.apply(doc! { "kong_consumer_id": { "$exists": true } });
+
+        let mut cursor = collection.find(filter).await?;
+        let mut users = Vec::new();
+
+        while let Some(user) = cursor.next().await {
+            users.push(user?);
+        }
+
+        Ok(users)
+    }
+
     pub async fn delete_user(&self, id: &ObjectId) -> Result<bool, Box<dyn Error + Send + Sync>> {
         let collection = self.users_collection();
-        let filter = doc! { "_id": id };
+        let filter = self.scope.apply(doc! { "_id": id });
 
         match collection.delete_one(filter).await {
             Ok(result) => Ok(result.deleted_count > 0),
@@ -72,29 +685,77 @@ impl UserService {
         }
     }
 
-    pub async fn list_users(&self, limit: Option<i64>) -> Result<Vec<User>, Box<dyn Error + Send + Sync>> {
+    // One page of users matching the query, plus the total number of matches
+    pub async fn list_users(&self, query: &UserListQuery) -> Result<(Vec<User>, u64), Box<dyn Error + Send + Sync>> {
         let collection = self.users_collection();
-        let filter = doc! {};
-        
-        let mut cursor = collection.find(filter).await?;
+        let filter = self.scope.apply(query.filter());
+
+        let total = collection.count_documents(filter.clone()).await?;
+
+        let options = FindOptions::builder()
+            .sort(query.sort())
+            .skip(query.skip())
+            .limit(query.per_page)
+            .build();
+
+        let mut cursor = collection.find(filter).with_options(options).await?;
         let mut users = Vec::new();
-        let mut count = 0;
 
         while let Some(user) = cursor.next().await {
-            match user {
-                Ok(u) => {
-                    users.push(u);
-                    count += 1;
-                    if let Some(limit_val) = limit {
-                        if count >= limit_val {
-                            break;
-                        }
-                    }
-                }
-                Err(e) => return Err(Box::new(e)),
-            }
+            users.push(user?);
         }
 
-        Ok(users)
+        Ok((users, total))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_list_filter() {
+        let query = UserListQuery {
+            role: Some("admin".to_string()),
+            is_active: Some(true),
+            email_prefix: Some("john.d".to_string()),
+            ..UserListQuery::default()
+        };
+
+        let filter = query.filter();
+        assert_eq!(filter.get_str("roles").unwrap(), "admin");
+        assert!(filter.get_bool("is_active").unwrap());
+        assert!(filter.get("email_verified").is_none());
+        match filter.get("email") {
+            Some(mongodb::bson::Bson::RegularExpression(regex)) => assert_eq!(regex.pattern, "^john\\.d"),
+            other => panic!("unexpected email filter: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_user_list_sort_and_skip() {
+        let query = UserListQuery {
+            page: 3,
+            per_page: 25,
+            sort_by: UserSortField::Email,
+            descending: false,
+            ..UserListQuery::default()
+        };
+
+        assert_eq!(query.sort(), doc! { "email": 1, "_id": 1 });
+        assert_eq!(query.skip(), 50);
+    }
+
+    #[test]
+    fn test_user_list_sort_by_last_login() {
+        let mut query = UserListQuery {
+            sort_by: UserSortField::parse("last_login").unwrap(),
+            ..UserListQuery::default()
+        };
+        // Most recent first; users who never logged in have no last_login and sort last
+        assert_eq!(query.sort(), doc! { "last_login": -1, "_id": -1 });
+
+        query.descending = false;
+        assert_eq!(query.sort(), doc! { "last_login": 1, "_id": 1 });
+    }
+}
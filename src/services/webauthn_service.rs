@@ -0,0 +1,53 @@
+use mongodb::{Database, Collection, bson::{doc, DateTime}};
+use std::error::Error;
+use crate::auth::webauthn::new_challenge;
+use crate::models::WebauthnChallenge;
+
+const CHALLENGE_TTL_MINUTES: i64 = 5;
+
+// Pending WebAuthn ceremonies, shared by every instance through MongoDB
+pub struct WebauthnService {
+    db: Database,
+    tenant_id: String,
+}
+
+impl WebauthnService {
+    pub fn new(db: Database, tenant_id: &str) -> Self {
+        Self { db, tenant_id: tenant_id.to_string() }
+    }
+
+    pub fn challenges_collection(&self) -> Collection<WebauthnChallenge> {
+        self.db.collection("WebauthnChallenges")
+    }
+
+    pub async fn create_challenge(&self, purpose: &str, user_id: Option<String>) -> Result<WebauthnChallenge, Box<dyn Error + Send + Sync>> {
+        let challenge = WebauthnChallenge {
+            challenge: new_challenge(),
+            tenant_id: self.tenant_id.clone(),
+            purpose: purpose.to_string(),
+            user_id,
+            // A TTL index on expires_at cleans up abandoned ceremonies
+            expires_at: DateTime::from_millis(DateTime::now().timestamp_millis() + CHALLENGE_TTL_MINUTES * 60 * 1000),
+        };
+
+        match self.challenges_collection().insert_one(&challenge).await {
+            Ok(_) => Ok(challenge),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    // Removes and returns the challenge, so each one can be answered only once
+    pub async fn take_challenge(&self, challenge: &str, purpose: &str) -> Result<Option<WebauthnChallenge>, Box<dyn Error + Send + Sync>> {
+        let filter = doc! {
+            "_id": challenge,
+            "tenant_id": &self.tenant_id,
+            "purpose": purpose,
+            "expires_at": { "$gt": DateTime::now() },
+        };
+
+        match self.challenges_collection().find_one_and_delete(filter).await {
+            Ok(challenge) => Ok(challenge),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+}
@@ -1,3 +1,3 @@
 pub mod user_agent_parser;
-
-pub use user_agent_parser::UserAgentInfo;
\ No newline at end of file
+pub mod request_info;
+pub mod secure_token;
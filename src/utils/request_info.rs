@@ -0,0 +1,14 @@
+use actix_web::HttpRequest;
+
+pub fn get_client_ip(req: &HttpRequest) -> Option<String> {
+    req.connection_info()
+        .peer_addr()
+        .map(|addr| addr.to_string())
+}
+
+pub fn get_user_agent(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get("user-agent")
+        .and_then(|value| value.to_str().ok())
+        .map(|s| s.to_string())
+}
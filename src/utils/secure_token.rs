@@ -0,0 +1,43 @@
+use rand::{Rng, RngCore};
+use sha2::{Digest, Sha256};
+
+// Random URL-safe token handed to the user; only its hash is stored
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+// Short numeric code for people to type, e.g. "042917"
+pub fn generate_numeric_code(digits: u32) -> String {
+    let code = rand::thread_rng().gen_range(0..10u64.pow(digits));
+    format!("{:0width$}", code, width = digits as usize)
+}
+
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_are_random_and_hash_deterministically() {
+        let token = generate_token();
+        assert_eq!(token.len(), 64);
+        assert_ne!(token, generate_token());
+
+        assert_eq!(hash_token(&token), hash_token(&token));
+        assert_ne!(hash_token(&token), token);
+    }
+
+    #[test]
+    fn test_numeric_codes_keep_leading_zeros() {
+        for _ in 0..100 {
+            let code = generate_numeric_code(6);
+            assert_eq!(code.len(), 6);
+            assert!(code.chars().all(|c| c.is_ascii_digit()));
+        }
+    }
+}